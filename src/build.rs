@@ -1,3 +1,5 @@
+mod builder;
 mod engine;
 
+pub use builder::*;
 pub use engine::*;
@@ -0,0 +1,108 @@
+use anyhow::{bail, Context};
+
+use super::Engine;
+use crate::{
+    config::Config,
+    hooks::{ExternalHook, Hook, Hooks},
+    plugins::WasmPlugin,
+    source::SourceTree,
+    theme::Theme,
+};
+
+/// Configures and creates an [`Engine`].
+///
+/// By default the config and theme are read from the source tree and every
+/// post is rendered on each build.
+#[derive(Debug)]
+pub struct EngineBuilder {
+    source: SourceTree,
+    config: Option<Config>,
+    theme: Option<Theme>,
+    hooks: Hooks,
+    dev_mode: bool,
+    incremental: bool,
+}
+
+impl EngineBuilder {
+    pub fn new(source: impl Into<SourceTree>) -> Self {
+        Self {
+            source: source.into(),
+            config: None,
+            theme: None,
+            hooks: Hooks::default(),
+            dev_mode: false,
+            incremental: false,
+        }
+    }
+
+    /// Uses `config` instead of reading `config.toml` from the source tree.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Uses `theme` instead of loading the theme named in the config.
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = Some(theme);
+        self
+    }
+
+    /// Registers a hook, run before any hooks configured in `config.toml`.
+    pub fn hook(mut self, hook: impl Hook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Renders unpublished posts as well.
+    pub fn dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    /// Only re-renders pages whose sources changed since the last build.
+    ///
+    /// Requires a source tree on disk, as the hash cache and previous output live there.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Loads the config and theme and creates the engine.
+    pub fn load(self) -> anyhow::Result<Engine> {
+        if self.incremental && self.source.root().is_none() {
+            bail!("Incremental builds require a source tree on disk")
+        }
+        let mut config = match self.config {
+            Some(config) => config,
+            None => Config::from_source(&self.source)?,
+        };
+        if self.dev_mode {
+            config.__is_dev_mode = true;
+        }
+        let mut theme = match self.theme {
+            Some(theme) => theme,
+            None => Theme::load(&self.source, &config.theme)?,
+        };
+        theme.set_base_path(&config.serve.base_path);
+        if !config.__is_dev_mode {
+            theme.optimize()?;
+        }
+        let mut hooks = self.hooks;
+        for hook_config in &config.hooks {
+            hooks.push(Box::new(ExternalHook::new(
+                hook_config.clone(),
+                self.source.root(),
+            )));
+        }
+        for plugin_config in &config.plugins {
+            let wasm = self
+                .source
+                .read(&plugin_config.path)
+                .with_context(|| format!("Unable to read plugin `{}`", plugin_config.path))?;
+            let plugin = WasmPlugin::load(plugin_config, &wasm)
+                .with_context(|| format!("Unable to load plugin `{}`", plugin_config.path))?;
+            hooks.push(Box::new(plugin));
+        }
+        Engine::new(self.source, config, theme, hooks, !self.incremental)
+    }
+}
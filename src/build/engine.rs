@@ -1,21 +1,35 @@
-use std::{collections::HashMap, io::Write, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
 
+use super::EngineBuilder;
 use crate::{
     cache::HashCache,
+    check::{Linter, Rule},
     config::Config,
+    diagnostics::{Diagnostic, Diagnostics},
+    hooks::{Hook, Hooks},
+    images::ImageProcessor,
+    links::PostLinks,
+    markdown::ConvertOptions,
     post_format::PostFormat,
     post_metadata::PostMetadata,
-    theme::{RenderData, Theme},
+    site,
+    source::SourceTree,
+    theme::{PostIndex, RenderData, Theme},
 };
 
-struct DirectoryStructure {
-    pub build_dir: PathBuf,
-    pub build_post_dir: PathBuf,
-}
+pub(crate) static POSTS_DIR: &str = "posts";
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Post {
     pub format: PostFormat,
+    /// Source file, relative to the project root.
     pub filename: PathBuf,
     pub metadata: PostMetadata,
     pub contents: String,
@@ -28,64 +42,131 @@ impl Post {
     }
 }
 
+/// Turns a [`SourceTree`] into a [`Bundle`] of rendered pages.
+///
+/// Create one with [`Engine::builder`].
 #[derive(Debug)]
 pub struct Engine {
+    source: SourceTree,
     last_cache: HashCache,
     current_cache: HashCache,
     config: Config,
     theme: Theme,
+    hooks: Hooks,
     bypass_cache: bool,
+    diagnostics: Diagnostics,
+    rendered: RenderedPages,
+    images: ImageProcessor,
+}
+
+/// Pages rendered by previous builds of the same engine, keyed by a hash of their inputs.
+#[derive(Debug, Default)]
+struct RenderedPages {
+    /// Each post's page followed by its images.
+    posts: HashMap<PathBuf, (u32, Vec<BuildFile>)>,
+    listings: Option<(u32, Vec<BuildFile>)>,
 }
 
-#[derive(Debug, Clone)]
+/// A single output file of a build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BuildFile {
     path: PathBuf,
     virtual_path: String,
     contents: String,
+    /// Contents of binary files like processed images, which aren't passed to hooks.
+    #[serde(skip)]
+    bytes: Option<Arc<[u8]>>,
 }
 
 impl BuildFile {
-    pub fn new(path: PathBuf, virtual_path: impl ToString, contents: impl ToString) -> Self {
+    /// Creates a file at `path` (relative to the output directory) served under `virtual_path`.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        virtual_path: impl ToString,
+        contents: impl ToString,
+    ) -> Self {
         Self {
-            path,
+            path: path.into(),
             virtual_path: virtual_path.to_string(),
             contents: contents.to_string(),
+            bytes: None,
         }
     }
 
-    pub fn write_to_disk(&self) -> anyhow::Result<()> {
+    /// Creates a binary file, like a processed image.
+    pub fn binary(
+        path: impl Into<PathBuf>,
+        virtual_path: impl ToString,
+        bytes: impl Into<Arc<[u8]>>,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            virtual_path: virtual_path.to_string(),
+            contents: String::new(),
+            bytes: Some(bytes.into()),
+        }
+    }
+
+    pub fn write_to_disk(&self, output_dir: &Path) -> anyhow::Result<()> {
         use anyhow::Context;
-        let mut file = std::fs::File::create(&self.path)?;
-        file.write_all(self.contents.as_bytes())
-            .context(format!("Unable to write file: {:?}", self.path))
+        let path = output_dir.join(&self.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(self.bytes())
+            .context(format!("Unable to write file: {:?}", path))
+    }
+
+    /// Path relative to the output directory.
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
     pub fn virtual_path(&self) -> &str {
         &self.virtual_path
     }
 
+    /// The contents of a text file, empty for binary files.
     pub fn contents(&self) -> &str {
         &self.contents
     }
+
+    /// The contents of any file.
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes.as_deref().unwrap_or(self.contents.as_bytes())
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.bytes.is_some()
+    }
 }
 
-#[derive(Debug)]
+/// All files produced by a build, held in memory until written to disk.
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Bundle {
     files: Vec<BuildFile>,
 }
 
 impl Bundle {
     pub fn new() -> Self {
-        Self { files: Vec::new() }
+        Self::default()
     }
 
     pub fn add_file(&mut self, file: BuildFile) {
         self.files.push(file)
     }
 
-    pub fn write_to_disk(&self) -> anyhow::Result<()> {
+    /// Looks up a file by the URL path it is served under, e.g. `/posts/hello.html`.
+    pub fn get(&self, virtual_path: impl AsRef<str>) -> Option<&BuildFile> {
+        self.files
+            .iter()
+            .find(|file| file.virtual_path == virtual_path.as_ref())
+    }
+
+    pub fn write_to_disk(&self, output_dir: impl AsRef<Path>) -> anyhow::Result<()> {
         for file in &self.files {
-            file.write_to_disk()?;
+            file.write_to_disk(output_dir.as_ref())?;
         }
         Ok(())
     }
@@ -93,15 +174,35 @@ impl Bundle {
     pub fn iter(&self) -> std::slice::Iter<'_, BuildFile> {
         self.files.iter()
     }
+
+    /// Removes and returns all binary files, e.g. while the bundle is passed to a hook.
+    pub(crate) fn take_binary_files(&mut self) -> Vec<BuildFile> {
+        let (binary, text) = std::mem::take(&mut self.files)
+            .into_iter()
+            .partition(BuildFile::is_binary);
+        self.files = text;
+        binary
+    }
 }
 
 impl Engine {
-    pub fn new(config: Config, theme: Theme, bypass_cache: bool) -> anyhow::Result<Self> {
+    /// Starts configuring an engine that reads from `source`.
+    pub fn builder(source: impl Into<SourceTree>) -> EngineBuilder {
+        EngineBuilder::new(source)
+    }
+
+    pub(crate) fn new(
+        source: SourceTree,
+        config: Config,
+        theme: Theme,
+        hooks: Hooks,
+        bypass_cache: bool,
+    ) -> anyhow::Result<Self> {
         let last_cache = {
             if bypass_cache {
                 HashCache::empty()
             } else {
-                HashCache::read_from_file()
+                HashCache::read_from_file(&config.root)
                     .ok()
                     .unwrap_or_else(HashCache::empty)
             }
@@ -110,42 +211,141 @@ impl Engine {
             let mut cache = HashCache::empty();
             if !bypass_cache {
                 cache.mix_config(&config)?;
+                cache.mix_theme(theme.hash());
             }
             cache
         };
+        let images = ImageProcessor::new(&config.images, source.root());
         Ok(Self {
+            source,
             config,
             theme,
+            hooks,
             last_cache,
             current_cache,
             bypass_cache,
+            diagnostics: Diagnostics::new(),
+            rendered: RenderedPages::default(),
+            images,
         })
     }
 
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Warnings collected during the last build.
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Builds the blog, checking every post before failing with all collected [`Diagnostics`].
+    ///
+    /// Calling this again on the same engine only re-renders posts whose contents changed
+    /// since, plus the index and 404 pages if any post was added, changed or removed. Pages are
+    /// served from memory otherwise, which keeps rebuilds in the dev server fast.
     pub fn build(&mut self) -> anyhow::Result<Bundle> {
-        // Build directory structure
-        let dirs = Self::create_output_directories()?;
+        let mut diagnostics = Diagnostics::new();
         // Collect posts
-        let posts = self.gather_posts()?;
-        // Generate difference between last and current build
-        let diff = self.last_cache.diff(&self.current_cache);
-        if !self.bypass_cache {
-            // Synchronize changes (actually delete removed files, etc.)
-            diff.sync()?;
-            // Save cache if different
-            if diff.any_changed() {
-                self.current_cache.save_to_file()?;
+        let mut posts = self.gather_posts(&mut diagnostics)?;
+        let gathered = posts
+            .iter()
+            .map(|post| post.filename.clone())
+            .collect::<Vec<_>>();
+        for hook in self.hooks.iter() {
+            if let Err(err) = hook.posts_gathered(&mut posts) {
+                diagnostics.push(Self::hook_error(hook, err));
+            }
+        }
+        // Posts left out by hooks still exist, so they must not be treated as removed
+        for path in &gathered {
+            if !posts.iter().any(|post| &post.filename == path) {
+                self.current_cache.keep_post(&self.last_cache, path);
+            }
+        }
+        for hook in self.hooks.iter() {
+            if let Err(err) = hook.prepare_theme(&mut self.theme) {
+                diagnostics.push(Self::hook_error(hook, err));
             }
         }
+        let site_data = site::load_data(&self.source, &mut diagnostics);
+        let data_hash = HashCache::hash_contents(serde_json::to_string(&site_data)?);
+        self.current_cache.mix_data(data_hash);
+        let links = PostLinks::new(&posts, &self.config.serve.base_path);
+        let backlinks = self.backlinks(&links, &posts);
+        let links_hash = {
+            // Pages change with the URLs they link to and the posts linking to them
+            let hashed = posts
+                .iter()
+                .map(|post| {
+                    let targets = links
+                        .targets(post)
+                        .into_iter()
+                        .filter_map(|target| links.url(&target).map(str::to_string))
+                        .collect::<Vec<_>>();
+                    (&post.filename, (targets, backlinks.get(&post.filename)))
+                })
+                .collect::<BTreeMap<_, _>>();
+            HashCache::hash_contents(format!("{:?}", hashed))
+        };
+        self.current_cache.mix_links(links_hash);
+        let mut hashes = HashMap::<PathBuf, u32>::new();
+        for post in &posts {
+            let images_hash = self.images.hash_images(post, &self.source);
+            self.current_cache.mix_post(post, images_hash);
+            hashes.insert(
+                post.filename.clone(),
+                Self::hash_post(post, data_hash, links_hash, images_hash)?,
+            );
+        }
+        self.rendered
+            .posts
+            .retain(|filename, _| hashes.contains_key(filename));
+        let index_hash = {
+            let mut sorted = hashes.iter().collect::<Vec<_>>();
+            sorted.sort();
+            HashCache::hash_contents(format!("{:?}", sorted))
+        };
+        // Generate difference between last and current build
+        let diff = self.last_cache.diff(&self.current_cache);
         // Populate output map
         let mut output_map = HashMap::<String, Post>::new();
         for post in &posts {
             output_map.insert(post.get_final_file_name(), post.clone());
         }
+        // Generate bundle
+        let mut bundle = Bundle::new();
+        // Images used by several posts are only added once
+        let mut add_files = {
+            let mut added_images = HashSet::new();
+            move |bundle: &mut Bundle, files: &[BuildFile]| {
+                for file in files {
+                    if !file.is_binary() || added_images.insert(file.virtual_path().to_string()) {
+                        bundle.add_file(file.clone());
+                    }
+                }
+            }
+        };
+        // Reuse posts that are unchanged since the last build of this engine
+        let posts = posts
+            .into_iter()
+            .filter(|post| match self.rendered.posts.get(&post.filename) {
+                Some((hash, files)) if *hash == hashes[&post.filename] => {
+                    add_files(&mut bundle, files);
+                    false
+                }
+                _ => true,
+            })
+            .collect::<Vec<_>>();
         // Collect post that actually have to be rendered
         let posts = {
             let paths = {
-                if self.bypass_cache {
+                if self.bypass_cache
+                    || diff.config_changed()
+                    || diff.theme_changed()
+                    || diff.data_changed()
+                    || diff.links_changed()
+                {
                     posts.iter().map(|post| post.filename.clone()).collect()
                 } else {
                     diff.changed_post_paths()
@@ -154,68 +354,465 @@ impl Engine {
             posts
                 .into_iter()
                 .filter(|post| {
-                    let file_path = dirs.build_post_dir.join(post.get_final_file_name());
-                    paths.contains(&post.filename) || !file_path.exists()
+                    let file_path = Path::new(POSTS_DIR).join(post.get_final_file_name());
+                    paths.contains(&post.filename) || !self.is_built(&file_path)
                 })
                 .collect::<Vec<_>>()
         };
-        // Generate bundle
-        let mut bundle = Bundle::new();
         // Generate posts
         for post in posts.into_iter() {
             let file_name = post.get_final_file_name();
-            let file_path = dirs.build_post_dir.join(&file_name);
+            let file_path = Path::new(POSTS_DIR).join(&file_name);
             let virtual_path = format!("/posts/{}", file_name);
-            let data = RenderData::for_post(&self.config, &post)?;
-            let post_page = self.theme.render_post(data)?;
-            bundle.add_file(BuildFile::new(file_path, virtual_path, post_page));
-        }
-        // Generate index.html
-        let index_file_path = dirs.build_dir.join("index.html");
-        if self.bypass_cache || (diff.should_rerender_index_page() || !index_file_path.exists()) {
-            let data = RenderData::for_index(&self.config, &output_map);
-            let index_page = self.theme.render_index(data)?;
-            bundle.add_file(BuildFile::new(index_file_path, "/", index_page));
-        }
-        // Create style.css
+            let template_errors = self.check_templates(&post);
+            if !template_errors.is_empty() {
+                template_errors
+                    .into_iter()
+                    .for_each(|diagnostic| diagnostics.push(diagnostic));
+                continue;
+            }
+            let options = ConvertOptions::new(&self.config.markdown)
+                .toc(post.metadata.toc.unwrap_or(true))
+                .shortcodes(&self.theme)
+                .links(&links, &post.filename);
+            let mut document = match post.format.convert(&post.contents, &options) {
+                Ok(document) => document,
+                Err(err) => {
+                    diagnostics.push(Diagnostic::error(err).with_file(&post.filename));
+                    continue;
+                }
+            };
+            let (html, images) = self.images.rewrite(
+                &document.html,
+                &post,
+                &self.source,
+                &self.config.serve.base_path,
+                &mut document.diagnostics,
+            );
+            document.html = html;
+            let has_errors = document.diagnostics.iter().any(Diagnostic::is_error);
+            for diagnostic in std::mem::take(&mut document.diagnostics) {
+                diagnostics.push(diagnostic.with_file(&post.filename));
+            }
+            if has_errors {
+                continue;
+            }
+            for hook in self.hooks.iter() {
+                if let Err(err) = hook.post_converted(&post, &mut document.html) {
+                    diagnostics.push(Self::hook_error(hook, err).with_file(&post.filename));
+                }
+            }
+            let mut data = RenderData::for_post(&self.config, &post, document);
+            data.site.data = site_data.clone();
+            if let Some(post_data) = &mut data.post {
+                post_data.backlinks = backlinks.get(&post.filename).cloned().unwrap_or_default();
+            }
+            for hook in self.hooks.iter() {
+                if let Err(err) = hook.before_render(&mut data) {
+                    diagnostics.push(Self::hook_error(hook, err).with_file(&post.filename));
+                }
+            }
+            match self.theme.render_post(data) {
+                Ok(post_page) => {
+                    let mut files = vec![BuildFile::new(file_path, virtual_path, post_page)];
+                    files.extend(images);
+                    add_files(&mut bundle, &files);
+                    self.rendered
+                        .posts
+                        .insert(post.filename.clone(), (hashes[&post.filename], files));
+                }
+                Err(diagnostic) => diagnostics.push(Diagnostic {
+                    message: format!(
+                        "{} (while rendering {})",
+                        diagnostic.message,
+                        post.filename.display()
+                    ),
+                    ..*diagnostic
+                }),
+            }
+        }
+        // Generate index.html and 404.html, which both list posts
+        let index_file_path = PathBuf::from("index.html");
+        let cached_listings = match &self.rendered.listings {
+            Some((hash, files)) if *hash == index_hash => Some(files.clone()),
+            _ => None,
+        };
+        if let Some(files) = cached_listings {
+            files.into_iter().for_each(|file| bundle.add_file(file));
+        } else if self.bypass_cache
+            || (diff.should_rerender_index_page() || !self.is_built(&index_file_path))
         {
-            let file_path = dirs.build_dir.join("style.css");
-            bundle.add_file(BuildFile::new(file_path, "/style.css", &self.theme.css));
+            let error_count = diagnostics.error_count();
+            let mut listings = Vec::new();
+            let mut data = RenderData::for_index(&self.config, &output_map);
+            data.site.data = site_data.clone();
+            self.before_render(&mut data, &mut diagnostics);
+            match self.theme.render_index(data) {
+                Ok(index_page) => listings.push(BuildFile::new(index_file_path, "/", index_page)),
+                Err(diagnostic) => diagnostics.push(*diagnostic),
+            }
+            if self.theme.has_not_found_page() {
+                let mut data = RenderData::for_not_found(&self.config, &output_map);
+                data.site.data = site_data.clone();
+                self.before_render(&mut data, &mut diagnostics);
+                match self.theme.render_not_found(data) {
+                    Ok(page) => listings.push(BuildFile::new("404.html", "/404.html", page)),
+                    Err(diagnostic) => diagnostics.push(*diagnostic),
+                }
+            }
+            if diagnostics.error_count() == error_count {
+                self.rendered.listings = Some((index_hash, listings.clone()));
+            }
+            listings.into_iter().for_each(|file| bundle.add_file(file));
+        }
+        self.theme_files()
+            .into_iter()
+            .for_each(|file| bundle.add_file(file));
+        for hook in self.hooks.iter() {
+            if let Err(err) = hook.bundle_assembled(&mut bundle) {
+                diagnostics.push(Self::hook_error(hook, err));
+            }
+        }
+        // Bail with every collected error at once
+        if diagnostics.has_errors() {
+            return Err(diagnostics.into());
+        }
+        if !self.bypass_cache {
+            // Save cache if different
+            if diff.any_changed() {
+                self.current_cache.save_to_file(&self.config.root)?;
+            }
+            // Remove the pages of removed posts from the output directory
+            diff.sync(&self.config.build_dir())?;
+            self.images
+                .remove_stale(&gathered, &self.config.build_dir())?;
+            self.images.save()?;
         }
+        self.diagnostics = diagnostics;
         Ok(bundle)
     }
 
-    fn create_output_directories() -> anyhow::Result<DirectoryStructure> {
-        let output_dir = std::env::current_dir()?.join("build");
-        let posts_dir = output_dir.join("posts");
-        std::fs::create_dir_all(&output_dir)?;
-        std::fs::create_dir_all(&posts_dir)?;
-        Ok(DirectoryStructure {
-            build_dir: output_dir,
-            build_post_dir: posts_dir,
+    /// Creates `style.css`, any other stylesheets and the static files of the theme.
+    fn theme_files(&self) -> Vec<BuildFile> {
+        let stylesheets = self.theme.stylesheets.iter().map(|stylesheet| {
+            let file_name = stylesheet.file_name();
+            let virtual_path = format!("/{}", file_name);
+            BuildFile::new(file_name, virtual_path, &stylesheet.css)
+        });
+        let assets = self.theme.assets.iter().map(|asset| {
+            let virtual_path = format!("/{}", asset.path);
+            BuildFile::binary(&asset.path, virtual_path, asset.bytes.clone())
+        });
+        stylesheets.chain(assets).collect()
+    }
+
+    /// Paths of the pages and files a build emits besides posts, which posts may link to.
+    fn output_paths(&self) -> Vec<String> {
+        let mut paths = vec!["/".to_string(), "/index.html".to_string()];
+        if self.theme.has_not_found_page() {
+            paths.push("/404.html".to_string());
+        }
+        paths.extend(
+            self.theme_files()
+                .iter()
+                .map(|file| file.virtual_path().to_string()),
+        );
+        paths
+    }
+
+    fn before_render(&self, data: &mut RenderData, diagnostics: &mut Diagnostics) {
+        for hook in self.hooks.iter() {
+            if let Err(err) = hook.before_render(data) {
+                diagnostics.push(Self::hook_error(hook, err));
+            }
+        }
+    }
+
+    /// Checks that the theme provides the template and layout selected in a post's metadata.
+    fn check_templates(&self, post: &Post) -> Vec<Diagnostic> {
+        let metadata = &post.metadata;
+        [
+            (
+                "template",
+                self.theme.post_template(metadata.template.as_deref()),
+            ),
+            (
+                "layout",
+                self.theme.layout("post", metadata.layout.as_deref()),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(key, result)| result.err().map(|message| (key, message)))
+        .map(|(key, message)| {
+            Diagnostic::error(message)
+                .with_file(&post.filename)
+                .at(post.format.find_metadata_line(&post.contents, key), None)
+                .with_source(&post.contents)
         })
+        .collect()
+    }
+
+    /// Lists the posts linking to each post, newest first, leaving out unpublished ones
+    /// outside of the dev server.
+    fn backlinks(&self, links: &PostLinks, posts: &[Post]) -> HashMap<PathBuf, Vec<PostIndex>> {
+        links
+            .backlinks(posts)
+            .into_iter()
+            .map(|(target, mut sources)| {
+                sources.retain(|post| post.metadata.published || self.config.__is_dev_mode);
+                sources.sort_by_key(|post| {
+                    std::cmp::Reverse(post.metadata.parse_published_at().ok().flatten())
+                });
+                let base_path = &self.config.serve.base_path;
+                let sources = sources
+                    .into_iter()
+                    .map(|post| PostIndex::new(post, base_path))
+                    .collect();
+                (target, sources)
+            })
+            .collect()
     }
 
-    fn gather_posts(&mut self) -> anyhow::Result<Vec<Post>> {
-        let posts_dir = std::env::current_dir()?.join("posts");
+    /// Hashes everything a post page is rendered from, after `posts_gathered` hooks ran.
+    fn hash_post(
+        post: &Post,
+        data_hash: u32,
+        links_hash: u32,
+        images_hash: u32,
+    ) -> anyhow::Result<u32> {
+        Ok(HashCache::hash_contents(format!(
+            "{:08x}{:08x}{:08x}{}",
+            data_hash,
+            links_hash,
+            images_hash,
+            serde_json::to_string(post)?
+        )))
+    }
+
+    fn hook_error(hook: &dyn Hook, err: anyhow::Error) -> Diagnostic {
+        Diagnostic::error(format!("Hook `{}` failed: {:#}", hook.name(), err))
+    }
+
+    /// Whether a file from a previous build exists in the output directory.
+    fn is_built(&self, path: &Path) -> bool {
+        self.config.build_dir().join(path).exists()
+    }
+
+    /// Lints all posts without rendering or writing anything.
+    pub fn check(&mut self) -> anyhow::Result<Diagnostics> {
+        let mut diagnostics = Diagnostics::new();
+        let mut posts = self.gather_posts(&mut diagnostics)?;
+        posts.sort_by(|a, b| a.filename.cmp(&b.filename));
+        let outputs = self.output_paths();
+        Ok(Linter::new(&self.config.check, &self.source, &outputs).lint(&posts, diagnostics))
+    }
+
+    pub fn gather_posts(&self, diagnostics: &mut Diagnostics) -> anyhow::Result<Vec<Post>> {
         let mut posts = Vec::<Post>::new();
-        for dir_entry in std::fs::read_dir(posts_dir)? {
-            let dir_entry = dir_entry?;
-            let path = dir_entry.path();
+        for path in self.source.list_files(POSTS_DIR)? {
             if let Some(Some(extension)) = path.extension().map(|s| s.to_str()) {
-                let format = PostFormat::from_file_extension(extension)?;
-                let contents = std::fs::read_to_string(&path)?;
-                let metadata = format.extract_metadata(&contents)?;
+                let format = match PostFormat::from_file_extension(extension) {
+                    Ok(format) => format,
+                    Err(err) => {
+                        diagnostics.push(Diagnostic::error(err).with_file(&path));
+                        continue;
+                    }
+                };
+                let contents = match self.source.read_to_string(&path) {
+                    Ok(contents) => contents,
+                    Err(err) => {
+                        diagnostics.push(
+                            Diagnostic::error(format!("Unable to read post: {}", err))
+                                .with_file(&path),
+                        );
+                        continue;
+                    }
+                };
+                let metadata = match format.extract_metadata(&contents) {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        diagnostics.push(Diagnostic::error(err).with_file(&path));
+                        continue;
+                    }
+                };
+                match metadata.parse_published_at() {
+                    Ok(Some(_)) => (),
+                    Ok(None) => diagnostics.push(
+                        Diagnostic::warning("Post has no `published_at` date and is listed last")
+                            .with_file(&path)
+                            .with_code(Rule::MissingDate.code()),
+                    ),
+                    Err(err) => {
+                        let line = format.find_metadata_line(&contents, "published_at");
+                        diagnostics.push(
+                            Diagnostic::error(format!(
+                                "Unable to parse `published_at` date: {}",
+                                err
+                            ))
+                            .with_file(&path)
+                            .at(line, None)
+                            .with_source(&contents)
+                            .with_code(Rule::InvalidDate.code()),
+                        );
+                    }
+                }
                 let post = Post {
                     format,
                     metadata,
                     contents,
                     filename: path,
                 };
-                self.current_cache.mix_post(&post);
                 posts.push(post);
             }
         }
         Ok(posts)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MANIFEST: &str =
+        "name = \"plain\"\n\n[options.accent]\ntype = \"string\"\ndefault = \"red\"\n";
+
+    /// Creates a project on disk with a theme showing the site's title on post pages.
+    fn project(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("nail-engine-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let theme = root.join("themes/plain");
+        std::fs::create_dir_all(&theme).unwrap();
+        std::fs::create_dir_all(root.join(POSTS_DIR)).unwrap();
+        std::fs::write(theme.join("plain.base.html"), "{{{ page.content }}}").unwrap();
+        std::fs::write(theme.join("plain.home.html"), "Home").unwrap();
+        std::fs::write(
+            theme.join("plain.post.html"),
+            "{{ site.title }}: {{ post.title }}",
+        )
+        .unwrap();
+        std::fs::write(theme.join("plain.css"), "body { margin: 0; }").unwrap();
+        std::fs::write(theme.join("theme.toml"), MANIFEST).unwrap();
+        std::fs::write(
+            root.join("posts/hello.md"),
+            "[//]: # (title: Hello)\n[//]: # (published_at: 2024-01-01T00:00:00Z)\n\nText\n",
+        )
+        .unwrap();
+        let mut config = Config::new("Blog");
+        config.theme.name = "plain".to_string();
+        config.save_to_file(&root).unwrap();
+        root
+    }
+
+    /// Runs an incremental build like `nail build`, returning the post page if it was rendered.
+    fn build(root: &Path) -> Option<String> {
+        let mut engine = Engine::builder(SourceTree::disk(root))
+            .incremental(true)
+            .load()
+            .unwrap();
+        let bundle = engine.build().unwrap();
+        bundle.write_to_disk(engine.config().build_dir()).unwrap();
+        bundle
+            .get("/posts/hello.html")
+            .map(|page| page.contents().to_string())
+    }
+
+    #[test]
+    fn image_hashes_are_only_saved_by_incremental_builds() {
+        let root = project("image-hashes");
+        let mut config = Config::load(&root).unwrap();
+        config.images.enabled = true;
+        config.images.widths = vec![4];
+        config.save_to_file(&root).unwrap();
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(8, 8)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        std::fs::create_dir_all(root.join("images")).unwrap();
+        std::fs::write(root.join("images/dot.png"), png).unwrap();
+        std::fs::write(
+            root.join("posts/hello.md"),
+            "[//]: # (title: Hello)\n[//]: # (published_at: 2024-01-01T00:00:00Z)\n\n![Dot](/images/dot.png)\n",
+        )
+        .unwrap();
+        let hashes = root.join(".cache/images/hashes.json");
+        Engine::builder(SourceTree::disk(&root))
+            .load()
+            .unwrap()
+            .build()
+            .unwrap();
+        // The variants are cached either way
+        assert!(hashes.parent().unwrap().exists());
+        assert!(!hashes.exists());
+        build(&root);
+        assert!(hashes.exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn posts_may_link_to_every_output_of_the_theme() {
+        let root = project("outputs");
+        let theme = root.join("themes/plain");
+        std::fs::write(theme.join("plain.404.html"), "Not found").unwrap();
+        std::fs::create_dir_all(theme.join("static/fonts")).unwrap();
+        std::fs::write(theme.join("static/fonts/a.woff2"), "font").unwrap();
+        let mut engine = Engine::builder(SourceTree::disk(&root)).load().unwrap();
+        // Stylesheets are fingerprinted, so the unhashed name isn't published
+        let stylesheet = engine.theme.stylesheets[0].file_name();
+        std::fs::write(
+            root.join("posts/hello.md"),
+            format!(
+                "[//]: # (title: Hello)\n[//]: # (published_at: 2024-01-01T00:00:00Z)\n\n\
+                 [Home](/) [Lost](/404.html) [Style](/{}) [Font](/fonts/a.woff2)\n\n\
+                 [Unhashed](/style.css)\n",
+                stylesheet
+            ),
+        )
+        .unwrap();
+        let broken = engine
+            .check()
+            .unwrap()
+            .into_iter()
+            .filter(|diagnostic| diagnostic.code.as_deref() == Some(Rule::BrokenLink.code()))
+            .map(|diagnostic| diagnostic.message)
+            .collect::<Vec<_>>();
+        assert_eq!(broken, ["Broken link to `/style.css`"]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn unchanged_posts_are_not_rendered_again() {
+        let root = project("unchanged");
+        assert_eq!(build(&root).as_deref(), Some("Blog: Hello"));
+        assert_eq!(build(&root), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn config_changes_render_posts_again() {
+        let root = project("config");
+        assert_eq!(build(&root).as_deref(), Some("Blog: Hello"));
+        let mut config = Config::load(&root).unwrap();
+        config.title = "Renamed".to_string();
+        config.save_to_file(&root).unwrap();
+        assert_eq!(build(&root).as_deref(), Some("Renamed: Hello"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn theme_changes_render_posts_again() {
+        let root = project("theme");
+        assert_eq!(build(&root).as_deref(), Some("Blog: Hello"));
+        let template = root.join("themes/plain/plain.post.html");
+        std::fs::write(&template, "{{ post.title }} on {{ site.title }}").unwrap();
+        assert_eq!(build(&root).as_deref(), Some("Hello on Blog"));
+        std::fs::write(root.join("themes/plain/plain.css"), "body { margin: 1em; }").unwrap();
+        assert_eq!(build(&root).as_deref(), Some("Hello on Blog"));
+        std::fs::write(&template, "{{ theme.options.accent }}").unwrap();
+        assert_eq!(build(&root).as_deref(), Some("red"));
+        let manifest = root.join("themes/plain/theme.toml");
+        std::fs::write(&manifest, MANIFEST.replace("red", "blue")).unwrap();
+        assert_eq!(build(&root).as_deref(), Some("blue"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
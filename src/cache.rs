@@ -1,15 +1,33 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{build::Post, config::Config};
+use crate::{
+    build::{Post, POSTS_DIR},
+    config::Config,
+};
 
 static CACHE_FILE_NAME: &str = ".cache.toml";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct HashCache {
     config: u32,
+    /// Hash of the files in `data/`.
+    #[serde(default)]
+    data: u32,
+    /// Hash of the links between posts, see [`PostLinks`](crate::links::PostLinks).
+    #[serde(default)]
+    links: u32,
+    /// Hash of the theme's options, templates and stylesheets.
+    #[serde(default)]
+    theme: u32,
     posts: HashMap<PathBuf, u32>,
+    /// File name of each post's page, to remove it once the post is gone or renamed.
+    #[serde(default)]
+    pages: HashMap<PathBuf, String>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -23,37 +41,67 @@ pub enum FileDiffMode {
 #[derive(Debug)]
 pub struct HashDiff {
     config: FileDiffMode,
+    data: FileDiffMode,
+    links: FileDiffMode,
+    theme: FileDiffMode,
     posts: Vec<(PathBuf, FileDiffMode)>,
+    /// Pages of the last build that no post renders anymore.
+    stale_pages: Vec<String>,
 }
 
 impl HashCache {
     pub fn empty() -> Self {
         Self {
             config: 0,
+            data: 0,
+            links: 0,
+            theme: 0,
             posts: HashMap::new(),
+            pages: HashMap::new(),
         }
     }
 
-    pub fn read_from_file() -> anyhow::Result<Self> {
-        let path = std::env::current_dir()?.join(CACHE_FILE_NAME);
+    pub fn read_from_file<P>(root: P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = root.as_ref().join(CACHE_FILE_NAME);
         let str = std::fs::read_to_string(&path)?;
         Ok(toml::from_str(&str)?)
     }
 
-    pub fn save_to_file(&self) -> anyhow::Result<()> {
+    pub fn save_to_file<P>(&self, root: P) -> anyhow::Result<()>
+    where
+        P: AsRef<Path>,
+    {
         use std::io::Write;
         let cache_toml = toml::to_string_pretty(&self)?;
-        let path = std::env::current_dir()?.join(CACHE_FILE_NAME);
+        let path = root.as_ref().join(CACHE_FILE_NAME);
         let mut file = std::fs::File::create(&path)?;
         let _ = file.write(cache_toml.as_bytes())?;
         Ok(())
     }
 
-    pub fn mix_post(&mut self, post: &Post) {
-        self.posts.insert(
-            post.filename.clone(),
-            Self::hash_contents(post.contents.as_str()),
-        );
+    /// Records a post along with `images_hash`, the hash of the images it refers to, if any.
+    pub fn mix_post(&mut self, post: &Post, images_hash: u32) {
+        let hash = match images_hash {
+            0 => Self::hash_contents(post.contents.as_str()),
+            _ => Self::hash_contents(format!("{:08x}{}", images_hash, post.contents)),
+        };
+        self.posts.insert(post.filename.clone(), hash);
+        self.pages
+            .insert(post.filename.clone(), post.get_final_file_name());
+    }
+
+    /// Keeps the entry of a post from `last`, e.g. one left out by a hook, so its page isn't
+    /// treated as removed.
+    pub fn keep_post(&mut self, last: &HashCache, path: &Path) {
+        if let Some(hash) = last.posts.get(path) {
+            self.posts.insert(path.to_path_buf(), *hash);
+        }
+        if let Some(page) = last.pages.get(path) {
+            self.pages.insert(path.to_path_buf(), page.clone());
+        }
     }
 
     pub fn mix_config(&mut self, config: &Config) -> anyhow::Result<()> {
@@ -61,6 +109,18 @@ impl HashCache {
         Ok(())
     }
 
+    pub fn mix_data(&mut self, hash: u32) {
+        self.data = hash;
+    }
+
+    pub fn mix_links(&mut self, hash: u32) {
+        self.links = hash;
+    }
+
+    pub fn mix_theme(&mut self, hash: u32) {
+        self.theme = hash;
+    }
+
     pub fn diff(&self, hashes: &HashCache) -> HashDiff {
         fn diff_entries(
             a: &HashMap<PathBuf, u32>,
@@ -92,28 +152,60 @@ impl HashCache {
         } else {
             FileDiffMode::Updated
         };
+        let data_diff = if self.data == hashes.data {
+            FileDiffMode::Unchanged
+        } else {
+            FileDiffMode::Updated
+        };
+        let links_diff = if self.links == hashes.links {
+            FileDiffMode::Unchanged
+        } else {
+            FileDiffMode::Updated
+        };
+        let theme_diff = if self.theme == hashes.theme {
+            FileDiffMode::Unchanged
+        } else {
+            FileDiffMode::Updated
+        };
+        let mut stale_pages = self
+            .pages
+            .iter()
+            .filter(|(path, page)| hashes.pages.get(*path) != Some(page))
+            .map(|(_, page)| page.clone())
+            .filter(|page| !hashes.pages.values().any(|current| current == page))
+            .collect::<Vec<_>>();
+        stale_pages.sort();
+        stale_pages.dedup();
         HashDiff {
             config: config_diff,
+            data: data_diff,
+            links: links_diff,
+            theme: theme_diff,
             posts: post_diffs,
+            stale_pages,
         }
     }
 
-    fn hash_contents<S>(str: S) -> u32
+    pub(crate) fn hash_contents<S>(str: S) -> u32
     where
         S: AsRef<str>,
     {
-        crc::Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(str.as_ref().as_bytes())
+        Self::hash_bytes(str.as_ref().as_bytes())
+    }
+
+    pub(crate) fn hash_bytes(bytes: &[u8]) -> u32 {
+        crc::Crc::<u32>::new(&crc::CRC_32_CKSUM).checksum(bytes)
     }
 }
 
 impl HashDiff {
-    pub fn sync(&self) -> anyhow::Result<()> {
-        for (path, _) in self
-            .posts
-            .iter()
-            .filter(|(_, mode)| mode == &FileDiffMode::Removed)
-        {
-            std::fs::remove_file(path)?;
+    /// Removes the pages of removed or renamed posts from `build_dir`, the output directory.
+    pub fn sync(&self, build_dir: &Path) -> anyhow::Result<()> {
+        for page in &self.stale_pages {
+            match std::fs::remove_file(build_dir.join(POSTS_DIR).join(page)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
         }
         Ok(())
     }
@@ -126,8 +218,28 @@ impl HashDiff {
             .collect::<Vec<_>>()
     }
 
+    /// Whether any file in `data/` changed, which every page may depend on.
+    pub fn data_changed(&self) -> bool {
+        self.data == FileDiffMode::Updated
+    }
+
+    /// Whether a post's URL or the links between posts changed, which post pages depend on.
+    pub fn links_changed(&self) -> bool {
+        self.links == FileDiffMode::Updated
+    }
+
+    /// Whether `config.toml` changed, whose settings every page is rendered with as `site`.
+    pub fn config_changed(&self) -> bool {
+        self.config == FileDiffMode::Updated
+    }
+
+    /// Whether the theme's options, templates or stylesheets changed, which every page uses.
+    pub fn theme_changed(&self) -> bool {
+        self.theme == FileDiffMode::Updated
+    }
+
     pub fn should_rerender_index_page(&self) -> bool {
-        let config_changed = self.config == FileDiffMode::Updated;
+        let config_changed = self.config_changed() || self.theme_changed() || self.data_changed();
         let posts_changed = self
             .posts
             .iter()
@@ -135,13 +247,150 @@ impl HashDiff {
         config_changed || posts_changed
     }
 
+    /// Whether the config, theme, data, links or any post changed since the last build.
     pub fn any_changed(&self) -> bool {
-        if self.config == FileDiffMode::Unchanged {
-            return false;
+        self.config_changed()
+            || self.theme_changed()
+            || self.data_changed()
+            || self.links_changed()
+            || self
+                .posts
+                .iter()
+                .any(|(_, mode)| mode != &FileDiffMode::Unchanged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post_format::PostFormat;
+
+    fn post(path: &str, title: &str) -> Post {
+        let contents = format!("[//]: # (title: {})\n\nText\n", title);
+        Post {
+            format: PostFormat::Markdown,
+            filename: path.into(),
+            metadata: PostFormat::Markdown.extract_metadata(&contents).unwrap(),
+            contents,
         }
-        return self
-            .posts
-            .iter()
-            .all(|(_, diff)| diff == &FileDiffMode::Unchanged);
+    }
+
+    fn cache(posts: &[Post]) -> HashCache {
+        let mut cache = HashCache::empty();
+        for post in posts {
+            cache.mix_post(post, 0);
+        }
+        cache
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nail-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(POSTS_DIR)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn identical_caches_have_no_changes() {
+        let posts = [post("posts/a.md", "A"), post("posts/b.md", "B")];
+        let diff = cache(&posts).diff(&cache(&posts));
+        assert!(!diff.any_changed());
+        assert!(!diff.should_rerender_index_page());
+        assert!(diff.changed_post_paths().is_empty());
+    }
+
+    #[test]
+    fn changed_posts_are_reported() {
+        let last = cache(&[post("posts/a.md", "A"), post("posts/b.md", "B")]);
+        let current = cache(&[post("posts/a.md", "A2"), post("posts/c.md", "C")]);
+        let diff = last.diff(&current);
+        assert!(diff.any_changed());
+        assert!(diff.should_rerender_index_page());
+        let mut changed = diff.changed_post_paths();
+        changed.sort();
+        assert_eq!(changed, [PathBuf::from("posts/a.md"), "posts/c.md".into()]);
+    }
+
+    #[test]
+    fn removed_posts_alone_are_a_change() {
+        let last = cache(&[post("posts/a.md", "A"), post("posts/b.md", "B")]);
+        let diff = last.diff(&cache(&[post("posts/a.md", "A")]));
+        assert!(diff.any_changed());
+        assert!(diff.changed_post_paths().is_empty());
+    }
+
+    #[test]
+    fn config_theme_data_and_links_are_changes() {
+        let posts = [post("posts/a.md", "A")];
+        let last = cache(&posts);
+        let mut current = cache(&posts);
+        current.mix_config(&Config::new("blog")).unwrap();
+        let diff = last.diff(&current);
+        assert!(diff.any_changed());
+        assert!(diff.should_rerender_index_page());
+        let mut current = cache(&posts);
+        current.mix_data(1);
+        assert!(last.diff(&current).any_changed());
+        let mut current = cache(&posts);
+        current.mix_links(1);
+        let diff = last.diff(&current);
+        assert!(diff.any_changed());
+        assert!(diff.links_changed());
+        let mut current = cache(&posts);
+        current.mix_theme(1);
+        let diff = last.diff(&current);
+        assert!(diff.any_changed());
+        assert!(diff.theme_changed());
+        assert!(diff.should_rerender_index_page());
+    }
+
+    #[test]
+    fn sync_removes_pages_of_removed_posts_from_the_output() {
+        let root = temp_dir("removed");
+        let build_dir = root.join("build");
+        std::fs::create_dir_all(build_dir.join(POSTS_DIR)).unwrap();
+        std::fs::write(root.join("posts/second.md"), "source").unwrap();
+        std::fs::write(build_dir.join("posts/second.html"), "page").unwrap();
+        let first = post("posts/first.md", "First");
+        let last = cache(&[first.clone(), post("posts/second.md", "Second")]);
+        last.diff(&cache(&[first])).sync(&build_dir).unwrap();
+        assert!(!build_dir.join("posts/second.html").exists());
+        assert!(root.join("posts/second.md").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn sync_ignores_pages_that_are_already_gone() {
+        let root = temp_dir("gone");
+        let last = cache(&[post("posts/second.md", "Second")]);
+        last.diff(&HashCache::empty())
+            .sync(&root.join("build"))
+            .unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn kept_posts_are_not_removed() {
+        let second = post("posts/second.md", "Second");
+        let last = cache(std::slice::from_ref(&second));
+        let mut current = HashCache::empty();
+        current.keep_post(&last, &second.filename);
+        let diff = last.diff(&current);
+        assert!(diff.stale_pages.is_empty());
+        assert!(diff.changed_post_paths().is_empty());
+    }
+
+    #[test]
+    fn renamed_posts_leave_their_old_page_stale() {
+        let last = cache(&[post("posts/a.md", "Old"), post("posts/b.md", "Kept")]);
+        let current = cache(&[post("posts/a.md", "New"), post("posts/b.md", "Kept")]);
+        assert_eq!(last.diff(&current).stale_pages, vec!["old.html"]);
+    }
+
+    #[test]
+    fn pages_taken_over_by_another_post_are_not_stale() {
+        let last = cache(&[post("posts/a.md", "Same")]);
+        let current = cache(&[post("posts/b.md", "Same")]);
+        assert!(last.diff(&current).stale_pages.is_empty());
     }
 }
@@ -0,0 +1,806 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::Post,
+    diagnostics::{Diagnostic, Diagnostics, Severity},
+    links::{find_wiki_links, PostLinks, Resolved},
+    post_format::PostFormat,
+    post_metadata::KNOWN_METADATA_KEYS,
+    source::SourceTree,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleLevel {
+    Error,
+    Warning,
+    Off,
+}
+
+/// The `[check]` section of `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckConfig {
+    /// Metadata keys that are used by the theme and shouldn't be reported as unknown.
+    ///
+    /// Declared before `rules`, as TOML requires plain values to come before tables.
+    #[serde(default)]
+    pub extra_metadata: Vec<String>,
+    /// Overrides the level of individual rules, e.g. `image-alt = "off"`.
+    #[serde(default)]
+    pub rules: HashMap<String, RuleLevel>,
+}
+
+impl CheckConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    MissingTitle,
+    MissingDate,
+    InvalidDate,
+    DuplicateSlug,
+    BrokenLink,
+    ImageAlt,
+    HeadingJump,
+    UnknownMetadata,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::MissingTitle,
+        Rule::MissingDate,
+        Rule::InvalidDate,
+        Rule::DuplicateSlug,
+        Rule::BrokenLink,
+        Rule::ImageAlt,
+        Rule::HeadingJump,
+        Rule::UnknownMetadata,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rule::MissingTitle => "missing-title",
+            Rule::MissingDate => "missing-date",
+            Rule::InvalidDate => "invalid-date",
+            Rule::DuplicateSlug => "duplicate-slug",
+            Rule::BrokenLink => "broken-link",
+            Rule::ImageAlt => "image-alt",
+            Rule::HeadingJump => "heading-jump",
+            Rule::UnknownMetadata => "unknown-metadata",
+        }
+    }
+
+    pub fn default_level(&self) -> RuleLevel {
+        match self {
+            Rule::MissingTitle | Rule::InvalidDate | Rule::DuplicateSlug | Rule::BrokenLink => {
+                RuleLevel::Error
+            }
+            Rule::MissingDate | Rule::ImageAlt | Rule::HeadingJump | Rule::UnknownMetadata => {
+                RuleLevel::Warning
+            }
+        }
+    }
+
+    pub fn from_code<S>(code: S) -> Option<Self>
+    where
+        S: AsRef<str>,
+    {
+        Self::ALL
+            .into_iter()
+            .find(|rule| rule.code() == code.as_ref())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow::anyhow!("Invalid output format: `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonReport<'a> {
+    errors: usize,
+    warnings: usize,
+    diagnostics: &'a Diagnostics,
+}
+
+/// Renders diagnostics as a JSON document for CI tooling.
+pub fn to_json(diagnostics: &Diagnostics) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(&JsonReport {
+        errors: diagnostics.error_count(),
+        warnings: diagnostics.warning_count(),
+        diagnostics,
+    })?)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ReferenceKind {
+    Link,
+    Image,
+    /// A `[[target]]`, see [`links`](crate::links).
+    WikiLink,
+}
+
+#[derive(Debug)]
+struct Reference {
+    kind: ReferenceKind,
+    target: String,
+    alt: Option<String>,
+    line: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Heading {
+    level: u32,
+    line: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct Linter<'a> {
+    config: &'a CheckConfig,
+    source: &'a SourceTree,
+    /// Paths of the pages and files a build emits besides posts, e.g. `/` or `/style.css`.
+    outputs: &'a [String],
+}
+
+impl<'a> Linter<'a> {
+    pub fn new(config: &'a CheckConfig, source: &'a SourceTree, outputs: &'a [String]) -> Self {
+        Self {
+            config,
+            source,
+            outputs,
+        }
+    }
+
+    /// Lints all posts, applying the configured rule levels to `diagnostics` as well.
+    pub fn lint(&self, posts: &[Post], diagnostics: Diagnostics) -> Diagnostics {
+        let mut diagnostics = diagnostics;
+        for code in self.config.rules.keys() {
+            if Rule::from_code(code).is_none() {
+                diagnostics.push(Diagnostic::error(format!(
+                    "Unknown rule `{}` in `[check.rules]`",
+                    code
+                )));
+            }
+        }
+        let known_paths = posts
+            .iter()
+            .map(|post| format!("/posts/{}", post.get_final_file_name()))
+            .chain(self.outputs.iter().cloned())
+            .collect::<Vec<_>>();
+        let links = PostLinks::new(posts, "");
+        let mut slugs = HashMap::<String, &Post>::new();
+        for post in posts {
+            self.lint_metadata(post, &mut diagnostics);
+            if let Some(other) = slugs.insert(post.get_final_file_name(), post) {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "Post slug `{}` is also used by {}",
+                        post.get_final_file_name(),
+                        other.filename.display()
+                    ))
+                    .with_file(&post.filename)
+                    .with_code(Rule::DuplicateSlug.code()),
+                );
+            }
+            let (references, headings) = match post.format {
+                PostFormat::Markdown => scan_markdown(&post.contents),
+                PostFormat::Html => scan_html(&post.contents),
+            };
+            self.lint_references(post, &references, &known_paths, &links, &mut diagnostics);
+            self.lint_headings(post, &headings, &mut diagnostics);
+        }
+        diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| self.apply_level(diagnostic))
+            .collect()
+    }
+
+    fn lint_metadata(&self, post: &Post, diagnostics: &mut Diagnostics) {
+        let title = post.metadata.title.trim();
+        if title.is_empty() || title == "N/A" {
+            diagnostics.push(
+                Diagnostic::error("Post has no title")
+                    .with_file(&post.filename)
+                    .with_code(Rule::MissingTitle.code()),
+            );
+        }
+        for (line, key, _) in post.format.extract_kvps(&post.contents) {
+            let is_known = KNOWN_METADATA_KEYS.contains(&key.as_str())
+                || self.config.extra_metadata.contains(&key);
+            if !is_known {
+                diagnostics.push(
+                    Diagnostic::warning(format!("Unknown metadata key `{}`", key))
+                        .with_file(&post.filename)
+                        .at(Some(line), None)
+                        .with_source(&post.contents)
+                        .with_code(Rule::UnknownMetadata.code()),
+                );
+            }
+        }
+    }
+
+    fn lint_references(
+        &self,
+        post: &Post,
+        references: &[Reference],
+        known_paths: &[String],
+        links: &PostLinks,
+        diagnostics: &mut Diagnostics,
+    ) {
+        for reference in references {
+            if reference.kind == ReferenceKind::Image
+                && reference
+                    .alt
+                    .as_deref()
+                    .is_none_or(|alt| alt.trim().is_empty())
+            {
+                diagnostics.push(
+                    Diagnostic::warning(format!("Image `{}` has no alt text", reference.target))
+                        .with_file(&post.filename)
+                        .at(reference.line, None)
+                        .with_source(&post.contents)
+                        .with_code(Rule::ImageAlt.code()),
+                );
+            }
+            let resolves = match reference.kind {
+                ReferenceKind::WikiLink => links.resolve_wiki(&reference.target).is_some(),
+                _ => self.resolves(post, &reference.target, known_paths, links),
+            };
+            if !resolves {
+                diagnostics.push(
+                    Diagnostic::error(match reference.kind {
+                        ReferenceKind::WikiLink => {
+                            format!("Broken wiki link `[[{}]]`", reference.target)
+                        }
+                        _ => format!("Broken link to `{}`", reference.target),
+                    })
+                    .with_file(&post.filename)
+                    .at(reference.line, None)
+                    .with_source(&post.contents)
+                    .with_code(Rule::BrokenLink.code()),
+                );
+            }
+        }
+    }
+
+    fn lint_headings(&self, post: &Post, headings: &[Heading], diagnostics: &mut Diagnostics) {
+        // The post title is rendered as the top-level heading
+        let mut previous_level = 1;
+        for heading in headings {
+            if heading.level > previous_level + 1 {
+                diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "Heading level jumps from h{} to h{}",
+                        previous_level, heading.level
+                    ))
+                    .with_file(&post.filename)
+                    .at(heading.line, None)
+                    .with_source(&post.contents)
+                    .with_code(Rule::HeadingJump.code()),
+                );
+            }
+            previous_level = heading.level;
+        }
+    }
+
+    /// Checks whether an internal link points at a generated page, a post or an existing file.
+    fn resolves(
+        &self,
+        post: &Post,
+        target: &str,
+        known_paths: &[String],
+        links: &PostLinks,
+    ) -> bool {
+        match links.resolve_url(&post.filename, target) {
+            Resolved::Post(..) => return true,
+            Resolved::Dangling => return false,
+            Resolved::Other => (),
+        }
+        let is_external = target.contains("://")
+            || ["mailto:", "tel:", "data:", "//", "#"]
+                .iter()
+                .any(|prefix| target.starts_with(prefix));
+        if is_external {
+            return true;
+        }
+        let path = target.split(['#', '?']).next().unwrap_or_default();
+        if path.is_empty() {
+            return true;
+        }
+        let virtual_path = if path.starts_with('/') {
+            normalize(Path::new(path))
+        } else {
+            normalize(&Path::new("/posts").join(path))
+        };
+        if known_paths
+            .iter()
+            .any(|known| Path::new(known) == virtual_path)
+        {
+            return true;
+        }
+        let file_path = if path.starts_with('/') {
+            PathBuf::from(path.trim_start_matches('/'))
+        } else {
+            post.filename
+                .parent()
+                .map(|dir| normalize(&dir.join(path)))
+                .unwrap_or_else(|| PathBuf::from(path))
+        };
+        self.source.exists(file_path)
+    }
+
+    fn apply_level(&self, mut diagnostic: Diagnostic) -> Option<Diagnostic> {
+        let rule = match diagnostic.code.as_deref().and_then(Rule::from_code) {
+            Some(rule) => rule,
+            None => return Some(diagnostic),
+        };
+        let level = self
+            .config
+            .rules
+            .get(rule.code())
+            .copied()
+            .unwrap_or_else(|| rule.default_level());
+        diagnostic.severity = match level {
+            RuleLevel::Error => Severity::Error,
+            RuleLevel::Warning => Severity::Warning,
+            RuleLevel::Off => return None,
+        };
+        Some(diagnostic)
+    }
+}
+
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => (),
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The sources of the images in a post, as written.
+pub(crate) fn image_targets(post: &Post) -> Vec<String> {
+    let (references, _) = match post.format {
+        PostFormat::Markdown => scan_markdown(&post.contents),
+        PostFormat::Html => scan_html(&post.contents),
+    };
+    references
+        .into_iter()
+        .filter(|reference| reference.kind == ReferenceKind::Image)
+        .map(|reference| reference.target)
+        .collect()
+}
+
+fn scan_markdown(contents: &str) -> (Vec<Reference>, Vec<Heading>) {
+    use comrak::{
+        arena_tree::Node,
+        nodes::{Ast, NodeValue},
+        parse_document, Arena, ComrakOptions,
+    };
+    use std::cell::RefCell;
+
+    // Inline nodes carry no line information, so fall back to their enclosing block
+    fn line_of<'a>(node: &'a Node<'a, RefCell<Ast>>) -> Option<usize> {
+        node.ancestors()
+            .map(|node| node.data.borrow().start_line as usize)
+            .find(|&line| line > 0)
+    }
+
+    fn text_of<'a>(node: &'a Node<'a, RefCell<Ast>>) -> String {
+        node.descendants()
+            .filter_map(|node| match &node.data.borrow().value {
+                NodeValue::Text(text)
+                | NodeValue::Code(comrak::nodes::NodeCode { literal: text, .. }) => {
+                    Some(String::from_utf8_lossy(text).to_string())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    let arena = Arena::new();
+    let options = ComrakOptions::default();
+    let ast = parse_document(&arena, contents, &options);
+    let mut references = Vec::new();
+    let mut headings = Vec::new();
+    for node in ast.descendants() {
+        let value = node.data.borrow().value.clone();
+        match value {
+            NodeValue::Link(link) => references.push(Reference {
+                kind: ReferenceKind::Link,
+                target: String::from_utf8_lossy(&link.url).to_string(),
+                alt: None,
+                line: line_of(node),
+            }),
+            NodeValue::Image(link) => references.push(Reference {
+                kind: ReferenceKind::Image,
+                target: String::from_utf8_lossy(&link.url).to_string(),
+                alt: Some(text_of(node)),
+                line: line_of(node),
+            }),
+            NodeValue::Heading(heading) => headings.push(Heading {
+                level: heading.level,
+                line: line_of(node),
+            }),
+            NodeValue::Text(text) => {
+                let text = String::from_utf8_lossy(&text);
+                references.extend(find_wiki_links(&text).into_iter().map(|link| Reference {
+                    kind: ReferenceKind::WikiLink,
+                    target: link.target.to_string(),
+                    alt: None,
+                    line: line_of(node),
+                }));
+            }
+            _ => (),
+        }
+    }
+    (references, headings)
+}
+
+fn scan_html(contents: &str) -> (Vec<Reference>, Vec<Heading>) {
+    let mut references = Vec::new();
+    let mut headings = Vec::new();
+    for tag in html_tags(contents) {
+        match tag.name.as_str() {
+            "a" => {
+                if let Some(href) = tag.attributes.get("href") {
+                    references.push(Reference {
+                        kind: ReferenceKind::Link,
+                        target: href.clone(),
+                        alt: None,
+                        line: Some(tag.line),
+                    });
+                }
+            }
+            "img" => references.push(Reference {
+                kind: ReferenceKind::Image,
+                target: tag.attributes.get("src").cloned().unwrap_or_default(),
+                alt: tag.attributes.get("alt").cloned(),
+                line: Some(tag.line),
+            }),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => headings.push(Heading {
+                level: tag.name[1..].parse().unwrap_or(1),
+                line: Some(tag.line),
+            }),
+            _ => (),
+        }
+    }
+    (references, headings)
+}
+
+#[derive(Debug)]
+struct HtmlTag {
+    name: String,
+    attributes: HashMap<String, String>,
+    line: usize,
+}
+
+/// A minimal scanner for opening tags, good enough to find links, images and headings.
+fn html_tags(contents: &str) -> Vec<HtmlTag> {
+    let mut tags = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find('<') {
+        let line = contents[..contents.len() - rest.len() + start]
+            .matches('\n')
+            .count()
+            + 1;
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.split_once("-->").map_or("", |(_, rest)| rest);
+            continue;
+        }
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = rest[..end].trim_end_matches('/');
+        rest = &rest[end.min(rest.len())..];
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        let name = tag[..name_end].to_ascii_lowercase();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        tags.push(HtmlTag {
+            name,
+            attributes: parse_attributes(&tag[name_end..]).into_iter().collect(),
+            line,
+        });
+    }
+    tags
+}
+
+/// Parses the attributes of a tag in order, with their values as written.
+pub(crate) fn parse_attributes(mut source: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        source = source.trim_start();
+        let name_end = source
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(source.len());
+        if name_end == 0 {
+            break;
+        }
+        let name = source[..name_end].to_ascii_lowercase();
+        source = source[name_end..].trim_start();
+        let value = match source.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, rest) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        value[1..].split_once(quote).unwrap_or((&value[1..], ""))
+                    }
+                    _ => value.split_once(char::is_whitespace).unwrap_or((value, "")),
+                };
+                source = rest;
+                value.to_string()
+            }
+            None => String::new(),
+        };
+        attributes.push((name, value));
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemoryTree;
+
+    /// A Markdown post at `path` with the given metadata lines and body.
+    fn post(path: &str, metadata: &str, body: &str) -> Post {
+        let contents = format!(
+            "{}[//]: # (published_at: 2024-01-01T00:00:00Z)\n\n{}",
+            metadata, body
+        );
+        Post {
+            format: PostFormat::Markdown,
+            filename: path.into(),
+            metadata: PostFormat::Markdown.extract_metadata(&contents).unwrap(),
+            contents,
+        }
+    }
+
+    /// Lints `posts` over an in-memory project, returning each diagnostic's code and severity.
+    fn lint(config: &CheckConfig, posts: &[Post]) -> Vec<(String, Severity, String)> {
+        let source = SourceTree::from(
+            MemoryTree::new()
+                .with_file("config.toml", "name = \"blog\"\ntitle = \"Blog\"\n")
+                .with_file("images/cat.png", "png"),
+        );
+        let outputs = ["/".to_string(), "/style.css".to_string()];
+        Linter::new(config, &source, &outputs)
+            .lint(posts, Diagnostics::new())
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.code.unwrap_or_default(),
+                    diagnostic.severity,
+                    diagnostic.message,
+                )
+            })
+            .collect()
+    }
+
+    fn codes(config: &CheckConfig, posts: &[Post]) -> Vec<String> {
+        lint(config, posts)
+            .into_iter()
+            .map(|(code, ..)| code)
+            .collect()
+    }
+
+    #[test]
+    fn clean_posts_have_no_diagnostics() {
+        let hello = post(
+            "posts/hello.md",
+            "[//]: # (title: Hello)\n",
+            "# Intro\n\n## Details\n\n[Home](/) [Style](/style.css) [Other](other.html)\n\n\
+             ![A cat](/images/cat.png)\n",
+        );
+        let other = post("posts/other.md", "[//]: # (title: Other)\n", "Text\n");
+        assert_eq!(lint(&CheckConfig::default(), &[hello, other]), []);
+    }
+
+    #[test]
+    fn posts_without_a_title_are_reported() {
+        let post = post("posts/untitled.md", "", "Text\n");
+        assert_eq!(
+            lint(&CheckConfig::default(), &[post]),
+            [(
+                "missing-title".to_string(),
+                Severity::Error,
+                "Post has no title".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn posts_with_the_same_slug_are_reported() {
+        let first = post("posts/first.md", "[//]: # (title: Same)\n", "Text\n");
+        let second = post("posts/second.md", "[//]: # (title: Same)\n", "Text\n");
+        let diagnostics = lint(&CheckConfig::default(), &[first, second]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].0, "duplicate-slug");
+        assert_eq!(diagnostics[0].1, Severity::Error);
+        assert!(
+            diagnostics[0].2.contains("posts/first.md"),
+            "{:?}",
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn broken_links_are_reported() {
+        let post = post(
+            "posts/hello.md",
+            "[//]: # (title: Hello)\n",
+            "[Gone](gone.html) [Missing](/images/dog.png) [[Nowhere]]\n",
+        );
+        let diagnostics = lint(&CheckConfig::default(), &[post]);
+        let messages = diagnostics
+            .iter()
+            .map(|(code, severity, message)| {
+                assert_eq!((code.as_str(), *severity), ("broken-link", Severity::Error));
+                message.as_str()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "Broken link to `gone.html`",
+                "Broken link to `/images/dog.png`",
+                "Broken wiki link `[[Nowhere]]`"
+            ]
+        );
+    }
+
+    #[test]
+    fn heading_jumps_are_reported() {
+        let post = post(
+            "posts/hello.md",
+            "[//]: # (title: Hello)\n",
+            "## Fine\n\n#### Jump\n",
+        );
+        assert_eq!(
+            lint(&CheckConfig::default(), &[post]),
+            [(
+                "heading-jump".to_string(),
+                Severity::Warning,
+                "Heading level jumps from h2 to h4".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn unknown_metadata_is_reported_unless_declared() {
+        let post = post(
+            "posts/hello.md",
+            "[//]: # (title: Hello)\n[//]: # (cover: cat.png)\n",
+            "Text\n",
+        );
+        assert_eq!(
+            lint(&CheckConfig::default(), std::slice::from_ref(&post)),
+            [(
+                "unknown-metadata".to_string(),
+                Severity::Warning,
+                "Unknown metadata key `cover`".to_string()
+            )]
+        );
+        let config = CheckConfig {
+            extra_metadata: vec!["cover".to_string()],
+            ..CheckConfig::default()
+        };
+        assert_eq!(lint(&config, &[post]), []);
+    }
+
+    #[test]
+    fn rule_levels_can_be_configured() {
+        let posts = [post(
+            "posts/hello.md",
+            "[//]: # (cover: cat.png)\n",
+            "## Fine\n\n#### Jump\n",
+        )];
+        let config = |levels: &[(&str, RuleLevel)]| CheckConfig {
+            rules: levels
+                .iter()
+                .map(|(code, level)| (code.to_string(), *level))
+                .collect(),
+            ..CheckConfig::default()
+        };
+        let severities = |config: &CheckConfig| {
+            lint(config, &posts)
+                .into_iter()
+                .map(|(code, severity, _)| (code, severity))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            codes(&CheckConfig::default(), &posts),
+            ["missing-title", "unknown-metadata", "heading-jump"]
+        );
+        assert_eq!(
+            severities(&config(&[
+                ("missing-title", RuleLevel::Warning),
+                ("unknown-metadata", RuleLevel::Error),
+                ("heading-jump", RuleLevel::Off),
+            ])),
+            [
+                ("missing-title".to_string(), Severity::Warning),
+                ("unknown-metadata".to_string(), Severity::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_rules_are_reported() {
+        let post = post("posts/hello.md", "[//]: # (title: Hello)\n", "Text\n");
+        let config = CheckConfig {
+            rules: HashMap::from([("no-such-rule".to_string(), RuleLevel::Off)]),
+            ..CheckConfig::default()
+        };
+        let diagnostics = lint(&config, &[post]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].1, Severity::Error);
+        assert_eq!(
+            diagnostics[0].2,
+            "Unknown rule `no-such-rule` in `[check.rules]`"
+        );
+    }
+
+    #[test]
+    fn html_tags_are_found_with_their_attributes_and_lines() {
+        let tags =
+            html_tags("<p>Intro</p>\n<IMG SRC=\"a.png\" alt='An image' hidden/>\n<a href=b.html>");
+        let names = tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["p", "img", "a"]);
+        assert_eq!(tags[1].attributes["src"], "a.png");
+        assert_eq!(tags[1].attributes["alt"], "An image");
+        assert_eq!(tags[1].attributes["hidden"], "");
+        assert_eq!(tags[1].line, 2);
+        assert_eq!(tags[2].attributes["href"], "b.html");
+        assert_eq!(tags[2].line, 3);
+    }
+
+    #[test]
+    fn html_tags_skip_comments_closing_tags_and_stray_brackets() {
+        let tags = html_tags("<!-- <img src=x> -->\n1 < 2 </div> <!doctype html> <br");
+        let names = tags.iter().map(|tag| tag.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["br"]);
+        assert!(html_tags("<!-- unterminated <img src=x>").is_empty());
+    }
+
+    #[test]
+    fn attributes_keep_their_order() {
+        let attributes = parse_attributes(r#" src="a b.png" width = 200 alt=''"#);
+        assert_eq!(
+            attributes,
+            [
+                ("src".to_string(), "a b.png".to_string()),
+                ("width".to_string(), "200".to_string()),
+                ("alt".to_string(), String::new()),
+            ]
+        );
+    }
+}
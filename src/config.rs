@@ -1,23 +1,67 @@
-use std::{borrow::Cow, fs::File, path::Path};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
 
 use serde::{Deserialize, Serialize};
 
-use crate::theme::Theme;
+use crate::{
+    check::CheckConfig, dev_server::DevConfig, hooks::HookConfig, images::ImagesConfig,
+    markdown::MarkdownConfig, plugins::PluginConfig, source::SourceTree,
+    static_server::ServeConfig, theme::manifest::ThemeConfig,
+};
 
-static CONFIG_FILE_NAME: &str = "config.toml";
+pub(crate) static CONFIG_FILE_NAME: &str = "config.toml";
 
-fn default_theme() -> Cow<'static, str> {
-    "minimal".into()
+fn default_output_dir() -> PathBuf {
+    "build".into()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     pub name: String,
     pub title: String,
-    #[serde(default = "default_theme")]
-    pub theme: Cow<'static, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Public URL the blog is deployed to, e.g. `https://example.com/blog`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Output directory, relative to the project root.
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
     #[serde(default)]
     pub __is_dev_mode: bool,
+    /// The theme, either its name or a `[theme]` table with `name` and `[theme.options]`.
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    /// Heading anchors and the table of contents.
+    #[serde(default, skip_serializing_if = "MarkdownConfig::is_default")]
+    pub markdown: MarkdownConfig,
+    /// Resized and re-encoded variants of the images in posts.
+    #[serde(default, skip_serializing_if = "ImagesConfig::is_default")]
+    pub images: ImagesConfig,
+    #[serde(default, skip_serializing_if = "CheckConfig::is_default")]
+    pub check: CheckConfig,
+    #[serde(default, skip_serializing_if = "DevConfig::is_default")]
+    pub dev: DevConfig,
+    #[serde(default, skip_serializing_if = "ServeConfig::is_default")]
+    pub serve: ServeConfig,
+    /// External executables run at each stage of the build.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookConfig>,
+    /// Sandboxed WebAssembly plugins run during the build.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<PluginConfig>,
+    /// Arbitrary values available to templates as `site.extra`.
+    #[serde(default, skip_serializing_if = "toml::value::Table::is_empty")]
+    pub extra: toml::value::Table,
+    /// Project root the config was loaded from.
+    #[serde(skip)]
+    pub root: PathBuf,
 }
 
 impl Config {
@@ -29,19 +73,67 @@ impl Config {
         Self {
             name: name.to_string(),
             title: name.to_string(),
-            theme: default_theme(),
+            description: None,
+            author: None,
+            base_url: None,
+            output_dir: default_output_dir(),
             __is_dev_mode: false,
+            theme: ThemeConfig::default(),
+            markdown: MarkdownConfig::default(),
+            images: ImagesConfig::default(),
+            check: CheckConfig::default(),
+            dev: DevConfig::default(),
+            serve: ServeConfig::default(),
+            hooks: Vec::new(),
+            plugins: Vec::new(),
+            extra: toml::value::Table::new(),
+            root: PathBuf::new(),
         }
     }
 
-    pub fn load() -> anyhow::Result<Self> {
-        let path = std::env::current_dir()?.join(CONFIG_FILE_NAME);
-        let str = std::fs::read_to_string(&path)?;
-        Ok(toml::from_str(&str)?)
+    pub fn load<P>(root: P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_source(&SourceTree::disk(root.as_ref()))
+    }
+
+    /// Reads `config.toml` from the root of a source tree.
+    pub fn from_source(source: &SourceTree) -> anyhow::Result<Self> {
+        let str = source
+            .read_to_string(CONFIG_FILE_NAME)
+            .with_context(|| format!("Unable to read config: {:?}", CONFIG_FILE_NAME))?;
+        let mut config: Self = toml::from_str(&str)?;
+        config.root = source.root().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    /// Finds the project root by walking up from `start` to the nearest `config.toml`.
+    ///
+    /// The root is returned as an absolute path, even for a relative `start` like `.` or `..`.
+    pub fn discover_root<P>(start: P) -> anyhow::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let absolute = start
+            .as_ref()
+            .canonicalize()
+            .with_context(|| format!("Unable to open `{}`", start.as_ref().display()))?;
+        absolute
+            .ancestors()
+            .find(|dir| dir.join(CONFIG_FILE_NAME).is_file())
+            .map(Path::to_path_buf)
+            .with_context(|| {
+                format!(
+                    "Unable to find `{}` in `{}` or any parent directory",
+                    CONFIG_FILE_NAME,
+                    start.as_ref().display()
+                )
+            })
     }
 
-    pub fn load_theme(&self) -> anyhow::Result<Theme> {
-        Theme::load(&self.theme)
+    pub fn build_dir(&self) -> PathBuf {
+        self.root.join(&self.output_dir)
     }
 
     pub fn to_toml_string(&self) -> anyhow::Result<String> {
@@ -61,3 +153,115 @@ impl Config {
         Ok(file.write_all(config_toml.as_bytes())?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::RuleLevel;
+
+    /// A config with every section filled, in the order `to_toml_string` writes them.
+    const FULL_CONFIG: &str = r##"
+name = "blog"
+title = "A Blog"
+description = "Notes"
+author = "Someone"
+base_url = "https://example.com/blog"
+output_dir = "public"
+
+[theme]
+name = "minimal"
+
+[theme.options]
+accent_color = "#336699"
+
+[markdown]
+anchor_links = "before"
+anchor_symbol = "¶"
+toc_depth = 2
+math = false
+
+[markdown.callouts]
+tldr = "TL;DR"
+
+[images]
+enabled = true
+widths = [320, 640]
+quality = 70
+avif = true
+sizes = "40rem"
+
+[check]
+extra_metadata = ["cover"]
+
+[check.rules]
+image-alt = "off"
+
+[dev]
+host = "0.0.0.0"
+port = 3000
+
+[serve]
+base_path = "/blog"
+
+[[hooks]]
+command = "python3"
+args = ["hooks/cdn.py"]
+events = ["bundle_assembled"]
+
+[[plugins]]
+path = "plugins/toc.wasm"
+max_memory_mb = 16
+fuel = 1000
+
+[extra]
+twitter = "@someone"
+"##;
+
+    #[test]
+    #[cfg(unix)]
+    fn relative_starts_are_discovered_as_absolute_roots() {
+        let root = std::env::temp_dir().join(format!("nail-discover-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("posts/drafts")).unwrap();
+        std::fs::write(
+            root.join(CONFIG_FILE_NAME),
+            "name = \"blog\"\ntitle = \"Blog\"\n",
+        )
+        .unwrap();
+        let root = root.canonicalize().unwrap();
+        // The same folder, relative to the working directory
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+        let mut relative = PathBuf::new();
+        for _ in cwd.components().skip(1) {
+            relative.push("..");
+        }
+        relative.push(root.strip_prefix("/").unwrap());
+        assert!(relative.is_relative());
+        assert_eq!(
+            Config::discover_root(relative.join("posts/drafts")).unwrap(),
+            root
+        );
+        assert_eq!(
+            Config::discover_root(relative.join("posts/drafts/..")).unwrap(),
+            root
+        );
+        assert!(Config::discover_root(relative.join("missing")).is_err());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn configs_with_every_section_round_trip() {
+        let config: Config = toml::from_str(FULL_CONFIG).unwrap();
+        let serialized = config.to_toml_string().unwrap();
+        let reparsed: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.to_toml_string().unwrap(), serialized);
+        assert_eq!(reparsed.check, config.check);
+        assert_eq!(reparsed.check.extra_metadata, vec!["cover"]);
+        assert_eq!(reparsed.check.rules["image-alt"], RuleLevel::Off);
+        assert_eq!(reparsed.theme, config.theme);
+        assert_eq!(reparsed.markdown, config.markdown);
+        assert_eq!(reparsed.images, config.images);
+        assert_eq!(reparsed.hooks, config.hooks);
+        assert_eq!(reparsed.plugins, config.plugins);
+        assert_eq!(reparsed.extra, config.extra);
+    }
+}
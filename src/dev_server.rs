@@ -1,114 +1,225 @@
-use std::{io::Cursor, str::FromStr, sync::Arc};
+mod live_reload;
+mod overlay;
+mod watcher;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
-use notify::Watcher;
 use parking_lot::RwLock;
-use tiny_http::{Response, Server};
+use serde::{Deserialize, Serialize};
+use tiny_http::Server;
 
+use self::watcher::{IgnoreRules, ProjectWatcher};
 use crate::{
-    build::{Bundle, Engine},
+    build::{Bundle, Engine, POSTS_DIR},
     config::Config,
+    diagnostics::{Diagnostic, Diagnostics},
+    http::{self, Reply},
+    source::SourceTree,
 };
 
+/// Settings for `nail dev`, configured under `[dev]` in `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DevConfig {
+    /// Address to listen on, e.g. `0.0.0.0` to preview from other devices on the network.
+    pub host: String,
+    /// Port to listen on. If it is taken, the next free port is used.
+    pub port: u16,
+}
+
+impl Default for DevConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".into(),
+            port: 8080,
+        }
+    }
+}
+
+impl DevConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 #[derive(Debug)]
-pub struct DevServer;
+pub struct DevServer {
+    root: PathBuf,
+    host: Option<String>,
+    port: Option<u16>,
+}
+
+/// What the dev server currently serves.
+#[derive(Debug, Default)]
+struct ServerState {
+    /// The bundle of the last successful build.
+    bundle: Bundle,
+    /// Errors of the last build, shown instead of any page until a rebuild succeeds.
+    error: Option<Diagnostics>,
+    /// Incremented on every rebuild so open pages know when to reload.
+    version: u64,
+}
+
+impl ServerState {
+    fn update(&mut self, result: anyhow::Result<Bundle>) {
+        match result {
+            Ok(bundle) => {
+                self.bundle = bundle;
+                self.error = None;
+            }
+            Err(err) => {
+                let diagnostics = match err.downcast::<Diagnostics>() {
+                    Ok(diagnostics) => diagnostics,
+                    Err(err) => Diagnostics::from_iter([Diagnostic::error(format!("{:#}", err))]),
+                };
+                eprint!("{}", diagnostics);
+                self.error = Some(diagnostics);
+            }
+        }
+        self.version += 1;
+    }
+}
 
 impl DevServer {
-    pub fn new() -> Self {
-        Self
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            host: None,
+            port: None,
+        }
+    }
+
+    /// Listens on `host` instead of the one configured under `[dev]`.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Listens on exactly `port`, failing instead of falling back if it is taken.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
     }
 
     pub fn serve(&self) -> anyhow::Result<()> {
-        let latest_bundle = {
-            let initial_bundle = Self::build_bundle()?;
-            Arc::new(RwLock::new(initial_bundle))
-        };
+        // Watcher events carry absolute paths
+        let root = self.root.canonicalize()?;
+        let mut engine = None;
+        let state = Arc::new(RwLock::new(ServerState::default()));
+        state.write().update(Self::rebuild(&root, &mut engine, &[]));
 
         // Rebuild bundle on file change
-        let watcher_bundle = latest_bundle.clone();
-        let mut watcher =
-            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
-                if res.is_err() {
-                    return;
+        let config = match &engine {
+            Some(engine) => engine.config().clone(),
+            None => Config::load(&root)?,
+        };
+        let build_dir = config.build_dir();
+        let watcher = ProjectWatcher::new(&root, IgnoreRules::new(&root, &build_dir))?;
+        let watcher_state = state.clone();
+        let watcher_root = root.clone();
+        std::thread::spawn(move || {
+            let root = watcher_root;
+            while let Some(changes) = watcher.next_changes() {
+                for path in &changes {
+                    println!(
+                        "| Changed {}",
+                        path.strip_prefix(&root).unwrap_or(path).display()
+                    );
                 }
-                if let Ok(bundle) = Self::build_bundle() {
-                    *watcher_bundle.write() = bundle;
+                let start = Instant::now();
+                let result = Self::rebuild(&root, &mut engine, &changes);
+                let succeeded = result.is_ok();
+                watcher_state.write().update(result);
+                if succeeded {
+                    println!("| Rebuilt in {}ms", start.elapsed().as_millis());
+                } else {
+                    eprintln!("| Rebuild failed after {}ms", start.elapsed().as_millis());
                 }
-            })?;
-        watcher.watch(&std::env::current_dir()?, notify::RecursiveMode::Recursive)?;
+            }
+        });
 
         // Start local development server
-        let server = Server::http("127.0.0.1:8080").unwrap();
-        println!("Development server running on http://127.0.0.1:8080.");
+        let server = self.bind(&config.dev)?;
+        println!("Development server running on:");
+        for url in http::reachable_urls(server.server_addr()) {
+            println!("  {}", url);
+        }
 
         // Handle incoming requests
-        for request in server.incoming_requests() {
-            let url = Self::map_url(request.url());
-            let bundle = latest_bundle.read();
-            let file = bundle.iter().find(|&file| file.virtual_path() == url);
-            if let Some(file) = file {
-                println!("| 200 {}", request.url());
-                request.respond(Self::make_response(
-                    file.contents(),
-                    Self::mime_from_url(file.virtual_path()),
-                    200,
-                ))?;
-            } else {
-                println!("| 404 {}", request.url());
-                let error_page = format!(
-                    "<div>Nail Development Server</div><b>File not found: <span><code>{}</code></span></b>",
-                    request.url()
-                );
-                request.respond(Self::make_response(error_page, "text/html", 404))?;
-            }
-        }
+        http::serve(&server, |path, _| Self::handle(&state.read(), &root, path));
         Ok(())
     }
 
-    fn load_config() -> anyhow::Result<Config> {
-        let mut config = Config::load()?;
-        config.__is_dev_mode = true;
-        Ok(config)
+    fn handle(state: &ServerState, root: &Path, path: &str) -> Reply {
+        if path == live_reload::VERSION_URL {
+            return Reply::new(200, "text/plain", state.version.to_string());
+        }
+        let file = http::candidates(path)
+            .iter()
+            .find_map(|candidate| state.bundle.get(candidate));
+        let mime = match file {
+            Some(file) => http::mime_type(file.path().to_str().unwrap_or_default()),
+            None => http::mime_type(path),
+        };
+        match (file, &state.error) {
+            (_, Some(diagnostics)) if mime == "text/html" => {
+                let overlay = overlay::render(diagnostics, root);
+                Reply::new(500, mime, live_reload::inject(&overlay, state.version))
+            }
+            (Some(file), _) if mime == "text/html" => Reply::new(
+                200,
+                mime,
+                live_reload::inject(file.contents(), state.version),
+            ),
+            (Some(file), _) => Reply::new(200, mime, file.bytes()),
+            (None, _) => {
+                let page = match state.bundle.get("/404.html") {
+                    Some(file) => file.contents().to_string(),
+                    None => String::from_utf8_lossy(&Reply::not_found(path).body).into_owned(),
+                };
+                Reply::new(404, "text/html", live_reload::inject(&page, state.version))
+            }
+        }
     }
 
-    fn build_bundle() -> anyhow::Result<Bundle> {
-        let config = Self::load_config()?;
-        let theme = config.load_theme()?;
-        let mut engine = Engine::new(config, theme, true)?;
-        engine.build()
+    fn bind(&self, config: &DevConfig) -> anyhow::Result<Server> {
+        let host = self.host.as_deref().unwrap_or(&config.host);
+        // Only fall back to another port if none was requested explicitly
+        match self.port {
+            Some(port) => http::bind(host, port, false),
+            None => http::bind(host, config.port, true),
+        }
     }
 
-    fn make_response(
-        content: impl AsRef<str>,
-        content_type: impl AsRef<str>,
-        status: u32,
-    ) -> Response<Cursor<Vec<u8>>> {
-        use tiny_http::Header;
-        Response::from_string(content.as_ref())
-            .with_header(
-                Header::from_str(&format!(
-                    "Content-Type: {}; charset=utf-8",
-                    content_type.as_ref()
-                ))
-                .unwrap(),
-            )
-            .with_status_code(status)
+    fn load_engine(root: &Path) -> anyhow::Result<Engine> {
+        Engine::builder(SourceTree::disk(root))
+            .dev_mode(true)
+            .load()
     }
 
-    fn mime_from_url(url: impl AsRef<str>) -> &'static str {
-        match url.as_ref() {
-            "/" => "text/html",
-            s if [".html", ".htm"].iter().any(|ext| s.ends_with(ext)) => "text/html",
-            s if [".css"].iter().any(|ext| s.ends_with(ext)) => "text/css",
-            s if [".js", ".mjs"].iter().any(|ext| s.ends_with(ext)) => "text/javascript",
-            _ => "text/plain",
+    /// Rebuilds after `changes`, re-rendering only changed posts when nothing else changed.
+    fn rebuild(
+        root: &Path,
+        engine: &mut Option<Engine>,
+        changes: &[PathBuf],
+    ) -> anyhow::Result<Bundle> {
+        // Posts are re-read on every build, anything else (config, theme, plugins) needs a new engine
+        let posts_dir = root.join(POSTS_DIR);
+        if changes.iter().any(|path| !path.starts_with(&posts_dir)) {
+            *engine = None;
         }
-    }
-
-    fn map_url(url: impl AsRef<str>) -> String {
-        match url.as_ref() {
-            "/index.html" => "/",
-            url => url,
+        let engine = match engine {
+            Some(engine) => engine,
+            None => engine.insert(Self::load_engine(root)?),
+        };
+        let bundle = engine.build()?;
+        if !engine.diagnostics().is_empty() {
+            eprint!("{}", engine.diagnostics());
         }
-        .into()
+        Ok(bundle)
     }
 }
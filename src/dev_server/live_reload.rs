@@ -0,0 +1,25 @@
+/// Polled by pages served from the dev server to find out about rebuilds.
+pub static VERSION_URL: &str = "/__nail/version";
+
+/// Injects a script that reloads the page once the build version differs from `version`.
+pub fn inject(html: &str, version: u64) -> String {
+    let script = format!(
+        r#"<script>
+(() => {{
+    const version = "{}";
+    setInterval(async () => {{
+        try {{
+            const response = await fetch("{}");
+            if (await response.text() !== version) location.reload();
+        }} catch (_) {{}}
+    }}, 500);
+}})();
+</script>
+"#,
+        version, VERSION_URL
+    );
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html[..index], script, &html[index..]),
+        None => format!("{}{}", html, script),
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use handlebars::html_escape;
+
+use crate::diagnostics::{Diagnostic, Diagnostics};
+
+/// Lines shown around the offending line of a source excerpt.
+static CONTEXT_LINES: usize = 3;
+
+static STYLE: &str = r#"
+body { margin: 0; background: #1b1b1f; color: #e8e8ea; font: 15px/1.5 system-ui, sans-serif; }
+main { max-width: 960px; margin: 0 auto; padding: 32px; }
+h1 { color: #ff6b6b; font-size: 20px; }
+section { margin: 24px 0; padding: 16px 20px; background: #26262b; border-left: 4px solid #ff6b6b; }
+section.warning { border-color: #f5c542; }
+h2 { margin: 0 0 8px; font-size: 16px; }
+.location { color: #9d9da6; font-family: monospace; }
+pre { margin: 12px 0 0; padding: 12px; overflow-x: auto; background: #111114; }
+.highlight { color: #fff; background: #4a1f22; display: inline-block; min-width: 100%; }
+"#;
+
+/// Renders a page explaining why the last build failed.
+///
+/// Source excerpts are read from the project at `root` when possible.
+pub fn render(diagnostics: &Diagnostics, root: &Path) -> String {
+    let sections = diagnostics
+        .iter()
+        .map(|diagnostic| render_diagnostic(diagnostic, root))
+        .collect::<String>();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <title>Build failed</title>
+    <style>{}</style>
+</head>
+<body>
+    <main>
+        <h1>Build failed with {} error{}</h1>
+        {}
+        <p class="location">Fix the problem and save, this page reloads automatically.</p>
+    </main>
+</body>
+</html>
+"#,
+        STYLE,
+        diagnostics.error_count(),
+        if diagnostics.error_count() == 1 {
+            ""
+        } else {
+            "s"
+        },
+        sections
+    )
+}
+
+fn render_diagnostic(diagnostic: &Diagnostic, root: &Path) -> String {
+    let title = match &diagnostic.code {
+        Some(code) => format!("{}[{}]: {}", diagnostic.severity, code, diagnostic.message),
+        None => format!("{}: {}", diagnostic.severity, diagnostic.message),
+    };
+    let location = match &diagnostic.file {
+        Some(file) => {
+            let mut location = file.display().to_string();
+            if let Some(line) = diagnostic.line {
+                location.push_str(&format!(":{}", line));
+            }
+            if let Some(column) = diagnostic.column {
+                location.push_str(&format!(":{}", column));
+            }
+            format!(r#"<div class="location">{}</div>"#, html_escape(&location))
+        }
+        None => String::new(),
+    };
+    let excerpt = match excerpt(diagnostic, root) {
+        Some(excerpt) => format!("<pre>{}</pre>", excerpt),
+        None => String::new(),
+    };
+    format!(
+        r#"<section class="{}"><h2>{}</h2>{}{}</section>"#,
+        diagnostic.severity,
+        html_escape(&title),
+        location,
+        excerpt
+    )
+}
+
+/// The lines around the diagnostic's location, with the offending line highlighted.
+fn excerpt(diagnostic: &Diagnostic, root: &Path) -> Option<String> {
+    let line = diagnostic.line.filter(|&line| line > 0)?;
+    let source = diagnostic
+        .file
+        .as_ref()
+        .and_then(|file| std::fs::read_to_string(root.join(file)).ok());
+    let (lines, context) = match &source {
+        Some(source) => (source.lines().collect::<Vec<_>>(), CONTEXT_LINES),
+        // Fall back to the single line captured when the diagnostic was created
+        None => {
+            let source_line = diagnostic.source_line.as_deref()?;
+            let mut lines = vec![""; line - 1];
+            lines.push(source_line);
+            (lines, 0)
+        }
+    };
+    let first = line.saturating_sub(context).max(1);
+    let last = (line + context).min(lines.len());
+    if line > last {
+        return None;
+    }
+    let gutter = last.to_string().len();
+    let mut excerpt = String::new();
+    for number in first..=last {
+        let text = html_escape(lines.get(number - 1).copied().unwrap_or_default());
+        if number == line {
+            excerpt.push_str(&format!(
+                "<span class=\"highlight\">{:>width$} | {}</span>\n",
+                number,
+                text,
+                width = gutter
+            ));
+            if let Some(column) = diagnostic.column {
+                excerpt.push_str(&format!(
+                    "{:>width$} | {}^\n",
+                    "",
+                    " ".repeat(column.saturating_sub(1)),
+                    width = gutter
+                ));
+            }
+        } else {
+            excerpt.push_str(&format!("{:>width$} | {}\n", number, text, width = gutter));
+        }
+    }
+    Some(excerpt)
+}
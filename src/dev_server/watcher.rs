@@ -0,0 +1,157 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long the project has to be quiet before a batch of changes is reported.
+static DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Files and directories that never affect a build.
+///
+/// `4913` is the file Vim creates and deletes right away to test whether it may write to a
+/// directory, before saving a file with `backupcopy=auto`.
+static IGNORED_NAMES: &[&str] = &[".git", ".cache", ".cache.toml", ".DS_Store", "4913"];
+
+/// Decides which changed files are irrelevant to a build.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    root: PathBuf,
+    build_dir: PathBuf,
+    gitignore: Gitignore,
+}
+
+impl IgnoreRules {
+    /// Ignores the output directory, VCS and cache files, editor swap files and
+    /// everything listed in the project's `.gitignore`.
+    pub fn new(root: &Path, build_dir: &Path) -> Self {
+        let mut builder = GitignoreBuilder::new(root);
+        // A missing or malformed `.gitignore` only means fewer files are ignored
+        let _ = builder.add(root.join(".gitignore"));
+        Self {
+            root: root.to_path_buf(),
+            build_dir: build_dir.to_path_buf(),
+            gitignore: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+        }
+    }
+
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => return true,
+        };
+        if path.starts_with(&self.build_dir) {
+            return true;
+        }
+        let has_ignored_component = relative.components().any(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .is_some_and(|name| IGNORED_NAMES.contains(&name))
+        });
+        if has_ignored_component {
+            return true;
+        }
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            let is_swap_file = [".swp", ".swo", ".swx", "~"]
+                .iter()
+                .any(|suffix| name.ends_with(suffix))
+                || name.starts_with(".#")
+                || (name.starts_with('#') && name.ends_with('#'));
+            if is_swap_file {
+                return true;
+            }
+        }
+        self.gitignore
+            .matched_path_or_any_parents(relative, path.is_dir())
+            .is_ignore()
+    }
+}
+
+/// Watches a project directory and reports debounced batches of relevant changes.
+pub struct ProjectWatcher {
+    // Dropping the watcher stops the events
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    rules: IgnoreRules,
+}
+
+impl ProjectWatcher {
+    pub fn new(root: &Path, rules: IgnoreRules) -> anyhow::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            rules,
+        })
+    }
+
+    /// Blocks until relevant files changed and no further changes happened for a moment.
+    ///
+    /// Returns the changed paths, or `None` once the watcher stopped.
+    pub fn next_changes(&self) -> Option<Vec<PathBuf>> {
+        let mut changes = Vec::new();
+        while changes.is_empty() {
+            let event = self.events.recv().ok()?;
+            self.collect(event, &mut changes);
+        }
+        loop {
+            match self.events.recv_timeout(DEBOUNCE) {
+                Ok(event) => self.collect(event, &mut changes),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+        changes.sort();
+        changes.dedup();
+        Some(changes)
+    }
+
+    fn collect(&self, event: notify::Result<notify::Event>, changes: &mut Vec<PathBuf>) {
+        match event {
+            // Reading files, e.g. during a build, doesn't change anything
+            Ok(event) if event.kind.is_access() => (),
+            Ok(event) => changes.extend(
+                event
+                    .paths
+                    .into_iter()
+                    .filter(|path| !self.rules.is_ignored(path)),
+            ),
+            Err(err) => eprintln!("| Unable to watch files: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn editor_and_vcs_files_are_ignored() {
+        let root = Path::new("/project");
+        let rules = IgnoreRules::new(root, &root.join("public"));
+        for path in [
+            "public/index.html",
+            ".git/index",
+            "posts/4913",
+            "posts/hello.md.swp",
+            "posts/hello.md~",
+            "posts/.#hello.md",
+            "posts/#hello.md#",
+            ".cache.toml",
+            ".cache/images/photo-0123456789abcdef-480.webp",
+        ] {
+            assert!(rules.is_ignored(&root.join(path)), "watched `{}`", path);
+        }
+        assert!(!rules.is_ignored(&root.join("posts/hello.md")));
+        assert!(!rules.is_ignored(&root.join("posts/49130.md")));
+        assert!(rules.is_ignored(Path::new("/elsewhere/hello.md")));
+    }
+}
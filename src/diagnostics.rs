@@ -0,0 +1,335 @@
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A single problem found while building, tied to a source location when one is known.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub template: Option<String>,
+    #[serde(skip)]
+    pub source_line: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl ToString) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.to_string(),
+            file: None,
+            line: None,
+            column: None,
+            template: None,
+            source_line: None,
+        }
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl ToString) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    /// Tags the diagnostic with the rule that produced it, e.g. `broken-link`.
+    pub fn with_code(mut self, code: impl ToString) -> Self {
+        self.code = Some(code.to_string());
+        self
+    }
+
+    pub fn with_file(mut self, file: impl AsRef<Path>) -> Self {
+        self.file = Some(file.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn at(mut self, line: Option<usize>, column: Option<usize>) -> Self {
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    pub fn in_template(mut self, name: impl ToString) -> Self {
+        self.template = Some(name.to_string());
+        self
+    }
+
+    /// Attaches the offending line of `source` so the report can show an excerpt.
+    pub fn with_source(mut self, source: impl AsRef<str>) -> Self {
+        self.source_line = self
+            .line
+            .and_then(|line| source.as_ref().lines().nth(line.saturating_sub(1)))
+            .map(|line| line.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn from_render_error(err: &handlebars::RenderError) -> Self {
+        let mut diagnostic = Self::error(&err.desc).at(err.line_no, err.column_no);
+        if let Some(name) = &err.template_name {
+            diagnostic = diagnostic.in_template(name);
+        }
+        // Parse errors surface as a render error wrapping the actual template error
+        if let Some(cause) = std::error::Error::source(err)
+            .and_then(|cause| cause.downcast_ref::<handlebars::TemplateError>())
+        {
+            let template_diagnostic = Self::from_template_error(cause);
+            diagnostic.message = template_diagnostic.message;
+            diagnostic.line = template_diagnostic.line.or(diagnostic.line);
+            diagnostic.column = template_diagnostic.column.or(diagnostic.column);
+        }
+        diagnostic
+    }
+
+    pub fn from_jinja_error(err: &minijinja::Error) -> Self {
+        let message = match err.detail() {
+            Some(detail) => format!("{}: {}", err.kind(), detail),
+            None => err.kind().to_string(),
+        };
+        let mut diagnostic = Self::error(message).at(err.line(), None);
+        if let Some(name) = err.name() {
+            diagnostic = diagnostic.in_template(name);
+        }
+        diagnostic
+    }
+
+    pub fn from_template_error(err: &handlebars::TemplateError) -> Self {
+        let mut diagnostic = Self::error(err.reason()).at(err.line_no, err.column_no);
+        if let Some(name) = &err.template_name {
+            diagnostic = diagnostic.in_template(name);
+        }
+        diagnostic
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => writeln!(f, "{}[{}]: {}", self.severity, code, self.message)?,
+            None => writeln!(f, "{}: {}", self.severity, self.message)?,
+        }
+        if let Some(file) = &self.file {
+            write!(f, "  --> {}", file.display())?;
+            match (self.line, self.column) {
+                (Some(line), Some(column)) => write!(f, ":{}:{}", line, column)?,
+                (Some(line), None) => write!(f, ":{}", line)?,
+                _ => (),
+            }
+            if let Some(template) = &self.template {
+                write!(f, " (template `{}`)", template)?;
+            }
+            writeln!(f)?;
+        } else if let Some(template) = &self.template {
+            writeln!(f, "  --> template `{}`", template)?;
+        }
+        if let (Some(line), Some(source_line)) = (self.line, &self.source_line) {
+            let gutter = " ".repeat(line.to_string().len());
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", line, source_line)?;
+            match self.column {
+                Some(column) => {
+                    writeln!(f, "{} | {}^", gutter, " ".repeat(column.saturating_sub(1)))?
+                }
+                None => writeln!(f, "{} |", gutter)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Diagnostics collected over a whole build, reported together at the end.
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(transparent)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.items.push(diagnostic)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(Diagnostic::is_error)
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.items.iter().filter(|d| d.is_error()).count()
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.items.len() - self.error_count()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.items.iter()
+    }
+
+    /// Returns `value` if no errors were collected, otherwise the diagnostics themselves.
+    pub fn into_result<T>(self, value: T) -> Result<T, Diagnostics> {
+        if self.has_errors() {
+            Err(self)
+        } else {
+            Ok(value)
+        }
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.items {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        let (errors, warnings) = (self.error_count(), self.warning_count());
+        match (errors, warnings) {
+            (0, 0) => Ok(()),
+            (0, warnings) => writeln!(
+                f,
+                "warning: {} warning{} emitted",
+                warnings,
+                plural(warnings)
+            ),
+            (errors, 0) => writeln!(
+                f,
+                "error: aborting due to {} previous error{}",
+                errors,
+                plural(errors)
+            ),
+            (errors, warnings) => writeln!(
+                f,
+                "error: aborting due to {} previous error{}; {} warning{} emitted",
+                errors,
+                plural(errors),
+                warnings,
+                plural(warnings)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl FromIterator<Diagnostic> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Diagnostic>>(iter: I) -> Self {
+        Self {
+            items: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_point_at_the_source_line() {
+        let diagnostic = Diagnostic::error("Broken link to `gone.html`")
+            .with_code("broken-link")
+            .with_file("posts/hello.md")
+            .at(Some(3), Some(7))
+            .with_source("# Hello\n\nSee [Gone](gone.html)\n");
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[broken-link]: Broken link to `gone.html`\n  \
+             --> posts/hello.md:3:7\n  \
+             |\n\
+             3 | See [Gone](gone.html)\n  \
+             |       ^\n"
+        );
+    }
+
+    #[test]
+    fn reports_without_a_column_have_no_caret() {
+        let diagnostic = Diagnostic::warning("Unknown metadata key `cover`")
+            .with_file("posts/hello.md")
+            .at(Some(1), None)
+            .with_source("[//]: # (cover: cat.png)\n");
+        assert_eq!(
+            diagnostic.to_string(),
+            "warning: Unknown metadata key `cover`\n  \
+             --> posts/hello.md:1\n  \
+             |\n\
+             1 | [//]: # (cover: cat.png)\n  \
+             |\n"
+        );
+        let template = Diagnostic::error("Unknown helper").in_template("base");
+        assert_eq!(
+            template.to_string(),
+            "error: Unknown helper\n  --> template `base`\n"
+        );
+    }
+
+    #[test]
+    fn reports_end_with_a_summary() {
+        let diagnostics = [Diagnostic::error("One"), Diagnostic::warning("Two")]
+            .into_iter()
+            .collect::<Diagnostics>();
+        assert_eq!(
+            diagnostics.to_string(),
+            "error: One\n\nwarning: Two\n\n\
+             error: aborting due to 1 previous error; 1 warning emitted\n"
+        );
+        assert_eq!(Diagnostics::new().to_string(), "");
+    }
+
+    #[test]
+    fn only_errors_fail_the_result() {
+        assert_eq!(Diagnostics::new().into_result(1).unwrap(), 1);
+        let warnings = [Diagnostic::warning("Careful")]
+            .into_iter()
+            .collect::<Diagnostics>();
+        assert_eq!(warnings.into_result(2).unwrap(), 2);
+        let errors = [Diagnostic::warning("Careful"), Diagnostic::error("Broken")]
+            .into_iter()
+            .collect::<Diagnostics>();
+        let errors = errors.into_result(3).unwrap_err();
+        assert_eq!(errors.error_count(), 1);
+        assert_eq!(errors.warning_count(), 1);
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    build::{Bundle, Post},
+    theme::{RenderData, Theme},
+};
+
+/// Extension points invoked by [`Engine::build`](crate::Engine::build).
+///
+/// Every method defaults to doing nothing, so hooks only implement the stages they care about.
+pub trait Hook: Send + Sync {
+    /// Name used when reporting errors.
+    fn name(&self) -> &str;
+
+    /// Called once after all posts were read, before anything is rendered.
+    fn posts_gathered(&self, _posts: &mut Vec<Post>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called once before any page is rendered, e.g. to register template helpers.
+    fn prepare_theme(&self, _theme: &mut Theme) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called for every post after its contents were converted to HTML.
+    fn post_converted(&self, _post: &Post, _html: &mut String) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called before a page is rendered with the theme's templates.
+    fn before_render(&self, _data: &mut RenderData) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called once with the finished bundle, e.g. to add generated pages.
+    fn bundle_assembled(&self, _bundle: &mut Bundle) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The hooks registered with an engine, run in registration order.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Box<dyn Hook>>,
+}
+
+impl Hooks {
+    pub fn push(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Hook> {
+        self.hooks.iter().map(|hook| hook.as_ref())
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.hooks.iter().map(|hook| hook.name()))
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    PostsGathered,
+    PostConverted,
+    BeforeRender,
+    BundleAssembled,
+}
+
+/// An external executable configured under `[[hooks]]` in `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HookConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Events the executable is invoked for; all events if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<HookEvent>>,
+}
+
+#[derive(Serialize)]
+struct HookRequest<'a, T> {
+    event: HookEvent,
+    payload: &'a T,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PostConvertedPayload {
+    post: Post,
+    html: String,
+}
+
+/// Runs an external executable for each event it subscribed to.
+///
+/// The executable receives `{"event": ..., "payload": ...}` on stdin and
+/// answers with the (possibly modified) payload on stdout. Empty output
+/// leaves the payload unchanged, a non-zero exit status fails the build.
+#[derive(Debug)]
+pub struct ExternalHook {
+    config: HookConfig,
+    working_dir: Option<PathBuf>,
+}
+
+impl ExternalHook {
+    pub fn new(config: HookConfig, working_dir: Option<&Path>) -> Self {
+        Self {
+            config,
+            working_dir: working_dir.map(Path::to_path_buf),
+        }
+    }
+
+    fn subscribes_to(&self, event: HookEvent) -> bool {
+        self.config
+            .events
+            .as_ref()
+            .is_none_or(|events| events.contains(&event))
+    }
+
+    fn call<T>(&self, event: HookEvent, payload: &mut T) -> anyhow::Result<()>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        if !self.subscribes_to(event) {
+            return Ok(());
+        }
+        let program = match &self.working_dir {
+            // Resolve relative paths like `./hooks/cdn` against the project root
+            Some(dir) if self.config.command.contains(['/', '\\']) => {
+                dir.join(&self.config.command)
+            }
+            _ => PathBuf::from(&self.config.command),
+        };
+        let mut command = Command::new(&program);
+        command
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = &self.working_dir {
+            command.current_dir(dir);
+        }
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Unable to start hook `{}`", self.config.command))?;
+        let request = serde_json::to_vec(&HookRequest { event, payload })?;
+        // Written from another thread, as a hook streaming its output back would otherwise
+        // block once both pipes are full
+        let writer = child
+            .stdin
+            .take()
+            .map(|mut stdin| std::thread::spawn(move || stdin.write_all(&request)));
+        let output = child.wait_with_output()?;
+        if let Some(writer) = writer {
+            // A hook may exit without reading its input, which is up to the hook
+            match writer.join().expect("hook writer panicked") {
+                Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(err.into()),
+                _ => (),
+            }
+        }
+        if !output.status.success() {
+            bail!(
+                "Hook `{}` failed with {}: {}",
+                self.config.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        if !output.stdout.iter().all(u8::is_ascii_whitespace) {
+            *payload = serde_json::from_slice(&output.stdout)
+                .with_context(|| format!("Hook `{}` returned invalid JSON", self.config.command))?;
+        }
+        Ok(())
+    }
+}
+
+impl Hook for ExternalHook {
+    fn name(&self) -> &str {
+        &self.config.command
+    }
+
+    fn posts_gathered(&self, posts: &mut Vec<Post>) -> anyhow::Result<()> {
+        self.call(HookEvent::PostsGathered, posts)
+    }
+
+    fn post_converted(&self, post: &Post, html: &mut String) -> anyhow::Result<()> {
+        if !self.subscribes_to(HookEvent::PostConverted) {
+            return Ok(());
+        }
+        let mut payload = PostConvertedPayload {
+            post: post.clone(),
+            html: std::mem::take(html),
+        };
+        let result = self.call(HookEvent::PostConverted, &mut payload);
+        *html = payload.html;
+        result
+    }
+
+    fn before_render(&self, data: &mut RenderData) -> anyhow::Result<()> {
+        self.call(HookEvent::BeforeRender, data)
+    }
+
+    fn bundle_assembled(&self, bundle: &mut Bundle) -> anyhow::Result<()> {
+        // Binary files like processed images don't survive the trip through JSON
+        let binary_files = bundle.take_binary_files();
+        let result = self.call(HookEvent::BundleAssembled, bundle);
+        binary_files
+            .into_iter()
+            .for_each(|file| bundle.add_file(file));
+        result
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn hook(command: &str, args: &[&str]) -> ExternalHook {
+        ExternalHook::new(
+            HookConfig {
+                command: command.to_string(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                events: None,
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn large_payloads_are_streamed_back_without_blocking() {
+        // Far more than fits into the pipe buffers, echoed back as it is read
+        let mut payload = serde_json::Value::String("x".repeat(4 << 20));
+        hook("cat", &[])
+            .call(HookEvent::BundleAssembled, &mut payload)
+            .unwrap();
+        assert_eq!(payload["event"], "bundle_assembled");
+        assert_eq!(payload["payload"].as_str().map(str::len), Some(4 << 20));
+    }
+
+    #[test]
+    fn hooks_may_ignore_their_input() {
+        let mut payload = serde_json::Value::String("x".repeat(4 << 20));
+        hook("true", &[])
+            .call(HookEvent::BundleAssembled, &mut payload)
+            .unwrap();
+        assert_eq!(payload.as_str().map(str::len), Some(4 << 20));
+    }
+
+    #[test]
+    fn failing_hooks_report_their_stderr() {
+        let mut payload = serde_json::Value::Null;
+        let err = hook("sh", &["-c", "echo broken >&2; exit 3"])
+            .call(HookEvent::PostsGathered, &mut payload)
+            .unwrap_err();
+        assert!(err.to_string().contains("broken"), "{}", err);
+    }
+}
@@ -0,0 +1,427 @@
+//! The HTTP layer shared by `nail dev` and `nail serve`.
+
+use std::{
+    io::{self, Cursor},
+    net::{IpAddr, SocketAddr, TcpListener},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::cache::HashCache;
+
+/// How many ports after the requested one are tried when it is taken.
+static PORT_ATTEMPTS: u16 = 10;
+
+/// A response produced by a request handler, before conditional requests are resolved.
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl Reply {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn not_found(path: &str) -> Self {
+        let page = format!(
+            "<div>Nail</div><b>File not found: <span><code>{}</code></span></b>",
+            handlebars::html_escape(path)
+        );
+        Self::new(404, "text/html", page)
+    }
+}
+
+/// Listens on `host` and `port`, trying the next few ports if `fallback` is set and it is taken.
+pub fn bind(host: &str, port: u16, fallback: bool) -> anyhow::Result<Server> {
+    let ip = match host {
+        "localhost" => IpAddr::from([127, 0, 0, 1]),
+        host => host
+            .parse::<IpAddr>()
+            .with_context(|| format!("Invalid host `{}`", host))?,
+    };
+    let last_port = match fallback {
+        true => port.saturating_add(PORT_ATTEMPTS - 1),
+        false => port,
+    };
+    let mut port = port;
+    loop {
+        match TcpListener::bind(SocketAddr::new(ip, port)) {
+            Ok(listener) => {
+                return Server::from_listener(listener, None)
+                    .map_err(|err| anyhow::anyhow!("{}", err))
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && port < last_port => {
+                println!("| Port {} is in use, trying {}", port, port + 1);
+                port += 1;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Unable to listen on {}:{}", ip, port))
+            }
+        }
+    }
+}
+
+/// URLs the server can be reached at, including all network interfaces if it listens on them.
+pub fn reachable_urls(addr: SocketAddr) -> Vec<String> {
+    let port = addr.port();
+    if !addr.ip().is_unspecified() {
+        return vec![format!("http://{}", addr)];
+    }
+    let mut urls = vec![format!("http://localhost:{}", port)];
+    let interfaces = if_addrs::get_if_addrs().unwrap_or_default();
+    for interface in interfaces
+        .iter()
+        .filter(|interface| !interface.is_loopback())
+    {
+        match interface.ip() {
+            IpAddr::V4(ip) => urls.push(format!("http://{}:{}", ip, port)),
+            // Binding `::` usually accepts IPv4 connections as well, but not the other way around
+            IpAddr::V6(ip) if addr.is_ipv6() => urls.push(format!("http://[{}]:{}", ip, port)),
+            IpAddr::V6(_) => (),
+        }
+    }
+    urls
+}
+
+/// Handles requests with one worker thread per available core until the server stops.
+///
+/// `handler` receives the normalized request path, see [`normalize_path`]. Paths starting
+/// with `/__nail/` are internal and not logged.
+pub fn serve<F>(server: &Server, handler: F)
+where
+    F: Fn(&str, &Request) -> Reply + Sync,
+{
+    let workers = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(4);
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    let url = request.url().to_string();
+                    let reply = match (request.method(), normalize_path(&url)) {
+                        (Method::Get | Method::Head, Some(path)) => handler(&path, &request),
+                        (Method::Get | Method::Head, None) => {
+                            Reply::new(400, "text/plain", "Bad request")
+                        }
+                        _ => Reply::new(405, "text/plain", "Method not allowed")
+                            .with_header("Allow", "GET, HEAD"),
+                    };
+                    let response = finalize(reply, &request);
+                    if !url.starts_with("/__nail/") {
+                        println!(
+                            "| {} {} {}",
+                            response.status_code().0,
+                            request.method(),
+                            url
+                        );
+                    }
+                    if let Err(err) = request.respond(response) {
+                        eprintln!("| Unable to respond to {}: {}", url, err);
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Adds content headers and an ETag, answering with `304 Not Modified` if the client's copy
+/// is still current. Bodies of `HEAD` requests are dropped by `tiny_http`.
+///
+/// Replies with a header value that can't be sent become `500 Internal Server Error`.
+fn finalize(reply: Reply, request: &Request) -> Response<Cursor<Vec<u8>>> {
+    let headers = std::iter::once(header(
+        "Content-Type",
+        content_type_header(reply.content_type),
+    ))
+    .chain(
+        reply
+            .headers
+            .iter()
+            .map(|(name, value)| header(name, value)),
+    )
+    .collect::<Option<Vec<_>>>();
+    let mut headers = match headers {
+        Some(headers) => headers,
+        None => {
+            let reply = Reply::new(500, "text/plain", "Invalid response header");
+            return finalize(reply, request);
+        }
+    };
+    let mut status = reply.status;
+    let mut body = reply.body;
+    if status == 200 {
+        let etag = format!("\"{:08x}-{:x}\"", HashCache::hash_bytes(&body), body.len());
+        headers.extend(header("Cache-Control", "no-cache"));
+        if is_fresh(request, &etag) {
+            status = 304;
+            body = Vec::new();
+        }
+        headers.extend(header("ETag", etag));
+    }
+    let mut response = Response::from_data(body).with_status_code(status);
+    for header in headers {
+        response.add_header(header);
+    }
+    response
+}
+
+/// Whether the client's `If-None-Match` header lists `etag`.
+fn is_fresh(request: &Request, etag: &str) -> bool {
+    request
+        .headers()
+        .iter()
+        .filter(|header| header.field.equiv("If-None-Match"))
+        .flat_map(|header| header.value.as_str().split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// A response header, or `None` if `value` isn't valid in one, e.g. because it isn't ASCII.
+fn header(name: &str, value: impl AsRef<str>) -> Option<Header> {
+    let value = value.as_ref();
+    if !value
+        .bytes()
+        .all(|byte| byte == b'\t' || (b' '..=b'~').contains(&byte))
+    {
+        return None;
+    }
+    Header::from_str(&format!("{}: {}", name, value)).ok()
+}
+
+/// Whether the client's `Accept-Encoding` header allows `encoding`, e.g. `gzip`.
+pub fn accepts_encoding(request: &Request, encoding: &str) -> bool {
+    request
+        .headers()
+        .iter()
+        .filter(|header| header.field.equiv("Accept-Encoding"))
+        .flat_map(|header| header.value.as_str().split(','))
+        .any(|value| {
+            let mut parts = value.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let disabled = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case(encoding) || name == "*") && !disabled
+        })
+}
+
+/// Turns a request URL into a clean absolute path.
+///
+/// Drops the query string and fragment, percent-decodes and collapses repeated slashes.
+/// Returns `None` for paths that try to escape the root with `..`, aren't valid UTF-8 or
+/// contain control characters such as line breaks.
+pub fn normalize_path(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let decoded = percent_decode(path)?;
+    if decoded.contains(|c: char| c == '\\' || c.is_control()) {
+        return None;
+    }
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => return None,
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if decoded.ends_with('/') && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// Percent-encodes a path for use in a URL or header, such as `Location`.
+///
+/// Only unreserved characters and `/` are kept as they are.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub(crate) fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = input.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Paths a normalized request path may be served from, most specific first.
+///
+/// Directories resolve to their `index.html`, which the dev server serves under `/` for the root.
+pub fn candidates(path: &str) -> Vec<String> {
+    if path == "/" || path == "/index.html" {
+        return vec!["/".to_string(), "/index.html".to_string()];
+    }
+    match path.strip_suffix('/') {
+        Some(dir) => vec![format!("{}/index.html", dir)],
+        // Pretty URLs like `/posts/hello` may refer to `hello.html` or `hello/index.html`
+        None => vec![
+            path.to_string(),
+            format!("{}.html", path),
+            format!("{}/index.html", path),
+        ],
+    }
+}
+
+/// The MIME type for a path, based on its extension.
+pub fn mime_type(path: &str) -> &'static str {
+    if path.ends_with('/') {
+        return "text/html";
+    }
+    let extension = path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js" | "mjs") => "text/javascript",
+        Some("json" | "map") => "application/json",
+        Some("webmanifest") => "application/manifest+json",
+        Some("xml") => "application/xml",
+        Some("rss") => "application/rss+xml",
+        Some("atom") => "application/atom+xml",
+        Some("txt") => "text/plain",
+        Some("md" | "markdown") => "text/markdown",
+        Some("csv") => "text/csv",
+        Some("toml") => "application/toml",
+        Some("yaml" | "yml") => "application/yaml",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("br") => "application/x-brotli",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("bmp") => "image/bmp",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        // Extensionless paths are pretty URLs of pages
+        None => "text/html",
+        Some(_) => "application/octet-stream",
+    }
+}
+
+fn content_type_header(mime: &str) -> String {
+    let is_text = mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/xml"
+                | "application/rss+xml"
+                | "application/atom+xml"
+                | "application/manifest+json"
+                | "application/toml"
+                | "application/yaml"
+                | "image/svg+xml"
+        );
+    match is_text {
+        true => format!("{}; charset=utf-8", mime),
+        false => mime.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+        assert_eq!(
+            normalize_path("//posts//./a.html?x=1#top").as_deref(),
+            Some("/posts/a.html")
+        );
+        assert_eq!(normalize_path("/posts/").as_deref(), Some("/posts/"));
+        assert_eq!(normalize_path("/caf%C3%A9").as_deref(), Some("/café"));
+    }
+
+    #[test]
+    fn paths_escaping_the_root_or_with_control_characters_are_rejected() {
+        for url in [
+            "/../secret",
+            "/a/%2e%2e/b",
+            "/a%5Cb",
+            "/a%00",
+            "/a%0D%0ALocation:%20x",
+            "/%ff",
+            "/%zz",
+        ] {
+            assert_eq!(normalize_path(url), None, "accepted `{}`", url);
+        }
+    }
+
+    #[test]
+    fn percent_decoding_requires_two_hex_digits() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("100%"), None);
+        assert_eq!(percent_decode("%4"), None);
+    }
+
+    #[test]
+    fn percent_encoding_round_trips() {
+        let path = "/café/a b?#%";
+        let encoded = percent_encode_path(path);
+        assert_eq!(encoded, "/caf%C3%A9/a%20b%3F%23%25");
+        assert_eq!(percent_decode(&encoded).as_deref(), Some(path));
+    }
+
+    #[test]
+    fn headers_must_be_printable_ascii() {
+        assert!(header("Location", "/caf%C3%A9/").is_some());
+        assert!(header("Location", "/café/").is_none());
+        assert!(header("Location", "/a\r\nSet-Cookie: x").is_none());
+    }
+}
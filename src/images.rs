@@ -0,0 +1,960 @@
+//! Responsive images, resized and re-encoded from the photos referenced in posts.
+//!
+//! Once enabled under `[images]` in `config.toml`, every local JPEG, PNG or WebP image in a post
+//! is resized to the configured widths, without upscaling, and converted to WebP, plus AVIF if
+//! enabled. Encoding the decoded pixels leaves out EXIF and other metadata, after rotating photos
+//! as it says. The `<img>` then lists the variants in `srcset`, inside a `<picture>` offering
+//! AVIF if enabled.
+//!
+//! Processed images are cached in `.cache/images/`, keyed by a 64-bit hash of their path,
+//! contents and the settings, so rebuilds only encode new or changed images. The hashes of the
+//! sources are kept there as well and only recomputed once a file's size or modification time
+//! changes, so rebuilds don't read unchanged images at all. Incremental builds remove the
+//! variants no post uses anymore from the cache and output folders.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Cursor,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Context};
+use image::{
+    codecs::avif::AvifEncoder, imageops::FilterType, DynamicImage, ImageDecoder, ImageReader,
+};
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{
+    build::{BuildFile, Post},
+    cache::HashCache,
+    check::{self, parse_attributes},
+    diagnostics::Diagnostic,
+    http::percent_decode,
+    markdown::{self, slugify},
+    source::SourceTree,
+};
+
+/// Where variants are published, relative to the output directory.
+static IMAGES_DIR: &str = "images";
+
+/// Where variants are cached, relative to the project root.
+static CACHE_DIR: &str = ".cache/images";
+
+/// The hashes of source images in the cache folder, see [`FileHash`].
+static HASHES_FILE_NAME: &str = "hashes.json";
+
+/// The images of each post's page in the cache folder, see [`ImageProcessor::remove_stale`].
+static PAGES_FILE_NAME: &str = "pages.json";
+
+static EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// The `[images]` section of `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Whether to process images, which are left as they are otherwise. Off by default, as
+    /// it re-encodes every image of existing blogs.
+    pub enabled: bool,
+    /// Widths in pixels to resize images to. Narrower images are only re-encoded.
+    #[serde(deserialize_with = "deserialize_widths")]
+    pub widths: Vec<u32>,
+    /// Encoding quality from 1 to 100.
+    pub quality: u8,
+    /// Whether to offer AVIF besides WebP, which is smaller but much slower to encode.
+    pub avif: bool,
+    /// How wide images are displayed, as the `sizes` attribute, e.g. `(min-width: 40rem) 40rem`.
+    pub sizes: String,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            widths: vec![480, 960, 1600],
+            quality: 80,
+            avif: false,
+            sizes: "100vw".to_string(),
+        }
+    }
+}
+
+impl ImagesConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+fn deserialize_widths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u32>, D::Error> {
+    let widths = Vec::<u32>::deserialize(deserializer)?;
+    if widths.contains(&0) {
+        return Err(de::Error::custom("image widths must be greater than 0"));
+    }
+    Ok(widths)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Webp,
+    Avif,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Webp => "webp",
+            Format::Avif => "avif",
+        }
+    }
+}
+
+/// An image encoded at one width.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Variant {
+    format: Format,
+    width: u32,
+    height: u32,
+    /// Name of the file in the output and cache folders.
+    file_name: String,
+    #[serde(skip)]
+    bytes: Arc<[u8]>,
+}
+
+/// The variants of an image, narrowest first.
+#[derive(Debug)]
+struct ProcessedImage {
+    variants: Vec<Variant>,
+}
+
+impl ProcessedImage {
+    fn url(variant: &Variant, base_path: &str) -> String {
+        format!("{}/{}/{}", base_path, IMAGES_DIR, variant.file_name)
+    }
+
+    fn srcset(&self, format: Format, base_path: &str) -> String {
+        self.variants
+            .iter()
+            .filter(|variant| variant.format == format)
+            .map(|variant| format!("{} {}w", Self::url(variant, base_path), variant.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// An `<img>` with the `attributes` of the original, wrapped in a `<picture>` if there are
+    /// AVIF variants.
+    ///
+    /// Dimensions, `sizes` and `loading` given by the author are kept.
+    fn html(&self, attributes: &[(String, String)], base_path: &str, sizes: &str) -> String {
+        let get = |name: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let sizes = get("sizes").unwrap_or(sizes);
+        let largest = self
+            .variants
+            .iter()
+            .rfind(|variant| variant.format == Format::Webp)
+            .expect("every image has a WebP variant");
+        let mut img = String::from("<img");
+        let mut push = |name: &str, value: &str| {
+            img.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;")));
+        };
+        for (name, value) in attributes {
+            match name.as_str() {
+                "src" => {
+                    push("src", &Self::url(largest, base_path));
+                    push("srcset", &self.srcset(Format::Webp, base_path));
+                    push("sizes", sizes);
+                }
+                "sizes" => (),
+                _ => push(name, value),
+            }
+        }
+        if get("width").is_none() && get("height").is_none() {
+            push("width", &largest.width.to_string());
+            push("height", &largest.height.to_string());
+        }
+        if get("loading").is_none() {
+            push("loading", "lazy");
+        }
+        if get("decoding").is_none() {
+            push("decoding", "async");
+        }
+        img.push_str(" />");
+        match self
+            .variants
+            .iter()
+            .any(|variant| variant.format == Format::Avif)
+        {
+            true => format!(
+                "<picture><source type=\"image/avif\" srcset=\"{}\" sizes=\"{}\" />{}</picture>",
+                self.srcset(Format::Avif, base_path),
+                sizes.replace('"', "&quot;"),
+                img
+            ),
+            false => img,
+        }
+    }
+
+    fn files(&self) -> impl Iterator<Item = BuildFile> + '_ {
+        self.variants.iter().map(|variant| {
+            BuildFile::binary(
+                Path::new(IMAGES_DIR).join(&variant.file_name),
+                format!("/{}/{}", IMAGES_DIR, variant.file_name),
+                variant.bytes.clone(),
+            )
+        })
+    }
+}
+
+/// The hash of a source image's contents, valid while its size and modification time stay the
+/// same.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct FileHash {
+    modified: SystemTime,
+    size: u64,
+    hash: u64,
+}
+
+/// Processes the images of posts, keeping the results for later builds.
+#[derive(Debug)]
+pub struct ImageProcessor {
+    config: ImagesConfig,
+    /// The cache folder, if the project is on disk.
+    cache_dir: Option<PathBuf>,
+    processed: HashMap<u64, Arc<ProcessedImage>>,
+    file_hashes: HashMap<PathBuf, FileHash>,
+    /// The cache keys of the images in each post's page, as it was last rendered.
+    page_images: HashMap<PathBuf, BTreeSet<u64>>,
+    /// The cache keys of the images posts referred to since stale variants were last removed.
+    referenced: HashSet<u64>,
+    /// Whether `file_hashes` or `page_images` changed since they were read from or saved to the
+    /// cache folder.
+    changed: bool,
+}
+
+impl ImageProcessor {
+    /// Creates a processor caching images below `root`, or only in memory without one.
+    pub fn new(config: &ImagesConfig, root: Option<&Path>) -> Self {
+        let cache_dir = root.map(|root| root.join(CACHE_DIR));
+        Self {
+            config: config.clone(),
+            file_hashes: read_json(cache_dir.as_deref(), HASHES_FILE_NAME),
+            page_images: read_json(cache_dir.as_deref(), PAGES_FILE_NAME),
+            cache_dir,
+            processed: HashMap::new(),
+            referenced: HashSet::new(),
+            changed: false,
+        }
+    }
+
+    /// Hashes the images a post refers to along with the settings, or returns 0 if it has none.
+    pub(crate) fn hash_images(&mut self, post: &Post, source: &SourceTree) -> u32 {
+        if !self.config.enabled {
+            return 0;
+        }
+        let hashes = check::image_targets(post)
+            .iter()
+            .filter_map(|target| local_path(&post.filename, target))
+            .filter_map(|path| {
+                let hash = self.file_hash(source, &path).ok()?;
+                Some((path, hash))
+            })
+            .collect::<Vec<_>>();
+        for (path, hash) in &hashes {
+            let key = self.cache_key(path, *hash);
+            self.referenced.insert(key);
+        }
+        match hashes.is_empty() {
+            true => 0,
+            false => HashCache::hash_contents(format!("{:?}{:?}", self.config, hashes)),
+        }
+    }
+
+    /// Swaps the local images in the HTML of `post` for responsive ones, returning the files
+    /// of their variants.
+    ///
+    /// Images that don't exist are left for `nail check` to report as broken links.
+    pub(crate) fn rewrite(
+        &mut self,
+        html: &str,
+        post: &Post,
+        source: &SourceTree,
+        base_path: &str,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> (String, Vec<BuildFile>) {
+        if !self.config.enabled {
+            return (html.to_string(), Vec::new());
+        }
+        let base_path = match base_path.trim_matches('/') {
+            "" => String::new(),
+            base_path => format!("/{}", base_path),
+        };
+        let mut rewritten = String::with_capacity(html.len());
+        let mut files = Vec::new();
+        let mut keys = BTreeSet::new();
+        let mut copied = 0;
+        for tag in img_tags(html) {
+            let src = match tag.attribute("src") {
+                Some(src) if tag.attribute("srcset").is_none() => src,
+                _ => continue,
+            };
+            let path = match local_path(&post.filename, src) {
+                Some(path) if source.is_file(&path) => path,
+                _ => continue,
+            };
+            let image = match self.process(source, &path) {
+                Ok((key, image)) => {
+                    keys.insert(key);
+                    image
+                }
+                Err(err) => {
+                    let mut diagnostic =
+                        Diagnostic::error(format!("Unable to process image `{}`: {:#}", src, err));
+                    // Reported at the first mention, the post's HTML isn't what the author wrote
+                    if let Some(offset) = post.contents.find(src) {
+                        let (line, column) = markdown::position(&post.contents, offset);
+                        diagnostic = diagnostic
+                            .at(Some(line), Some(column))
+                            .with_source(&post.contents);
+                    }
+                    diagnostics.push(diagnostic);
+                    continue;
+                }
+            };
+            rewritten.push_str(&html[copied..tag.range.start]);
+            rewritten.push_str(&image.html(&tag.attributes, &base_path, &self.config.sizes));
+            copied = tag.range.end;
+            files.extend(image.files());
+        }
+        rewritten.push_str(&html[copied..]);
+        if self.page_images.get(&post.filename) != Some(&keys) {
+            self.page_images.insert(post.filename.clone(), keys);
+            self.changed = true;
+        }
+        (rewritten, files)
+    }
+
+    /// Saves the hashes of source images and the images of each page to the cache folder, if
+    /// they changed.
+    pub(crate) fn save(&mut self) -> anyhow::Result<()> {
+        let dir = match &self.cache_dir {
+            Some(dir) if self.changed => dir,
+            _ => return Ok(()),
+        };
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(
+            dir.join(HASHES_FILE_NAME),
+            serde_json::to_vec(&self.file_hashes)?,
+        )?;
+        std::fs::write(
+            dir.join(PAGES_FILE_NAME),
+            serde_json::to_vec(&self.page_images)?,
+        )?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Removes the variants no post uses anymore from the cache folder and from `build_dir`,
+    /// like those of deleted images or of widths removed from the config.
+    ///
+    /// `posts` are the source files of all posts, including those left out by hooks, whose
+    /// pages stay in the output folder. An image is in use if a post refers to it, or if it was
+    /// in the post's page when it was last rendered, e.g. because a shortcode added it.
+    pub(crate) fn remove_stale(
+        &mut self,
+        posts: &[PathBuf],
+        build_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let dir = match &self.cache_dir {
+            Some(dir) if self.config.enabled => dir.clone(),
+            _ => return Ok(()),
+        };
+        let pages = self.page_images.len();
+        self.page_images.retain(|path, _| posts.contains(path));
+        self.changed |= self.page_images.len() != pages;
+        let in_use = self
+            .referenced
+            .iter()
+            .chain(self.page_images.values().flatten())
+            .collect::<BTreeSet<_>>();
+        let mut keep = HashSet::new();
+        let mut is_complete = true;
+        for &key in in_use {
+            match self.read_manifest(key) {
+                Some(variants) => {
+                    keep.insert(manifest_name(key));
+                    keep.extend(variants.into_iter().map(|variant| variant.file_name));
+                }
+                None => is_complete = false,
+            }
+        }
+        self.referenced.clear();
+        remove_variants(&dir, &keep)?;
+        // Published variants of an image whose manifest is gone can't be told from stale ones
+        if is_complete {
+            remove_variants(&build_dir.join(IMAGES_DIR), &keep)?;
+        }
+        Ok(())
+    }
+
+    /// The hash of an image's contents, only read again if its size or modification time
+    /// changed.
+    fn file_hash(&mut self, source: &SourceTree, path: &Path) -> std::io::Result<u64> {
+        let stamp = source.modified(path);
+        if let (Some((modified, size)), Some(known)) = (stamp, self.file_hashes.get(path)) {
+            if known.modified == modified && known.size == size {
+                return Ok(known.hash);
+            }
+        }
+        let hash = hash64(&source.read(path)?);
+        if let Some((modified, size)) = stamp {
+            let file_hash = FileHash {
+                modified,
+                size,
+                hash,
+            };
+            self.file_hashes.insert(path.to_path_buf(), file_hash);
+            self.changed = true;
+        }
+        Ok(hash)
+    }
+
+    /// The key of an image in the cache, from its path, contents and the settings.
+    fn cache_key(&self, path: &Path, hash: u64) -> u64 {
+        hash64(
+            format!(
+                "{}\0{:016x}{:?}{}{}",
+                path.display(),
+                hash,
+                self.config.widths,
+                self.config.quality,
+                self.config.avif
+            )
+            .as_bytes(),
+        )
+    }
+
+    /// Processes the image at `path`, reusing earlier results for the same source, and returns
+    /// it along with its cache key.
+    fn process(
+        &mut self,
+        source: &SourceTree,
+        path: &Path,
+    ) -> anyhow::Result<(u64, Arc<ProcessedImage>)> {
+        let hash = self.file_hash(source, path)?;
+        let key = self.cache_key(path, hash);
+        if let Some(image) = self.processed.get(&key) {
+            return Ok((key, image.clone()));
+        }
+        let image = match self.read_cache(key) {
+            Some(image) => image,
+            None => {
+                let stem = path
+                    .file_stem()
+                    .map(|stem| slugify(&stem.to_string_lossy()))
+                    .unwrap_or_default();
+                let bytes = source.read(path)?;
+                let image = encode(&bytes, &format!("{}-{:016x}", stem, key), &self.config)?;
+                self.write_cache(key, &image)
+                    .context("Unable to write the image cache")?;
+                image
+            }
+        };
+        let image = Arc::new(image);
+        self.processed.insert(key, image.clone());
+        Ok((key, image))
+    }
+
+    /// Reads the list of an image's variants from the cache folder, without their contents.
+    fn read_manifest(&self, key: u64) -> Option<Vec<Variant>> {
+        let dir = self.cache_dir.as_ref()?;
+        let manifest = std::fs::read(dir.join(manifest_name(key))).ok()?;
+        serde_json::from_slice(&manifest).ok()
+    }
+
+    /// Reads the variants of an image from the cache folder, if they're all there.
+    fn read_cache(&self, key: u64) -> Option<ProcessedImage> {
+        let dir = self.cache_dir.as_ref()?;
+        let mut variants = self.read_manifest(key)?;
+        for variant in &mut variants {
+            variant.bytes = std::fs::read(dir.join(&variant.file_name)).ok()?.into();
+        }
+        Some(ProcessedImage { variants })
+    }
+
+    fn write_cache(&self, key: u64, image: &ProcessedImage) -> anyhow::Result<()> {
+        let dir = match &self.cache_dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        std::fs::create_dir_all(dir)?;
+        for variant in &image.variants {
+            std::fs::write(dir.join(&variant.file_name), &variant.bytes)?;
+        }
+        // Written last, so an interrupted build doesn't leave a manifest of missing files
+        std::fs::write(
+            dir.join(manifest_name(key)),
+            serde_json::to_vec(&image.variants)?,
+        )?;
+        Ok(())
+    }
+}
+
+fn hash64(bytes: &[u8]) -> u64 {
+    crc::Crc::<u64>::new(&crc::CRC_64_XZ).checksum(bytes)
+}
+
+/// The file listing the variants of the image with the cache key `key`.
+fn manifest_name(key: u64) -> String {
+    format!("{:016x}.json", key)
+}
+
+/// Reads a file from the cache folder, starting over if it's missing or unreadable.
+fn read_json<T: DeserializeOwned + Default>(dir: Option<&Path>, file_name: &str) -> T {
+    dir.and_then(|dir| std::fs::read(dir.join(file_name)).ok())
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Whether a file name is one of a variant or manifest, like `cat-<key>-480.webp` or
+/// `<key>.json`, so other files in the same folder are never removed.
+fn is_variant_file(file_name: &str) -> bool {
+    let is_key = |key: &str| key.len() == 16 && key.bytes().all(|b| b.is_ascii_hexdigit());
+    if let Some(key) = file_name.strip_suffix(".json") {
+        return is_key(key);
+    }
+    let name = match Path::new(file_name).extension().and_then(|e| e.to_str()) {
+        Some("webp" | "avif") => &file_name[..file_name.rfind('.').unwrap_or(0)],
+        _ => return false,
+    };
+    let mut parts = name.rsplitn(3, '-');
+    let width = parts.next().unwrap_or_default();
+    let key = parts.next().unwrap_or_default();
+    !width.is_empty() && width.bytes().all(|b| b.is_ascii_digit()) && is_key(key)
+}
+
+/// Removes the variants and manifests in `dir` that aren't in `keep`.
+fn remove_variants(dir: &Path, keep: &HashSet<String>) -> anyhow::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if is_variant_file(&file_name) && !keep.contains(&file_name) && entry.path().is_file() {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Decodes an image and encodes it at each configured width, naming the files after `name`.
+fn encode(bytes: &[u8], name: &str, config: &ImagesConfig) -> anyhow::Result<ProcessedImage> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    // EXIF is left out, so photos are turned upright first
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    let largest = config
+        .widths
+        .iter()
+        .copied()
+        .filter(|&width| width > 0)
+        .max()
+        .unwrap_or(u32::MAX)
+        .min(image.width());
+    let mut widths = config
+        .widths
+        .iter()
+        .copied()
+        .filter(|&width| width > 0 && width < largest)
+        .chain([largest])
+        .collect::<Vec<_>>();
+    // Narrower variants are scaled down from wider ones, which is much faster
+    widths.sort_unstable_by(|a, b| b.cmp(a));
+    widths.dedup();
+    let mut formats = vec![Format::Webp];
+    if config.avif {
+        formats.push(Format::Avif);
+    }
+    let mut variants = Vec::new();
+    for width in widths {
+        if width != image.width() {
+            image = image.resize(width, u32::MAX, FilterType::Lanczos3);
+        }
+        let resized = image.to_rgba8();
+        for &format in &formats {
+            let bytes = match format {
+                Format::Webp => {
+                    webp::Encoder::from_rgba(&resized, resized.width(), resized.height())
+                        .encode_simple(false, config.quality as f32)
+                        .map_err(|err| anyhow!("Unable to encode WebP: {:?}", err))?
+                        .to_vec()
+                }
+                Format::Avif => {
+                    let mut bytes = Vec::new();
+                    DynamicImage::ImageRgba8(resized.clone()).write_with_encoder(
+                        AvifEncoder::new_with_speed_quality(&mut bytes, 6, config.quality),
+                    )?;
+                    bytes
+                }
+            };
+            variants.push(Variant {
+                format,
+                width: resized.width(),
+                height: resized.height(),
+                file_name: format!("{}-{}.{}", name, resized.width(), format.extension()),
+                bytes: bytes.into(),
+            });
+        }
+    }
+    variants.reverse();
+    Ok(ProcessedImage { variants })
+}
+
+/// The file an image's `src` refers to, relative to the project root, if it's a local image in
+/// a format that is processed.
+///
+/// Paths leading out of the project, like `../../secret.png`, aren't local images.
+fn local_path(post: &Path, src: &str) -> Option<PathBuf> {
+    let src = percent_decode(&src.replace("&amp;", "&"))?;
+    let is_external =
+        src.contains("://") || ["//", "data:"].iter().any(|prefix| src.starts_with(prefix));
+    if is_external {
+        return None;
+    }
+    let path = src.split(['#', '?']).next().unwrap_or_default();
+    let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    if !EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+    let path = match path.strip_prefix('/') {
+        Some(path) => PathBuf::from(path),
+        None => post.parent().unwrap_or_else(|| Path::new("")).join(path),
+    };
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => (),
+            Component::ParentDir if normalized.pop() => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// An `<img>` tag in a page.
+struct ImgTag {
+    range: Range<usize>,
+    attributes: Vec<(String, String)>,
+}
+
+impl ImgTag {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Finds the `<img>` tags outside of comments.
+fn img_tags(html: &str) -> Vec<ImgTag> {
+    let mut tags = Vec::new();
+    let mut from = 0;
+    while let Some(start) = html[from..].find('<') {
+        let start = from + start;
+        let rest = &html[start + 1..];
+        if rest.starts_with("!--") {
+            from = rest
+                .find("-->")
+                .map_or(html.len(), |end| start + 1 + end + "-->".len());
+            continue;
+        }
+        // The tag ends at the first `>` outside of quoted values
+        let mut quote = None;
+        let end = rest.char_indices().find_map(|(index, c)| {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(open), c) if c == open => quote = None,
+                (None, '>') => return Some(start + 1 + index),
+                _ => (),
+            }
+            None
+        });
+        let end = match end {
+            Some(end) => end,
+            None => break,
+        };
+        from = end + 1;
+        let inner = &html[start + 1..end];
+        let is_img = inner
+            .get(..3)
+            .is_some_and(|name| name.eq_ignore_ascii_case("img"))
+            && inner[3..].starts_with(|c: char| c.is_whitespace() || c == '/');
+        if is_img {
+            tags.push(ImgTag {
+                range: start..end + 1,
+                attributes: parse_attributes(inner[3..].trim_end().trim_end_matches('/')),
+            });
+        }
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post_format::PostFormat;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nail-images-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("images")).unwrap();
+        dir
+    }
+
+    fn png(width: u32, height: u32, color: [u8; 3]) -> Vec<u8> {
+        let image = image::RgbImage::from_pixel(width, height, image::Rgb(color));
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn images_are_left_alone_unless_enabled() {
+        let root = temp_dir("disabled");
+        std::fs::write(root.join("images/red.png"), png(40, 20, [255, 0, 0])).unwrap();
+        let contents = "[//]: # (title: Red)\n\n![Red](/images/red.png)\n".to_string();
+        let post = Post {
+            format: PostFormat::Markdown,
+            filename: "posts/red.md".into(),
+            metadata: PostFormat::Markdown.extract_metadata(&contents).unwrap(),
+            contents,
+        };
+        let html = "<p><img src=\"/images/red.png\" alt=\"Red\" /></p>";
+        let source = SourceTree::disk(&root);
+        let mut processor = ImageProcessor::new(&ImagesConfig::default(), Some(&root));
+        assert_eq!(processor.hash_images(&post, &source), 0);
+        let (rewritten, files) = processor.rewrite(html, &post, &source, "", &mut Vec::new());
+        assert_eq!(rewritten, html);
+        assert!(files.is_empty());
+        assert!(!root.join(CACHE_DIR).exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn img_tags_are_found_outside_comments() {
+        let html = "<p><img src=\"a.png\" alt=\"a > b\"><!-- <img src=\"b.png\"> --><IMG\nsrc='c.jpg'/></p><imgs src=\"d.png\">";
+        let tags = img_tags(html);
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].attribute("src"), Some("a.png"));
+        assert_eq!(tags[0].attribute("alt"), Some("a > b"));
+        assert_eq!(
+            &html[tags[0].range.clone()],
+            "<img src=\"a.png\" alt=\"a > b\">"
+        );
+        assert_eq!(tags[1].attribute("src"), Some("c.jpg"));
+        assert!(img_tags("<img src=\"unterminated.png\"").is_empty());
+    }
+
+    #[test]
+    fn local_paths_are_relative_to_the_post_or_the_root() {
+        let post = Path::new("posts/travel/japan.md");
+        assert_eq!(
+            local_path(post, "../../images/Fuji%20San.JPG?v=2"),
+            Some(PathBuf::from("images/Fuji San.JPG"))
+        );
+        assert_eq!(
+            local_path(post, "/images/a.png"),
+            Some("images/a.png".into())
+        );
+        assert_eq!(
+            local_path(post, "map.webp"),
+            Some("posts/travel/map.webp".into())
+        );
+        assert_eq!(local_path(post, "https://example.com/a.png"), None);
+        assert_eq!(local_path(post, "//cdn.example.com/a.png"), None);
+        assert_eq!(local_path(post, "drawing.svg"), None);
+        assert_eq!(
+            local_path(post, "../../images/./a/../b.png"),
+            Some("images/b.png".into())
+        );
+        assert_eq!(local_path(post, "../../../outside.png"), None);
+        assert_eq!(local_path(post, "/../outside.png"), None);
+        assert_eq!(local_path(post, "/images/../../outside.png"), None);
+        assert_eq!(local_path(post, "/%2e%2e/outside.png"), None);
+    }
+
+    #[test]
+    fn file_hashes_are_reused_until_size_or_time_change() {
+        let root = temp_dir("hashes");
+        let path = Path::new("images/a.png");
+        let file = root.join(path);
+        std::fs::write(&file, "first").unwrap();
+        let source = SourceTree::disk(&root);
+        let mut processor = ImageProcessor::new(&ImagesConfig::default(), Some(&root));
+        let first = processor.file_hash(&source, path).unwrap();
+        // Same size and modification time, so the stored hash is trusted
+        let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+        std::fs::write(&file, "other").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(processor.file_hash(&source, path).unwrap(), first);
+        std::fs::write(&file, "changed").unwrap();
+        let changed = processor.file_hash(&source, path).unwrap();
+        assert_ne!(changed, first);
+        processor.save().unwrap();
+        let mut reloaded = ImageProcessor::new(&ImagesConfig::default(), Some(&root));
+        assert_eq!(reloaded.file_hashes, processor.file_hashes);
+        assert_eq!(reloaded.file_hash(&source, path).unwrap(), changed);
+        assert!(!reloaded.changed);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn cache_keys_depend_on_the_path_and_settings() {
+        let processor = ImageProcessor::new(&ImagesConfig::default(), None);
+        let key = processor.cache_key(Path::new("images/a.png"), 1);
+        assert_ne!(key, processor.cache_key(Path::new("images/b.png"), 1));
+        assert_ne!(key, processor.cache_key(Path::new("images/a.png"), 2));
+        let config = ImagesConfig {
+            quality: 50,
+            ..ImagesConfig::default()
+        };
+        let other = ImageProcessor::new(&config, None);
+        assert_ne!(key, other.cache_key(Path::new("images/a.png"), 1));
+    }
+
+    #[test]
+    fn widths_of_zero_are_rejected() {
+        let config = toml::from_str::<ImagesConfig>("widths = [480, 960]").unwrap();
+        assert_eq!(config.widths, [480, 960]);
+        let err = toml::from_str::<ImagesConfig>("widths = [480, 0]").unwrap_err();
+        assert!(err.to_string().contains("greater than 0"), "{}", err);
+    }
+
+    #[test]
+    fn variant_files_are_told_apart_from_others() {
+        assert!(is_variant_file("red-00112233445566aa-480.webp"));
+        assert!(is_variant_file("my-photo-00112233445566aa-1600.avif"));
+        assert!(is_variant_file("00112233445566aa.json"));
+        assert!(!is_variant_file("hashes.json"));
+        assert!(!is_variant_file("pages.json"));
+        assert!(!is_variant_file("logo-480.webp"));
+        assert!(!is_variant_file("red-00112233445566aa-480.png"));
+        assert!(!is_variant_file("red-00112233445566aa-large.webp"));
+    }
+
+    #[test]
+    fn stale_variants_are_removed() {
+        let root = temp_dir("stale");
+        std::fs::write(root.join("images/red.png"), png(40, 20, [255, 0, 0])).unwrap();
+        std::fs::write(root.join("images/blue.png"), png(40, 20, [0, 0, 255])).unwrap();
+        let source = SourceTree::disk(&root);
+        let contents = "[//]: # (title: Red)\n\n![Red](/images/red.png)\n".to_string();
+        let post = Post {
+            format: PostFormat::Markdown,
+            filename: "posts/red.md".into(),
+            metadata: PostFormat::Markdown.extract_metadata(&contents).unwrap(),
+            contents,
+        };
+        let build_dir = root.join("build");
+        let published = build_dir.join(IMAGES_DIR);
+        std::fs::create_dir_all(&published).unwrap();
+        std::fs::write(published.join("logo.svg"), "<svg/>").unwrap();
+        let config = ImagesConfig {
+            enabled: true,
+            widths: vec![20],
+            ..ImagesConfig::default()
+        };
+        let mut processor = ImageProcessor::new(&config, Some(&root));
+        let publish = |processor: &mut ImageProcessor, path: &str| {
+            let (_, image) = processor.process(&source, Path::new(path)).unwrap();
+            for file in image.files() {
+                file.write_to_disk(&build_dir).unwrap();
+            }
+            image.variants[0].file_name.clone()
+        };
+        let red = publish(&mut processor, "images/red.png");
+        let blue = publish(&mut processor, "images/blue.png");
+        // Only the red image is still in a post
+        processor.hash_images(&post, &source);
+        processor
+            .remove_stale(std::slice::from_ref(&post.filename), &build_dir)
+            .unwrap();
+        let cache_dir = root.join(CACHE_DIR);
+        assert!(cache_dir.join(&red).exists() && published.join(&red).exists());
+        assert!(!cache_dir.join(&blue).exists() && !published.join(&blue).exists());
+        assert!(published.join("logo.svg").exists());
+        // Other widths make for new variants, replacing the old ones
+        let config = ImagesConfig {
+            widths: vec![10],
+            ..config
+        };
+        let mut processor = ImageProcessor::new(&config, Some(&root));
+        let narrow = publish(&mut processor, "images/red.png");
+        processor.hash_images(&post, &source);
+        processor
+            .remove_stale(std::slice::from_ref(&post.filename), &build_dir)
+            .unwrap();
+        assert!(published.join(&narrow).exists());
+        assert!(!cache_dir.join(&red).exists() && !published.join(&red).exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn images_are_processed_into_their_own_variants() {
+        let root = temp_dir("process");
+        std::fs::write(root.join("images/red.png"), png(40, 20, [255, 0, 0])).unwrap();
+        std::fs::write(root.join("images/blue.png"), png(40, 20, [0, 0, 255])).unwrap();
+        let source = SourceTree::disk(&root);
+        let config = ImagesConfig {
+            widths: vec![20, 80],
+            ..ImagesConfig::default()
+        };
+        let mut processor = ImageProcessor::new(&config, Some(&root));
+        let (_, red) = processor
+            .process(&source, Path::new("images/red.png"))
+            .unwrap();
+        let (_, blue) = processor
+            .process(&source, Path::new("images/blue.png"))
+            .unwrap();
+        let widths = red
+            .variants
+            .iter()
+            .map(|variant| variant.width)
+            .collect::<Vec<_>>();
+        assert_eq!(widths, [20, 40]);
+        assert_ne!(red.variants[0].file_name, blue.variants[0].file_name);
+        assert!(red.variants[0].file_name.starts_with("red-"));
+        // A new processor reads the variants back from the cache folder
+        let mut cached = ImageProcessor::new(&config, Some(&root));
+        let (_, again) = cached
+            .process(&source, Path::new("images/red.png"))
+            .unwrap();
+        assert_eq!(again.variants[1].file_name, red.variants[1].file_name);
+        assert_eq!(again.variants[1].bytes, red.variants[1].bytes);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+//! A lightning fast static blog engine.
+//!
+//! Besides the `nail` command line tool, this crate can be embedded to build
+//! blogs from other Rust programs. Sources are read from a [`SourceTree`],
+//! which is either a project directory on disk or a [`MemoryTree`], and every
+//! build produces an in-memory [`Bundle`] that can be inspected, served or
+//! written to disk.
+//!
+//! ```
+//! use nail_blog::{Engine, MemoryTree};
+//!
+//! let source = MemoryTree::new()
+//!     .with_file("config.toml", "name = \"My Blog\"\ntitle = \"My Blog\"\n")
+//!     .with_file(
+//!         "posts/hello.md",
+//!         "[//]: # (title: Hello)\n[//]: # (published: true)\n\
+//!          [//]: # (published_at: 2022-10-01T10:00:00Z)\n\nHello *world*!\n",
+//!     )
+//!     .with_file("themes/minimal/minimal.css", "body { margin: 0; }")
+//!     .with_file("themes/minimal/minimal.base.html", "{{{ page.content }}}")
+//!     .with_file("themes/minimal/minimal.home.html", "{{#each home.posts}}{{ this.title }}{{/each}}")
+//!     .with_file("themes/minimal/minimal.post.html", "<h1>{{ post.title }}</h1>{{{ post.content }}}");
+//!
+//! let mut engine = Engine::builder(source).load()?;
+//! let bundle = engine.build()?;
+//! let post = bundle.get("/posts/hello.html").unwrap();
+//! assert!(post.contents().contains("<em>world</em>"));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod build;
+mod cache;
+pub mod check;
+pub mod config;
+pub mod dev_server;
+pub mod diagnostics;
+pub mod hooks;
+mod http;
+pub mod images;
+pub mod links;
+pub mod markdown;
+pub mod plugins;
+pub mod post_format;
+pub mod post_metadata;
+pub mod scaffold;
+pub mod shortcodes;
+pub mod site;
+pub mod source;
+pub mod static_server;
+pub mod theme;
+
+pub use build::{BuildFile, Bundle, Engine, EngineBuilder, Post};
+pub use config::Config;
+pub use dev_server::DevServer;
+pub use diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use hooks::Hook;
+pub use post_format::PostFormat;
+pub use post_metadata::PostMetadata;
+pub use scaffold::Scaffold;
+pub use source::{MemoryTree, SourceTree};
+pub use static_server::StaticServer;
+pub use theme::{RenderData, Theme};
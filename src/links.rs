@@ -0,0 +1,313 @@
+//! Links between posts, written as `[text](other_post.md)` or `[[Other Post]]` in Markdown posts.
+//!
+//! Both are resolved to the URL the other post is published at, so they keep working when its
+//! title, and with it its URL, changes. Links to a `.md` file and wiki links that match no post
+//! fail the build. Templates get the posts linking to a post as `post.backlinks`.
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use comrak::{nodes::NodeValue, parse_document, Arena, ComrakOptions};
+
+use crate::{build::Post, check::normalize, markdown::slugify, post_format::PostFormat};
+
+/// Where every post is published, to resolve links to it by file or title.
+#[derive(Debug, Clone, Default)]
+pub struct PostLinks {
+    urls: HashMap<PathBuf, String>,
+    /// Posts by title and file name, see [`name_key`].
+    names: HashMap<String, PathBuf>,
+}
+
+/// What the destination of a link refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    /// A post, by source file, with the URL to link to instead.
+    Post(PathBuf, String),
+    /// A Markdown file that isn't a post.
+    Dangling,
+    /// Anything else, like another website or a static file.
+    Other,
+}
+
+impl PostLinks {
+    /// Indexes `posts`, which are published below `base_path`, e.g. `/blog`.
+    pub fn new(posts: &[Post], base_path: &str) -> Self {
+        let mut links = Self::default();
+        for post in posts {
+            links
+                .urls
+                .insert(post.filename.clone(), post_url(post, base_path));
+            if let Some(stem) = post.filename.file_stem() {
+                links
+                    .names
+                    .insert(name_key(&stem.to_string_lossy()), post.filename.clone());
+            }
+        }
+        // Titles take precedence over file names
+        for post in posts {
+            links
+                .names
+                .insert(name_key(&post.metadata.title), post.filename.clone());
+        }
+        links
+    }
+
+    /// The URL of the post at `source`.
+    pub fn url(&self, source: &Path) -> Option<&str> {
+        self.urls.get(source).map(String::as_str)
+    }
+
+    /// Resolves the destination of a link in the post at `from`, like `other.md#setup`.
+    ///
+    /// Relative paths start from the post's folder, absolute ones from the project root.
+    pub fn resolve_url(&self, from: &Path, url: &str) -> Resolved {
+        let is_external = url.contains("://")
+            || ["mailto:", "tel:", "data:", "//", "#"]
+                .iter()
+                .any(|prefix| url.starts_with(prefix));
+        if is_external {
+            return Resolved::Other;
+        }
+        let (path, fragment) = url.split_at(url.find(['#', '?']).unwrap_or(url.len()));
+        let source = match path.strip_prefix('/') {
+            Some(path) => PathBuf::from(path),
+            None => normalize(&from.parent().unwrap_or_else(|| Path::new("")).join(path)),
+        };
+        match self.urls.get(&source) {
+            Some(url) => Resolved::Post(source, format!("{}{}", url, fragment)),
+            None if path.to_ascii_lowercase().ends_with(".md") => Resolved::Dangling,
+            None => Resolved::Other,
+        }
+    }
+
+    /// Resolves the target of a wiki link: a post's title or file name, optionally followed by
+    /// `#Heading`.
+    pub fn resolve_wiki(&self, target: &str) -> Option<(PathBuf, String)> {
+        let (name, heading) = target.split_once('#').unwrap_or((target, ""));
+        let source = self.names.get(&name_key(name))?;
+        let url = match slugify(heading) {
+            id if id.is_empty() => self.urls[source].clone(),
+            id => format!("{}#{}", self.urls[source], id),
+        };
+        Some((source.clone(), url))
+    }
+
+    /// The source files of the other posts a Markdown post links to.
+    pub fn targets(&self, post: &Post) -> Vec<PathBuf> {
+        if !matches!(post.format, PostFormat::Markdown) {
+            return Vec::new();
+        }
+        let arena = Arena::new();
+        let root = parse_document(&arena, &post.contents, &ComrakOptions::default());
+        let mut targets = Vec::new();
+        for node in root.descendants() {
+            match &node.data.borrow().value {
+                NodeValue::Link(link) => {
+                    let url = String::from_utf8_lossy(&link.url);
+                    if let Resolved::Post(source, _) = self.resolve_url(&post.filename, &url) {
+                        targets.push(source);
+                    }
+                }
+                NodeValue::Text(text) => {
+                    let text = String::from_utf8_lossy(text);
+                    targets.extend(
+                        find_wiki_links(&text)
+                            .iter()
+                            .filter_map(|link| self.resolve_wiki(link.target))
+                            .map(|(source, _)| source),
+                    );
+                }
+                _ => (),
+            }
+        }
+        targets.retain(|target| target != &post.filename);
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    /// The posts linking to each post, by source file.
+    pub fn backlinks<'a>(&self, posts: &'a [Post]) -> HashMap<PathBuf, Vec<&'a Post>> {
+        let mut backlinks = HashMap::<PathBuf, Vec<&Post>>::new();
+        for post in posts {
+            for target in self.targets(post) {
+                backlinks.entry(target).or_default().push(post);
+            }
+        }
+        backlinks
+    }
+}
+
+/// The URL a post is published at below `base_path`, e.g. `/blog/posts/hello.html`.
+pub(crate) fn post_url(post: &Post, base_path: &str) -> String {
+    match base_path.trim_matches('/') {
+        "" => format!("/posts/{}", post.get_final_file_name()),
+        base_path => format!("/{}/posts/{}", base_path, post.get_final_file_name()),
+    }
+}
+
+/// Matches titles and file names regardless of case and separators, so `[[other post]]` finds
+/// both `Other Post` and `other_post.md`.
+fn name_key(name: &str) -> String {
+    slugify(name)
+}
+
+/// A `[[target]]` or `[[target|label]]` in a piece of text.
+#[derive(Debug)]
+pub(crate) struct WikiLink<'a> {
+    pub range: Range<usize>,
+    pub target: &'a str,
+    pub label: &'a str,
+}
+
+/// Finds the wiki links in `text`, leaving out the `[[toc]]` marker.
+pub(crate) fn find_wiki_links(text: &str) -> Vec<WikiLink<'_>> {
+    let mut links = Vec::new();
+    let mut from = 0;
+    while let Some(start) = text[from..].find("[[") {
+        let start = from + start;
+        let inner_start = start + "[[".len();
+        let end = match text[inner_start..].find("]]") {
+            Some(end) => inner_start + end,
+            None => break,
+        };
+        let inner = &text[inner_start..end];
+        if inner.contains(['[', '\n']) {
+            from = start + 1;
+            continue;
+        }
+        from = end + "]]".len();
+        let (target, label) = match inner.split_once('|') {
+            Some((target, label)) => (target.trim(), label.trim()),
+            None => (inner.trim(), inner.trim()),
+        };
+        if target.is_empty() || target.eq_ignore_ascii_case("toc") {
+            continue;
+        }
+        links.push(WikiLink {
+            range: start..from,
+            target,
+            label,
+        });
+    }
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(path: &str, title: &str, text: &str) -> Post {
+        let contents = format!("[//]: # (title: {})\n\n{}\n", title, text);
+        Post {
+            format: PostFormat::Markdown,
+            filename: path.into(),
+            metadata: PostFormat::Markdown.extract_metadata(&contents).unwrap(),
+            contents,
+        }
+    }
+
+    fn links() -> PostLinks {
+        PostLinks::new(
+            &[
+                post("posts/setup_guide.md", "Getting Started", ""),
+                post("posts/travel/japan.md", "Japan", ""),
+            ],
+            "/blog/",
+        )
+    }
+
+    #[test]
+    fn wiki_links_are_found_with_their_labels() {
+        let text = "See [[Japan]], [[setup guide|the guide]] and [[ Japan#Food ]].";
+        let found = find_wiki_links(text)
+            .iter()
+            .map(|link| (link.target, link.label, &text[link.range.clone()]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("Japan", "Japan", "[[Japan]]"),
+                ("setup guide", "the guide", "[[setup guide|the guide]]"),
+                ("Japan#Food", "Japan#Food", "[[ Japan#Food ]]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn toc_markers_and_malformed_wiki_links_are_skipped() {
+        assert!(find_wiki_links("[[toc]] [[TOC]] [[]] [[ | x]] [[a\nb]] [[open").is_empty());
+        let nested = find_wiki_links("[[a [[Japan]]");
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].target, "Japan");
+    }
+
+    #[test]
+    fn urls_resolve_relative_to_the_post() {
+        let links = links();
+        let from = Path::new("posts/travel/japan.md");
+        assert_eq!(
+            links.resolve_url(from, "../setup_guide.md#install"),
+            Resolved::Post(
+                "posts/setup_guide.md".into(),
+                "/blog/posts/getting_started.html#install".into()
+            )
+        );
+        assert_eq!(
+            links.resolve_url(Path::new("posts/setup_guide.md"), "/posts/travel/japan.md"),
+            Resolved::Post(
+                "posts/travel/japan.md".into(),
+                "/blog/posts/japan.html".into()
+            )
+        );
+        assert_eq!(links.resolve_url(from, "missing.MD"), Resolved::Dangling);
+        assert_eq!(links.resolve_url(from, "photo.jpg"), Resolved::Other);
+        assert_eq!(links.resolve_url(from, "#top"), Resolved::Other);
+        assert_eq!(
+            links.resolve_url(from, "https://example.com/a.md"),
+            Resolved::Other
+        );
+    }
+
+    #[test]
+    fn wiki_links_resolve_by_title_or_file_name() {
+        let links = links();
+        let url = |target| links.resolve_wiki(target).map(|(_, url)| url);
+        assert_eq!(
+            url("getting started").as_deref(),
+            Some("/blog/posts/getting_started.html")
+        );
+        assert_eq!(
+            url("Setup_Guide").as_deref(),
+            Some("/blog/posts/getting_started.html")
+        );
+        assert_eq!(
+            url("Japan#Street Food").as_deref(),
+            Some("/blog/posts/japan.html#street-food")
+        );
+        assert_eq!(url("Korea"), None);
+    }
+
+    #[test]
+    fn backlinks_list_the_posts_linking_to_a_post() {
+        let posts = [
+            post("posts/a.md", "A", "[B](b.md), [[B]] and [[A]]"),
+            post("posts/b.md", "B", "`[[A]]` in code"),
+            post("posts/c.md", "C", "[[b]]"),
+        ];
+        let links = PostLinks::new(&posts, "");
+        assert_eq!(links.targets(&posts[0]), [PathBuf::from("posts/b.md")]);
+        assert!(links.targets(&posts[1]).is_empty());
+        let backlinks = links.backlinks(&posts);
+        let titles = backlinks[Path::new("posts/b.md")]
+            .iter()
+            .map(|post| post.metadata.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["A", "C"]);
+        assert!(!backlinks.contains_key(Path::new("posts/a.md")));
+    }
+}
@@ -1,19 +1,12 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
-mod build;
-mod cache;
-mod config;
-mod dev_server;
-mod post_format;
-mod post_metadata;
-mod scaffold;
-mod theme;
+use clap::{Parser, Subcommand};
 
-use build::Engine;
-use config::Config;
-use dev_server::DevServer;
-use post_format::PostFormat;
-use scaffold::Scaffold;
+use nail_blog::{
+    check::{self, OutputFormat},
+    theme::manager::ThemeManager,
+    Config, DevServer, Diagnostics, Engine, PostFormat, Scaffold, SourceTree, StaticServer,
+};
 
 #[derive(Subcommand, Debug)]
 enum PostCommand {
@@ -27,6 +20,41 @@ enum PostCommand {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum ThemeCommand {
+    #[clap(about = "Scaffold new theme")]
+    New {
+        name: String,
+        #[clap(long = "extends", help = "Only override the given theme")]
+        extends: Option<String>,
+        #[clap(long = "force")]
+        force: bool,
+    },
+    #[clap(about = "List installed themes")]
+    List,
+    #[clap(about = "Validate a theme and archive it as <name>-<version>.zip")]
+    Package {
+        #[clap(help = "Theme to package, the project's theme by default")]
+        name: Option<String>,
+        #[clap(
+            long = "out",
+            help = "Directory to write the archive to, `themes` by default"
+        )]
+        out: Option<PathBuf>,
+    },
+    #[clap(about = "Install a theme from an archive or a directory")]
+    Install {
+        path: PathBuf,
+        #[clap(long = "force", help = "Replace an installed theme of the same name")]
+        force: bool,
+    },
+    #[clap(about = "Render a theme's templates with sample posts")]
+    Validate {
+        #[clap(help = "Theme to validate, the project's theme by default")]
+        name: Option<String>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
     #[clap(about = "Scaffold new project")]
@@ -40,43 +68,207 @@ enum Command {
         #[clap(subcommand)]
         command: PostCommand,
     },
+    #[clap(about = "Manage themes")]
+    Theme {
+        #[clap(subcommand)]
+        command: ThemeCommand,
+    },
     #[clap(about = "Start development server")]
-    Dev,
+    Dev {
+        #[clap(
+            long = "host",
+            help = "Address to listen on, e.g. 0.0.0.0 for the whole network"
+        )]
+        host: Option<String>,
+        #[clap(
+            long = "port",
+            help = "Port to listen on, without falling back if it is taken"
+        )]
+        port: Option<u16>,
+    },
     #[clap(about = "Build and bundle the blog")]
     Build,
+    #[clap(about = "Serve the built blog like a production web server")]
+    Serve {
+        #[clap(help = "Directory to serve, the output directory by default")]
+        dir: Option<PathBuf>,
+        #[clap(
+            long = "host",
+            help = "Address to listen on, e.g. 0.0.0.0 for the whole network"
+        )]
+        host: Option<String>,
+        #[clap(
+            long = "port",
+            help = "Port to listen on, without falling back if it is taken"
+        )]
+        port: Option<u16>,
+        #[clap(
+            long = "base-path",
+            help = "URL path the blog is deployed under, e.g. /blog"
+        )]
+        base_path: Option<String>,
+    },
+    #[clap(about = "Check posts for common content problems")]
+    Check {
+        #[clap(long = "format", default_value = "human")]
+        format: OutputFormat,
+    },
 }
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(
+        short = 'C',
+        long = "root",
+        global = true,
+        help = "Run as if nail was started in this directory"
+    )]
+    root: Option<PathBuf>,
     #[clap(subcommand)]
     command: Command,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     let args = Args::parse();
+    if let Err(err) = run(args) {
+        match err.downcast_ref::<Diagnostics>() {
+            Some(diagnostics) => eprint!("{}", diagnostics),
+            None => eprintln!("error: {:#}", err),
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    let working_dir = match args.root {
+        Some(dir) => dir,
+        None => std::env::current_dir()?,
+    };
+    // Every command but `new` operates on an existing project
+    let project_root = || Config::discover_root(&working_dir);
     match args.command {
-        Command::New { name, force } => Scaffold::create_project(name, force)?,
+        Command::New { name, force } => Scaffold::create_project(&working_dir, name, force)?,
         Command::Post { command } => match command {
             PostCommand::New {
                 name,
                 format,
                 force,
-            } => Scaffold::create_post(name, format.unwrap_or(PostFormat::Markdown), force)?,
+            } => Scaffold::create_post(
+                &project_root()?,
+                name,
+                format.unwrap_or(PostFormat::Markdown),
+                force,
+            )?,
         },
-        Command::Dev => {
-            let server = DevServer::new();
+        Command::Theme { command } => {
+            // Theme commands also work in a folder of themes without a project
+            let manager = ThemeManager::new(project_root().unwrap_or_else(|_| working_dir.clone()));
+            match command {
+                ThemeCommand::New {
+                    name,
+                    extends,
+                    force,
+                } => manager.create(&name, extends, force)?,
+                ThemeCommand::List => {
+                    for theme in manager.list()? {
+                        let mut line = theme.name.clone();
+                        if let Some(manifest) = &theme.manifest {
+                            if let Some(version) = &manifest.version {
+                                line.push_str(&format!(" {}", version));
+                            }
+                            if let Some(author) = &manifest.author {
+                                line.push_str(&format!(" by {}", author));
+                            }
+                            if let Some(parent) = &manifest.extends {
+                                line.push_str(&format!(", extends {}", parent));
+                            }
+                        }
+                        if theme.active {
+                            line.push_str(" (active)");
+                        }
+                        println!("{}", line);
+                    }
+                }
+                ThemeCommand::Package { name, out } => {
+                    let name = manager.resolve_name(name)?;
+                    let out = match out {
+                        Some(out) => working_dir.join(out),
+                        None => manager.themes_dir(),
+                    };
+                    manager.package(&name, &out)?;
+                }
+                ThemeCommand::Install { path, force } => {
+                    manager.install(&working_dir.join(path), force)?;
+                }
+                ThemeCommand::Validate { name } => {
+                    manager.validate(&manager.resolve_name(name)?)?
+                }
+            }
+        }
+        Command::Dev { host, port } => {
+            let mut server = DevServer::new(project_root()?);
+            if let Some(host) = host {
+                server = server.host(host);
+            }
+            if let Some(port) = port {
+                server = server.port(port);
+            }
             server.serve()?;
         }
         Command::Build => {
-            let config = Config::load()?;
-            let theme = config.load_theme()?;
-            let bundle = {
-                let mut engine = Engine::new(config, theme, false)?;
-                engine.build()?
+            let mut engine = Engine::builder(SourceTree::disk(project_root()?))
+                .incremental(true)
+                .load()?;
+            let bundle = engine.build()?;
+            if !engine.diagnostics().is_empty() {
+                eprint!("{}", engine.diagnostics());
+            }
+            bundle.write_to_disk(engine.config().build_dir())?;
+        }
+        Command::Serve {
+            dir,
+            host,
+            port,
+            base_path,
+        } => {
+            // Serving an explicit directory works outside of a project as well
+            let (dir, config) = match dir {
+                Some(dir) => (
+                    working_dir.join(dir),
+                    project_root().and_then(Config::load).ok(),
+                ),
+                None => {
+                    let config = Config::load(project_root()?)?;
+                    (config.build_dir(), Some(config))
+                }
             };
-            bundle.write_to_disk()?;
+            let mut server = StaticServer::new(dir);
+            if let Some(config) = &config {
+                server = server.config(config);
+            }
+            if let Some(base_path) = base_path {
+                server = server.base_path(base_path);
+            }
+            if let Some(host) = host {
+                server = server.host(host);
+            }
+            if let Some(port) = port {
+                server = server.port(port);
+            }
+            server.serve()?;
+        }
+        Command::Check { format } => {
+            let mut engine = Engine::builder(SourceTree::disk(project_root()?)).load()?;
+            let diagnostics = engine.check()?;
+            match format {
+                OutputFormat::Human => eprint!("{}", diagnostics),
+                OutputFormat::Json => println!("{}", check::to_json(&diagnostics)?),
+            }
+            if diagnostics.has_errors() {
+                std::process::exit(1);
+            }
         }
     }
     Ok(())
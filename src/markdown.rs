@@ -0,0 +1,1030 @@
+//! Converts Markdown posts to HTML.
+//!
+//! Besides plain CommonMark, headings get stable `id`s derived from their text (or set
+//! explicitly with a trailing `{#id}`), optionally with anchor links, and are collected into
+//! a table of contents that a `[[toc]]` paragraph inserts inline. LaTeX math is converted to
+//! MathML, see [`math`], callouts to asides, see [`callouts`], and shortcodes are expanded with
+//! the theme, see [`shortcodes`]. Links to other posts are resolved to their URL, see
+//! [`links`].
+//!
+//! Math, shortcodes and the fences of `:::` callouts are cut out of the Markdown before it is
+//! parsed and replaced with a placeholder, which is swapped for their HTML afterwards.
+//!
+//! [`shortcodes`]: crate::shortcodes
+//! [`links`]: crate::links
+
+mod callouts;
+mod math;
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    ops::Range,
+    path::Path,
+};
+
+use comrak::{
+    arena_tree::Node,
+    format_html,
+    nodes::{Ast, NodeHtmlBlock, NodeLink, NodeValue},
+    parse_document, Arena, ComrakOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    diagnostics::Diagnostic,
+    links::{self, PostLinks, Resolved},
+    shortcodes::{self, Shortcode, ShortcodeRenderer},
+};
+
+type AstNode<'a> = Node<'a, RefCell<Ast>>;
+
+/// What comrak renders raw HTML as, which posts may not contain.
+static OMITTED_HTML: &[u8] = b"<!-- raw HTML omitted -->";
+
+/// A paragraph consisting of only this marker is replaced with the table of contents.
+static TOC_MARKER: &str = "[[toc]]";
+
+/// Surrounds the index of an [`Embed`] in the Markdown passed to comrak.
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+/// Stands for a [`PLACEHOLDER_START`] written in the post, so it isn't taken for a placeholder.
+const ESCAPED_START: &str = "\u{E000}\u{E001}";
+
+/// The `[markdown]` section of `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct MarkdownConfig {
+    /// Where headings link to themselves, if at all.
+    pub anchor_links: AnchorPosition,
+    /// Text of the anchor links, e.g. `#` or `¶`.
+    pub anchor_symbol: String,
+    /// Deepest heading level listed in the table of contents.
+    pub toc_depth: u32,
+    /// Whether to convert `$...$`, `$$...$$` and `math` code blocks to MathML.
+    pub math: bool,
+    /// Callout types besides `note`, `tip`, `important`, `warning` and `caution`, with their
+    /// default title, e.g. `tldr = "TL;DR"`.
+    pub callouts: BTreeMap<String, String>,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            anchor_links: AnchorPosition::None,
+            anchor_symbol: "#".to_string(),
+            toc_depth: 3,
+            math: true,
+            callouts: BTreeMap::new(),
+        }
+    }
+}
+
+impl MarkdownConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AnchorPosition {
+    #[default]
+    None,
+    Before,
+    After,
+}
+
+/// A heading in a post's table of contents, with the headings below it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    pub level: u32,
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// A post converted to HTML.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub html: String,
+    /// The post's headings, empty if disabled with `toc: false`.
+    pub toc: Vec<TocEntry>,
+    /// Problems with the post's contents, such as malformed math, without a file.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// How a post is converted.
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertOptions<'a> {
+    pub config: &'a MarkdownConfig,
+    /// Whether to collect the table of contents and insert it at `[[toc]]`.
+    pub toc: bool,
+    /// Expands `{{< ... >}}` shortcodes, which are left as they are without one.
+    pub shortcodes: Option<&'a dyn ShortcodeRenderer>,
+    /// Resolves links to other posts from the post at the given path, which are left as they
+    /// are without it.
+    pub links: Option<(&'a PostLinks, &'a Path)>,
+}
+
+impl<'a> ConvertOptions<'a> {
+    pub fn new(config: &'a MarkdownConfig) -> Self {
+        Self {
+            config,
+            toc: true,
+            shortcodes: None,
+            links: None,
+        }
+    }
+
+    pub fn toc(mut self, toc: bool) -> Self {
+        self.toc = toc;
+        self
+    }
+
+    pub fn shortcodes(mut self, shortcodes: &'a dyn ShortcodeRenderer) -> Self {
+        self.shortcodes = Some(shortcodes);
+        self
+    }
+
+    /// Resolves links in the post at `path`, relative to the project root, with `links`.
+    pub fn links(mut self, links: &'a PostLinks, path: &'a Path) -> Self {
+        self.links = Some((links, path));
+        self
+    }
+}
+
+/// Converts Markdown to HTML.
+pub fn convert(content: &str, options: &ConvertOptions) -> anyhow::Result<Document> {
+    let arena = Arena::new();
+    let mut comrak_options = ComrakOptions::default();
+    let mut diagnostics = Vec::new();
+    let needs_code_blocks =
+        options.config.math || options.shortcodes.is_some() || content.contains(":::");
+    let code_blocks = match needs_code_blocks {
+        true => scan_code_blocks(content, &comrak_options),
+        false => Vec::new(),
+    };
+    let embeds = find_embeds(content, &code_blocks, options, &mut diagnostics);
+    let root = parse_document(
+        &arena,
+        &with_placeholders(content, &embeds),
+        &comrak_options,
+    );
+    sanitize(root);
+    // Only HTML generated below is rendered, raw HTML in the post was omitted above
+    comrak_options.render.unsafe_ = true;
+    callouts::render_blockquotes(&arena, root, options.config, &comrak_options)?;
+    let inline_math = render_embeds(&arena, root, content, &embeds, options, &mut diagnostics)?;
+    if content.contains(PLACEHOLDER_START) {
+        unescape_code(root);
+    }
+    if options.config.math {
+        // Blocks in the body of a shortcode were cut out along with it
+        let math_lines = code_blocks
+            .iter()
+            .filter(|block| block.is_math)
+            .filter(|block| {
+                !embeds
+                    .iter()
+                    .any(|embed| embed.range().contains(&block.bytes.start))
+            })
+            .map(|block| block.line + 1)
+            .collect::<Vec<_>>();
+        render_math_blocks(&arena, root, content, &math_lines, &mut diagnostics);
+    }
+    if let Some((links, path)) = options.links {
+        resolve_links(&arena, root, content, links, path, &mut diagnostics);
+    }
+    let headings = collect_headings(root, &inline_math);
+    for heading in &headings {
+        let html = render_heading(heading, options.config, &comrak_options)?;
+        replace_with_html(&arena, heading.node, html);
+    }
+    let toc = nest(
+        headings
+            .iter()
+            .filter(|heading| heading.level <= options.config.toc_depth)
+            .map(|heading| TocEntry {
+                level: heading.level,
+                id: heading.id.clone(),
+                title: heading.title.clone(),
+                children: Vec::new(),
+            }),
+    );
+    for marker in root
+        .children()
+        .filter(|node| is_toc_marker(node))
+        .collect::<Vec<_>>()
+    {
+        match options.toc && !toc.is_empty() {
+            true => replace_with_html(&arena, marker, toc_html(&toc)),
+            false => marker.detach(),
+        }
+    }
+    Ok(Document {
+        html: render(root, &comrak_options)?,
+        toc: if options.toc { toc } else { Vec::new() },
+        diagnostics,
+    })
+}
+
+/// Turns text into a lowercase, dash-separated slug, e.g. `Hello, World!` into `hello-world`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Omits raw HTML and clears dangerous URLs, as comrak does unless rendering unsafely.
+fn sanitize<'a>(root: &'a AstNode<'a>) {
+    for node in root.descendants() {
+        match &mut node.data.borrow_mut().value {
+            NodeValue::HtmlBlock(block) => block.literal = OMITTED_HTML.to_vec(),
+            NodeValue::HtmlInline(literal) => *literal = OMITTED_HTML.to_vec(),
+            NodeValue::Link(link) | NodeValue::Image(link) if is_dangerous_url(&link.url) => {
+                link.url.clear()
+            }
+            _ => (),
+        }
+    }
+}
+
+fn is_dangerous_url(url: &[u8]) -> bool {
+    let url = String::from_utf8_lossy(url).to_ascii_lowercase();
+    if let Some(data) = url.strip_prefix("data:") {
+        return !["image/png", "image/gif", "image/jpeg", "image/webp"]
+            .iter()
+            .any(|image| data.starts_with(image));
+    }
+    ["javascript:", "vbscript:", "file:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+/// A code block in a post, which math isn't looked for in.
+struct CodeBlock {
+    bytes: Range<usize>,
+    /// Line of the opening fence, or first line if indented.
+    line: usize,
+    is_math: bool,
+}
+
+fn scan_code_blocks(content: &str, options: &ComrakOptions) -> Vec<CodeBlock> {
+    let line_starts = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(index, _)| index + 1))
+        .collect::<Vec<_>>();
+    let line_start = |line: usize| {
+        line_starts
+            .get(line.saturating_sub(1))
+            .copied()
+            .unwrap_or(content.len())
+    };
+    let arena = Arena::new();
+    let root = parse_document(&arena, content, options);
+    let mut blocks = Vec::new();
+    for node in root.descendants() {
+        let ast = node.data.borrow();
+        let block = match &ast.value {
+            NodeValue::CodeBlock(block) => block,
+            _ => continue,
+        };
+        let line = ast.start_line as usize;
+        let body_lines = block.literal.iter().filter(|&&b| b == b'\n').count();
+        // Fenced blocks span their fences, which may be missing at the end of the post
+        let last_line = match block.fenced {
+            true => line + body_lines + 1,
+            false => line + body_lines - 1,
+        };
+        blocks.push(CodeBlock {
+            bytes: line_start(line)..line_start(last_line + 1),
+            line,
+            is_math: block.fenced && block.info == b"math",
+        });
+    }
+    blocks
+}
+
+/// Math, a shortcode or a callout fence, cut out of the Markdown before it is parsed.
+enum Embed {
+    Math(math::Math),
+    Shortcode(Shortcode),
+    Fence(callouts::Fence),
+}
+
+impl Embed {
+    fn range(&self) -> Range<usize> {
+        match self {
+            Embed::Math(math) => math.range.clone(),
+            Embed::Shortcode(shortcode) => shortcode.range.clone(),
+            Embed::Fence(fence) => fence.range.clone(),
+        }
+    }
+}
+
+/// Finds the shortcodes, callout fences and math of a post, in order, reporting malformed
+/// shortcodes and callouts.
+fn find_embeds(
+    content: &str,
+    code_blocks: &[CodeBlock],
+    options: &ConvertOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Embed> {
+    let mut skip = code_blocks
+        .iter()
+        .map(|block| block.bytes.clone())
+        .collect::<Vec<_>>();
+    let mut embeds = Vec::new();
+    if options.shortcodes.is_some() {
+        skip.extend(code_spans(content, &skip));
+        let (found, errors) = shortcodes::find(content, &skip);
+        for (message, offset) in errors {
+            diagnostics.push(shortcodes::syntax_error(message, content, offset));
+        }
+        skip.extend(found.iter().map(|shortcode| shortcode.range.clone()));
+        embeds.extend(found.into_iter().map(Embed::Shortcode));
+    }
+    let (fences, errors) = callouts::find(content, &skip, options.config);
+    for (message, offset) in errors {
+        let (line, column) = position(content, offset);
+        diagnostics.push(
+            Diagnostic::error(message)
+                .at(Some(line), Some(column))
+                .with_source(content),
+        );
+    }
+    skip.extend(fences.iter().map(|fence| fence.range.clone()));
+    embeds.extend(fences.into_iter().map(Embed::Fence));
+    if options.config.math {
+        embeds.extend(math::find(content, &skip).into_iter().map(Embed::Math));
+    }
+    embeds.sort_by_key(|embed| embed.range().start);
+    embeds
+}
+
+/// Byte ranges of the code spans outside of the `code` blocks.
+fn code_spans(content: &str, code: &[Range<usize>]) -> Vec<Range<usize>> {
+    let bytes = content.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(block) = code.iter().find(|block| block.contains(&i)) {
+            i = block.end;
+            continue;
+        }
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => {
+                let end = skip_code_span(bytes, i);
+                spans.push(i..end);
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    spans
+}
+
+/// Skips a code span starting at `start`, or just its opening backticks if it isn't closed.
+fn skip_code_span(bytes: &[u8], start: usize) -> usize {
+    let run = bytes[start..].iter().take_while(|&&b| b == b'`').count();
+    let mut i = start + run;
+    while i < bytes.len() {
+        if bytes[i] == b'`' {
+            let closing = bytes[i..].iter().take_while(|&&b| b == b'`').count();
+            if closing == run {
+                return i + closing;
+            }
+            i += closing;
+        } else {
+            i += 1;
+        }
+    }
+    start + run
+}
+
+/// Replaces each embed with a placeholder holding its index, escaping placeholder characters
+/// already in the post.
+fn with_placeholders(content: &str, embeds: &[Embed]) -> String {
+    let mut source = String::with_capacity(content.len());
+    let push_escaped = |source: &mut String, text: &str| match text.contains(PLACEHOLDER_START) {
+        true => source.push_str(&text.replace(PLACEHOLDER_START, ESCAPED_START)),
+        false => source.push_str(text),
+    };
+    let mut copied = 0;
+    for (index, embed) in embeds.iter().enumerate() {
+        let range = embed.range();
+        push_escaped(&mut source, &content[copied..range.start]);
+        let placeholder = format!("{}{}{}", PLACEHOLDER_START, index, PLACEHOLDER_END);
+        match embed {
+            // A paragraph of its own, even if the fence is next to text
+            Embed::Fence(_) => source.push_str(&format!("\n{}\n", placeholder)),
+            _ => source.push_str(&placeholder),
+        }
+        copied = range.end;
+    }
+    push_escaped(&mut source, &content[copied..]);
+    source
+}
+
+/// Splits text into the parts before, between and after placeholders, which are returned as
+/// indices of the `count` embeds they stand for.
+///
+/// Escaped placeholder characters and placeholders without a matching embed are kept as text.
+fn split_placeholders(text: &str, count: usize) -> Vec<Result<&str, usize>> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let literal_end = start + PLACEHOLDER_START.len_utf8();
+        let after = &rest[literal_end..];
+        if let Some(escaped) = after.strip_prefix(PLACEHOLDER_END) {
+            parts.push(Ok(&rest[..literal_end]));
+            rest = escaped;
+            continue;
+        }
+        let index = after
+            .find(PLACEHOLDER_END)
+            .and_then(|end| Some((after[..end].parse::<usize>().ok()?, end)))
+            .filter(|&(index, _)| index < count);
+        match index {
+            Some((index, end)) => {
+                if start > 0 {
+                    parts.push(Ok(&rest[..start]));
+                }
+                parts.push(Err(index));
+                rest = &after[end + PLACEHOLDER_END.len_utf8()..];
+            }
+            None => {
+                parts.push(Ok(&rest[..literal_end]));
+                rest = after;
+            }
+        }
+    }
+    if !rest.is_empty() {
+        parts.push(Ok(rest));
+    }
+    parts
+}
+
+/// Swaps placeholders for the HTML of what they stand for.
+///
+/// Display math, shortcodes and fences that make up a whole paragraph replace the paragraph.
+/// Returns the nodes of inline math along with their LaTeX, which stands for them in the text
+/// of headings.
+fn render_embeds<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    content: &str,
+    embeds: &[Embed],
+    options: &ConvertOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<Vec<(&'a AstNode<'a>, String)>> {
+    let mut inline_math = Vec::new();
+    if embeds.is_empty() && !content.contains(PLACEHOLDER_START) {
+        return Ok(inline_math);
+    }
+    for node in root.descendants().collect::<Vec<_>>() {
+        let text = match &node.data.borrow().value {
+            NodeValue::Text(text) => String::from_utf8_lossy(text).into_owned(),
+            _ => continue,
+        };
+        if !text.contains(PLACEHOLDER_START) {
+            continue;
+        }
+        let parts = split_placeholders(&text, embeds.len());
+        let is_alone = parts.len() == 1
+            && node.parent().is_some_and(|parent| {
+                matches!(parent.data.borrow().value, NodeValue::Paragraph)
+                    && parent.children().count() == 1
+            });
+        for part in parts {
+            let new_node = match part {
+                Ok(text) => arena.alloc(AstNode::from(NodeValue::Text(text.as_bytes().to_vec()))),
+                Err(index) => {
+                    let (html, is_block) = match &embeds[index] {
+                        Embed::Math(math) => {
+                            let html = math_to_html(
+                                &math.latex,
+                                math.display,
+                                content,
+                                &|offset| position(content, math.offset + offset),
+                                diagnostics,
+                            );
+                            (html, math.display)
+                        }
+                        Embed::Shortcode(shortcode) => (
+                            expand_shortcode(shortcode, content, options, diagnostics)?,
+                            true,
+                        ),
+                        Embed::Fence(fence) => (fence.html(options.config), true),
+                    };
+                    if is_alone && is_block {
+                        let paragraph = node.parent().expect("checked above");
+                        replace_with_html(arena, paragraph, html + "\n");
+                        break;
+                    }
+                    let new_node: &'a AstNode<'a> =
+                        arena.alloc(AstNode::from(NodeValue::HtmlInline(html.into_bytes())));
+                    if let Embed::Math(math) = &embeds[index] {
+                        inline_math.push((new_node, math.latex.trim().to_string()));
+                    }
+                    new_node
+                }
+            };
+            node.insert_before(new_node);
+        }
+        node.detach();
+    }
+    Ok(inline_math)
+}
+
+/// Restores the placeholder characters escaped in code, which holds no embeds.
+fn unescape_code<'a>(root: &'a AstNode<'a>) {
+    let unescape = |literal: &mut Vec<u8>| {
+        let text = String::from_utf8_lossy(literal);
+        if text.contains(ESCAPED_START) {
+            *literal = text
+                .replace(ESCAPED_START, &PLACEHOLDER_START.to_string())
+                .into_bytes();
+        }
+    };
+    for node in root.descendants() {
+        match &mut node.data.borrow_mut().value {
+            NodeValue::Code(code) => unescape(&mut code.literal),
+            NodeValue::CodeBlock(block) => unescape(&mut block.literal),
+            _ => (),
+        }
+    }
+}
+
+/// Swaps `math` code blocks for MathML.
+///
+/// `math_lines` holds the first line of each block's body in the original post.
+fn render_math_blocks<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    content: &str,
+    math_lines: &[usize],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut math_blocks = math_lines.iter();
+    for node in root.descendants().collect::<Vec<_>>() {
+        let latex = match &node.data.borrow().value {
+            NodeValue::CodeBlock(block) if block.fenced && block.info == b"math" => {
+                String::from_utf8_lossy(&block.literal).into_owned()
+            }
+            _ => continue,
+        };
+        let first_line = math_blocks.next().copied().unwrap_or(1);
+        let html = math_to_html(
+            &latex,
+            true,
+            content,
+            &|offset| {
+                let (line, column) = position(&latex, offset);
+                (first_line + line - 1, column)
+            },
+            diagnostics,
+        );
+        replace_with_html(arena, node, html + "\n");
+    }
+}
+
+/// Converts LaTeX to MathML, or reports it at the position of the error and shows the LaTeX
+/// as code.
+fn math_to_html(
+    latex: &str,
+    display: bool,
+    content: &str,
+    position: &dyn Fn(usize) -> (usize, usize),
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    math::to_mathml(latex, display).unwrap_or_else(|(message, range)| {
+        let (line, column) = position(range.start);
+        diagnostics.push(
+            Diagnostic::error(format!(
+                "Invalid math `{}`: {}",
+                latex.split_whitespace().collect::<Vec<_>>().join(" "),
+                message
+            ))
+            .at(Some(line), Some(column))
+            .with_source(content),
+        );
+        format!("<code>{}</code>", handlebars::html_escape(latex))
+    })
+}
+
+/// Renders a shortcode after converting its body, which is a paragraph of inline Markdown
+/// if it's on a single line.
+fn expand_shortcode(
+    shortcode: &Shortcode,
+    content: &str,
+    options: &ConvertOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> anyhow::Result<String> {
+    let body = match &shortcode.body {
+        Some(body) => {
+            let document = convert(body, &options.toc(false))?;
+            let (line, column) = position(content, shortcode.body_offset);
+            diagnostics.extend(
+                document
+                    .diagnostics
+                    .into_iter()
+                    .map(|diagnostic| shift(diagnostic, line, column, content)),
+            );
+            let html = match body.contains('\n') {
+                true => None,
+                false => document
+                    .html
+                    .strip_prefix("<p>")
+                    .and_then(|html| html.strip_suffix("</p>\n"))
+                    .filter(|html| !html.contains("<p>"))
+                    .map(str::to_string),
+            };
+            Some(html.unwrap_or(document.html))
+        }
+        None => None,
+    };
+    Ok(shortcodes::render(
+        shortcode,
+        body,
+        content,
+        options,
+        diagnostics,
+    ))
+}
+
+/// Moves a diagnostic about a part of `content` starting at `line` and `column` to its place
+/// in `content`.
+pub(crate) fn shift(
+    mut diagnostic: Diagnostic,
+    line: usize,
+    column: usize,
+    content: &str,
+) -> Diagnostic {
+    let inner_line = match diagnostic.line {
+        Some(inner_line) => inner_line,
+        None => return diagnostic,
+    };
+    if inner_line == 1 {
+        diagnostic.column = diagnostic.column.map(|inner| column + inner - 1);
+    }
+    diagnostic.line = Some(line + inner_line - 1);
+    diagnostic.with_source(content)
+}
+
+/// Points links to other posts at their URL, and turns `[[Other Post]]` into such links.
+fn resolve_links<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    content: &str,
+    links: &PostLinks,
+    path: &Path,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // Inline nodes carry no position, so problems are reported at the first occurrence
+    let mut report = |message: String, needle: &str| {
+        let mut diagnostic = Diagnostic::error(message);
+        if let Some(offset) = content.find(needle) {
+            let (line, column) = position(content, offset);
+            diagnostic = diagnostic.at(Some(line), Some(column)).with_source(content);
+        }
+        diagnostics.push(diagnostic);
+    };
+    for node in root.descendants().collect::<Vec<_>>() {
+        let is_in_link = node
+            .ancestors()
+            .skip(1)
+            .any(|ancestor| matches!(ancestor.data.borrow().value, NodeValue::Link(_)));
+        let text = match &mut node.data.borrow_mut().value {
+            NodeValue::Link(link) => {
+                let url = String::from_utf8_lossy(&link.url).into_owned();
+                match links.resolve_url(path, &url) {
+                    Resolved::Post(_, resolved) => link.url = resolved.into_bytes(),
+                    Resolved::Dangling => {
+                        report(format!("Link to `{}` doesn't match any post", url), &url)
+                    }
+                    Resolved::Other => (),
+                }
+                continue;
+            }
+            NodeValue::Text(text) if !is_in_link => String::from_utf8_lossy(text).into_owned(),
+            _ => continue,
+        };
+        let wiki_links = links::find_wiki_links(&text);
+        if wiki_links.is_empty() {
+            continue;
+        }
+        let mut copied = 0;
+        for wiki_link in wiki_links {
+            let url = match links.resolve_wiki(wiki_link.target) {
+                Some((_, url)) => url,
+                None => {
+                    let source = &text[wiki_link.range.clone()];
+                    report(
+                        format!(
+                            "Wiki link `{}` doesn't match any post's title or file",
+                            source
+                        ),
+                        source,
+                    );
+                    continue;
+                }
+            };
+            let before = &text[copied..wiki_link.range.start];
+            if !before.is_empty() {
+                node.insert_before(arena.alloc(AstNode::from(NodeValue::Text(before.into()))));
+            }
+            let link = arena.alloc(AstNode::from(NodeValue::Link(NodeLink {
+                url: url.into_bytes(),
+                title: Vec::new(),
+            })));
+            link.append(arena.alloc(AstNode::from(NodeValue::Text(wiki_link.label.into()))));
+            node.insert_before(link);
+            copied = wiki_link.range.end;
+        }
+        if let NodeValue::Text(rest) = &mut node.data.borrow_mut().value {
+            *rest = text[copied..].into();
+        }
+    }
+}
+
+/// The 1-based line and column of a byte offset.
+pub(crate) fn position(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+struct Heading<'a> {
+    node: &'a AstNode<'a>,
+    level: u32,
+    id: String,
+    title: String,
+}
+
+/// Finds all headings and assigns each a unique id, e.g. `setup`, `setup-1`, `setup-2`.
+///
+/// Inline math in headings counts as its LaTeX, see [`text_of`].
+fn collect_headings<'a>(
+    root: &'a AstNode<'a>,
+    inline_math: &[(&'a AstNode<'a>, String)],
+) -> Vec<Heading<'a>> {
+    let mut ids = HashSet::new();
+    let mut headings = Vec::new();
+    for node in root.descendants() {
+        let level = match &node.data.borrow().value {
+            NodeValue::Heading(heading) => heading.level,
+            _ => continue,
+        };
+        let explicit_id = take_explicit_id(node);
+        let title = text_of(node, inline_math).trim().to_string();
+        let base = explicit_id.unwrap_or_else(|| match slugify(&title) {
+            slug if slug.is_empty() => "section".to_string(),
+            slug => slug,
+        });
+        let mut id = base.clone();
+        let mut suffix = 0;
+        while !ids.insert(id.clone()) {
+            suffix += 1;
+            id = format!("{}-{}", base, suffix);
+        }
+        headings.push(Heading {
+            node,
+            level,
+            id,
+            title,
+        });
+    }
+    headings
+}
+
+/// Removes a trailing `{#id}` from a heading's text and returns the id.
+fn take_explicit_id<'a>(heading: &'a AstNode<'a>) -> Option<String> {
+    let last = heading.last_child()?;
+    let mut ast = last.data.borrow_mut();
+    let text = match &mut ast.value {
+        NodeValue::Text(text) => text,
+        _ => return None,
+    };
+    let string = String::from_utf8_lossy(text).into_owned();
+    let (rest, id) = string.trim_end().strip_suffix('}')?.rsplit_once("{#")?;
+    let is_valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_:.".contains(c));
+    if !is_valid {
+        return None;
+    }
+    let id = id.to_string();
+    *text = rest.trim_end().as_bytes().to_vec();
+    Some(id)
+}
+
+/// The plain text of a node, with `inline_math` nodes standing for their LaTeX.
+fn text_of<'a>(node: &'a AstNode<'a>, inline_math: &[(&'a AstNode<'a>, String)]) -> String {
+    node.descendants()
+        .filter_map(|node| match &node.data.borrow().value {
+            NodeValue::Text(text)
+            | NodeValue::Code(comrak::nodes::NodeCode { literal: text, .. }) => {
+                Some(String::from_utf8_lossy(text).into_owned())
+            }
+            NodeValue::HtmlInline(_) => inline_math
+                .iter()
+                .find(|(math, _)| std::ptr::eq(*math, node))
+                .map(|(_, latex)| latex.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn render_heading(
+    heading: &Heading,
+    config: &MarkdownConfig,
+    options: &ComrakOptions,
+) -> anyhow::Result<String> {
+    let html = render(heading.node, options)?;
+    let open = format!("<h{}>", heading.level);
+    let close = format!("</h{}>", heading.level);
+    let content = html
+        .trim_end()
+        .strip_prefix(&open)
+        .and_then(|html| html.strip_suffix(&close))
+        .unwrap_or_default();
+    let anchor = format!(
+        "<a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\">{}</a>",
+        heading.id,
+        handlebars::html_escape(&config.anchor_symbol)
+    );
+    let content = match config.anchor_links {
+        AnchorPosition::None => content.to_string(),
+        AnchorPosition::Before => format!("{} {}", anchor, content),
+        AnchorPosition::After => format!("{} {}", content, anchor),
+    };
+    Ok(format!(
+        "<h{level} id=\"{}\">{}</h{level}>\n",
+        heading.id,
+        content,
+        level = heading.level
+    ))
+}
+
+/// Nests headings below the closest preceding heading of a higher level.
+fn nest(entries: impl Iterator<Item = TocEntry>) -> Vec<TocEntry> {
+    fn insert(siblings: &mut Vec<TocEntry>, entry: TocEntry) {
+        match siblings.last_mut() {
+            Some(last) if last.level < entry.level => insert(&mut last.children, entry),
+            _ => siblings.push(entry),
+        }
+    }
+
+    let mut toc = Vec::new();
+    for entry in entries {
+        insert(&mut toc, entry);
+    }
+    toc
+}
+
+fn toc_html(toc: &[TocEntry]) -> String {
+    fn list(entries: &[TocEntry], html: &mut String) {
+        html.push_str("<ul>\n");
+        for entry in entries {
+            html.push_str(&format!(
+                "<li><a href=\"#{}\">{}</a>",
+                entry.id,
+                handlebars::html_escape(&entry.title)
+            ));
+            if !entry.children.is_empty() {
+                html.push('\n');
+                list(&entry.children, html);
+            }
+            html.push_str("</li>\n");
+        }
+        html.push_str("</ul>\n");
+    }
+
+    let mut html = String::from("<nav class=\"toc\">\n");
+    list(toc, &mut html);
+    html.push_str("</nav>\n");
+    html
+}
+
+fn is_toc_marker<'a>(node: &'a AstNode<'a>) -> bool {
+    matches!(node.data.borrow().value, NodeValue::Paragraph)
+        && node
+            .children()
+            .all(|child| matches!(child.data.borrow().value, NodeValue::Text(_)))
+        && text_of(node, &[]).trim() == TOC_MARKER
+}
+
+/// Replaces a block with generated HTML.
+fn replace_with_html<'a>(arena: &'a Arena<AstNode<'a>>, node: &'a AstNode<'a>, html: String) {
+    node.insert_before(html_block(arena, html));
+    node.detach();
+}
+
+fn html_block<'a>(arena: &'a Arena<AstNode<'a>>, html: String) -> &'a AstNode<'a> {
+    arena.alloc(AstNode::from(NodeValue::HtmlBlock(NodeHtmlBlock {
+        block_type: 0,
+        literal: html.into_bytes(),
+    })))
+}
+
+fn render<'a>(node: &'a AstNode<'a>, options: &ComrakOptions) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    format_html(node, options, &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(content: &str) -> String {
+        convert(content, &ConvertOptions::new(&MarkdownConfig::default()))
+            .unwrap()
+            .html
+    }
+
+    fn toc(content: &str) -> Vec<(String, String)> {
+        fn flatten(entries: &[TocEntry], out: &mut Vec<(String, String)>) {
+            for entry in entries {
+                out.push((entry.id.clone(), entry.title.clone()));
+                flatten(&entry.children, out);
+            }
+        }
+        let document = convert(content, &ConvertOptions::new(&MarkdownConfig::default())).unwrap();
+        let mut entries = Vec::new();
+        flatten(&document.toc, &mut entries);
+        entries
+    }
+
+    #[test]
+    fn headings_get_unique_ids() {
+        let entries =
+            toc("# Setup\n\n## Setup\n\n## Setup\n\n## Other {#custom}\n\n## `code` *here*\n");
+        let ids = entries
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["setup", "setup-1", "setup-2", "custom", "code-here"]);
+        assert_eq!(entries[3].1, "Other");
+    }
+
+    #[test]
+    fn headings_keep_their_inline_math() {
+        let entries = toc("## The $O(n)$ bound\n");
+        assert_eq!(
+            entries,
+            [("the-o-n-bound".to_string(), "The O(n) bound".to_string())]
+        );
+        assert!(html("## The $O(n)$ bound\n").contains("<h2 id=\"the-o-n-bound\">The <math"));
+    }
+
+    #[test]
+    fn indented_fences_leave_lists_intact() {
+        let html = html("- One\n\n  :::note\n  Text\n  :::\n- Two\n");
+        assert!(!html.contains("<aside"), "{}", html);
+        assert_eq!(html.matches("<ul>").count(), 1, "{}", html);
+        assert_eq!(html.matches("<li>").count(), 2, "{}", html);
+    }
+
+    #[test]
+    fn placeholder_characters_in_posts_are_kept_as_text() {
+        let forged = "Costs \u{E000}7\u{E001} and $x$";
+        let html = html(forged);
+        assert!(html.contains("Costs \u{E000}7\u{E001} and"), "{}", html);
+        assert!(html.contains("<math"), "{}", html);
+    }
+
+    #[test]
+    fn placeholder_characters_cannot_stand_for_other_embeds() {
+        let html = html("\u{E000}0\u{E001} before $y$");
+        assert!(html.contains("\u{E000}0\u{E001} before"), "{}", html);
+        assert_eq!(html.matches("<math").count(), 1, "{}", html);
+    }
+
+    #[test]
+    fn placeholder_characters_in_code_are_kept() {
+        let html = html("`\u{E000}1\u{E001}` $z$\n\n```\n\u{E000}\n```\n");
+        assert!(html.contains("<code>\u{E000}1\u{E001}</code>"), "{}", html);
+        assert!(html.contains("<code>\u{E000}\n</code>"), "{}", html);
+    }
+
+    #[test]
+    fn out_of_range_placeholders_are_text() {
+        assert_eq!(
+            split_placeholders("a\u{E000}0\u{E001}b\u{E000}3\u{E001}", 1),
+            [Ok("a"), Err(0), Ok("b\u{E000}"), Ok("3\u{E001}")]
+        );
+    }
+}
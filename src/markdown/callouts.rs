@@ -0,0 +1,311 @@
+//! Callouts, written as GitHub-style `> [!NOTE]` blockquotes or `:::note` containers.
+//!
+//! Both become `<aside class="callout callout-note">` starting with a
+//! `<p class="callout-title">`. Besides the built-in types, `config.toml` can declare more
+//! under `[markdown.callouts]`. Blockquotes of an unknown type stay blockquotes, as on GitHub.
+
+use std::ops::Range;
+
+use comrak::{nodes::NodeValue, Arena, ComrakOptions};
+
+use super::{html_block, render, AstNode, MarkdownConfig};
+
+/// The types GitHub supports, with their titles.
+static BUILTIN: [(&str, &str); 5] = [
+    ("note", "Note"),
+    ("tip", "Tip"),
+    ("important", "Important"),
+    ("warning", "Warning"),
+    ("caution", "Caution"),
+];
+
+static CLOSE_HTML: &str = "</aside>";
+
+/// The title of a callout type, in any case, or `None` if it's unknown.
+fn default_title(config: &MarkdownConfig, kind: &str) -> Option<String> {
+    let kind = kind.to_lowercase();
+    config.callouts.get(&kind).cloned().or_else(|| {
+        BUILTIN
+            .iter()
+            .find(|(name, _)| *name == kind)
+            .map(|(_, title)| title.to_string())
+    })
+}
+
+/// All callout types, for error messages.
+fn names(config: &MarkdownConfig) -> Vec<String> {
+    let mut names = BUILTIN
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(config.callouts.keys().cloned())
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// The opening tags of a callout, with its title already converted to HTML.
+fn open_html(kind: &str, title: &str) -> String {
+    format!(
+        "<aside class=\"callout callout-{}\">\n<p class=\"callout-title\">{}</p>",
+        kind.to_lowercase(),
+        title
+    )
+}
+
+/// A `:::type Title` line opening a container, or a `:::` line closing one.
+#[derive(Debug)]
+pub(super) struct Fence {
+    /// The line without its line break.
+    pub range: Range<usize>,
+    pub open: Option<(String, String)>,
+}
+
+impl Fence {
+    pub(super) fn html(&self, config: &MarkdownConfig) -> String {
+        match &self.open {
+            Some((kind, title)) => {
+                let title = match title.is_empty() {
+                    true => handlebars::html_escape(
+                        &default_title(config, kind).unwrap_or_else(|| kind.clone()),
+                    ),
+                    false => inline_html(title),
+                };
+                open_html(kind, &title)
+            }
+            None => CLOSE_HTML.to_string(),
+        }
+    }
+}
+
+/// Finds the fences of all containers outside the `skip` ranges, along with errors and the
+/// byte offsets they occurred at.
+///
+/// A `:::` line that doesn't close a container is left as text, and so are indented fences,
+/// which belong to a list item or code block the container can't be placed in.
+pub(super) fn find(
+    content: &str,
+    skip: &[Range<usize>],
+    config: &MarkdownConfig,
+) -> (Vec<Fence>, Vec<(String, usize)>) {
+    let mut fences = Vec::new();
+    let mut errors = Vec::new();
+    // Indices of the fences of open containers
+    let mut open = Vec::new();
+    let mut start = 0;
+    for line in content.split_inclusive('\n') {
+        let range = start..start + line.trim_end_matches(['\r', '\n']).len();
+        start += line.len();
+        if skip.iter().any(|skipped| skipped.contains(&range.start)) {
+            continue;
+        }
+        let rest = match line.trim_end().strip_prefix(":::") {
+            Some(rest) => rest.trim(),
+            None => continue,
+        };
+        if rest.is_empty() {
+            if open.pop().is_some() {
+                fences.push(Fence { range, open: None });
+            }
+            continue;
+        }
+        let (kind, title) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if default_title(config, kind).is_none() {
+            errors.push((
+                format!(
+                    "Unknown callout type `{}` (available: {})",
+                    kind,
+                    names(config).join(", ")
+                ),
+                range.start,
+            ));
+            continue;
+        }
+        open.push(fences.len());
+        fences.push(Fence {
+            range,
+            open: Some((kind.to_string(), title.trim().to_string())),
+        });
+    }
+    // Leaving out an unclosed container also leaves out what it contains
+    for index in open.into_iter().rev() {
+        let fence = fences.remove(index);
+        errors.push((
+            "Callout is never closed with a `:::` line".to_string(),
+            fence.range.start,
+        ));
+    }
+    (fences, errors)
+}
+
+/// Turns blockquotes starting with `[!TYPE]` into callouts, with the rest of that line as the
+/// title.
+pub(super) fn render_blockquotes<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    root: &'a AstNode<'a>,
+    config: &MarkdownConfig,
+    options: &ComrakOptions,
+) -> anyhow::Result<()> {
+    for node in root.descendants().collect::<Vec<_>>() {
+        if !matches!(node.data.borrow().value, NodeValue::BlockQuote) {
+            continue;
+        }
+        let paragraph = match node.first_child() {
+            Some(paragraph) if matches!(paragraph.data.borrow().value, NodeValue::Paragraph) => {
+                paragraph
+            }
+            _ => continue,
+        };
+        let first = match paragraph.first_child() {
+            Some(first) => first,
+            None => continue,
+        };
+        let (kind, default) = {
+            let mut ast = first.data.borrow_mut();
+            let text = match &mut ast.value {
+                NodeValue::Text(text) => text,
+                _ => continue,
+            };
+            let string = String::from_utf8_lossy(text).into_owned();
+            let (kind, rest) = match string
+                .strip_prefix("[!")
+                .and_then(|marker| marker.split_once(']'))
+            {
+                Some(marker) => marker,
+                None => continue,
+            };
+            let default = match default_title(config, kind) {
+                Some(default) => default,
+                None => continue,
+            };
+            *text = rest.trim_start().as_bytes().to_vec();
+            (kind.to_string(), default)
+        };
+        // The title is what follows the marker on its line
+        let title = arena.alloc(AstNode::from(NodeValue::Paragraph));
+        for child in paragraph.children().collect::<Vec<_>>() {
+            let is_break = matches!(
+                child.data.borrow().value,
+                NodeValue::SoftBreak | NodeValue::LineBreak
+            );
+            child.detach();
+            if is_break {
+                break;
+            }
+            title.append(child);
+        }
+        if paragraph.first_child().is_none() {
+            paragraph.detach();
+        }
+        // Paragraphs are only rendered inside a document
+        let document = arena.alloc(AstNode::from(NodeValue::Document));
+        document.append(title);
+        let title = render(document, options)?;
+        let title = title
+            .trim_end()
+            .trim_start_matches("<p>")
+            .trim_end_matches("</p>");
+        let title = match title.is_empty() {
+            true => handlebars::html_escape(&default),
+            false => title.to_string(),
+        };
+        node.insert_before(html_block(arena, open_html(&kind, &title) + "\n"));
+        for child in node.children().collect::<Vec<_>>() {
+            node.insert_before(child);
+        }
+        node.insert_before(html_block(arena, format!("{}\n", CLOSE_HTML)));
+        node.detach();
+    }
+    Ok(())
+}
+
+/// Converts a line of Markdown to HTML without a paragraph around it.
+fn inline_html(markdown: &str) -> String {
+    let html = comrak::markdown_to_html(markdown, &ComrakOptions::default());
+    html.trim_end()
+        .trim_start_matches("<p>")
+        .trim_end_matches("</p>")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(content: &str) -> Vec<Option<String>> {
+        let (fences, errors) = find(content, &[], &MarkdownConfig::default());
+        assert!(errors.is_empty(), "{:?}", errors);
+        fences
+            .into_iter()
+            .map(|fence| fence.open.map(|(kind, _)| kind))
+            .collect()
+    }
+
+    #[test]
+    fn containers_are_found_with_their_titles() {
+        let content = "Intro\n\n:::warning Mind *this*\nText\n:::\n";
+        let (fences, errors) = find(content, &[], &MarkdownConfig::default());
+        assert!(errors.is_empty());
+        assert_eq!(fences.len(), 2);
+        assert_eq!(
+            fences[0].open,
+            Some(("warning".to_string(), "Mind *this*".to_string()))
+        );
+        assert_eq!(&content[fences[0].range.clone()], ":::warning Mind *this*");
+        assert_eq!(&content[fences[1].range.clone()], ":::");
+    }
+
+    #[test]
+    fn containers_nest() {
+        let content = ":::note\n:::tip\nText\n:::\n:::\n";
+        assert_eq!(
+            kinds(content),
+            [
+                Some("note".to_string()),
+                Some("tip".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn stray_closing_fences_are_text() {
+        assert!(kinds("Text\n:::\n").is_empty());
+    }
+
+    #[test]
+    fn indented_fences_are_text() {
+        assert!(kinds("- Item\n\n  :::note\n  Text\n  :::\n- Second\n").is_empty());
+        assert!(kinds("    :::note\n    :::\n").is_empty());
+    }
+
+    #[test]
+    fn skipped_ranges_hold_no_fences() {
+        let content = "```\n:::note\n:::\n```\n";
+        let code_block = 0..content.len();
+        let (fences, errors) = find(
+            content,
+            std::slice::from_ref(&code_block),
+            &MarkdownConfig::default(),
+        );
+        assert!(fences.is_empty() && errors.is_empty());
+    }
+
+    #[test]
+    fn unknown_and_unclosed_containers_are_errors() {
+        let (fences, errors) = find(":::nope\n:::note\n", &[], &MarkdownConfig::default());
+        assert!(fences.is_empty());
+        let messages = errors
+            .iter()
+            .map(|(message, _)| message.as_str())
+            .collect::<Vec<_>>();
+        assert!(
+            messages[0].starts_with("Unknown callout type `nope`"),
+            "{:?}",
+            messages
+        );
+        assert_eq!(messages[1], "Callout is never closed with a `:::` line");
+        assert_eq!(errors[1].1, 8);
+    }
+}
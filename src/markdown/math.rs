@@ -0,0 +1,176 @@
+//! LaTeX math in `$...$`, `$$...$$` and ```` ```math ```` blocks, converted to MathML.
+//!
+//! Dollar-delimited math is cut out of the Markdown before it is parsed, so that LaTeX
+//! backslashes, underscores and asterisks aren't taken for Markdown.
+
+use std::{ops::Range, sync::OnceLock};
+
+use math_core::{LatexToMathML, MathCoreConfig, MathDisplay};
+
+/// A math expression found in a post.
+#[derive(Debug)]
+pub(super) struct Math {
+    pub latex: String,
+    pub display: bool,
+    /// Byte offset of `latex` in the post.
+    pub offset: usize,
+    /// Bytes of the expression including its delimiters.
+    pub range: Range<usize>,
+}
+
+/// Finds every `$...$` and `$$...$$` outside of the `skip` ranges and code spans.
+///
+/// `skip` holds the byte ranges of code blocks and shortcodes. Inline math ends at the next `$` on the same
+/// line, which mustn't be preceded by whitespace or followed by a digit, and the opening `$`
+/// mustn't be followed by whitespace. This keeps prices like `$5 or $10` text.
+pub(super) fn find(content: &str, skip: &[Range<usize>]) -> Vec<Math> {
+    let bytes = content.as_bytes();
+    let mut expressions = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(block) = skip.iter().find(|block| block.contains(&i)) {
+            i = block.end;
+            continue;
+        }
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => i = super::skip_code_span(bytes, i),
+            b'$' => {
+                let display = bytes.get(i + 1) == Some(&b'$');
+                let delimiter = if display { 2 } else { 1 };
+                let start = i + delimiter;
+                let end = match display {
+                    true => find_display_end(content, start),
+                    false => find_inline_end(bytes, start),
+                };
+                match end {
+                    Some(end) => {
+                        expressions.push(Math {
+                            latex: content[start..end].to_string(),
+                            display,
+                            offset: start,
+                            range: i..end + delimiter,
+                        });
+                        i = end + delimiter;
+                    }
+                    None => i += delimiter,
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    expressions
+}
+
+fn find_inline_end(bytes: &[u8], start: usize) -> Option<usize> {
+    if bytes
+        .get(start)
+        .is_none_or(|b| b.is_ascii_whitespace() || *b == b'$')
+    {
+        return None;
+    }
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' | b'`' => return None,
+            b'\\' => i += 2,
+            b'$' => {
+                let is_closing = !bytes[i - 1].is_ascii_whitespace()
+                    && !bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+                return is_closing.then_some(i);
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Finds the closing `$$`, which may be on a later line of the same paragraph.
+fn find_display_end(content: &str, start: usize) -> Option<usize> {
+    let end = start + content[start..].find("$$")?;
+    let is_same_paragraph = content[start..end]
+        .lines()
+        .skip(1)
+        .all(|line| !line.trim().is_empty());
+    is_same_paragraph.then_some(end)
+}
+
+/// Converts LaTeX to MathML, returning the error message and the byte range it refers to.
+pub(super) fn to_mathml(latex: &str, display: bool) -> Result<String, (String, Range<usize>)> {
+    static CONVERTER: OnceLock<LatexToMathML> = OnceLock::new();
+    let converter = CONVERTER.get_or_init(|| {
+        LatexToMathML::new(MathCoreConfig::default()).expect("no custom macros to parse")
+    });
+    let display = match display {
+        true => MathDisplay::Block,
+        false => MathDisplay::Inline,
+    };
+    converter
+        .convert_with_local_state(latex.trim(), display)
+        .map(|result| result.mathml)
+        .map_err(|err| {
+            // Errors refer to the trimmed expression
+            let leading = latex.len() - latex.trim_start().len();
+            let range = err.0.start + leading..err.0.end + leading;
+            (err.error_message(), range)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latex(content: &str) -> Vec<(&str, bool)> {
+        find(content, &[])
+            .into_iter()
+            .map(|math| {
+                (
+                    &content[math.offset..math.offset + math.latex.len()],
+                    math.display,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inline_and_display_math_are_found() {
+        let content = "Let $x^2$ be\n\n$$\n\\sum_i i\n$$\n";
+        assert_eq!(latex(content), [("x^2", false), ("\n\\sum_i i\n", true)]);
+        let math = find(content, &[]);
+        assert_eq!(&content[math[0].range.clone()], "$x^2$");
+        assert_eq!(&content[math[1].range.clone()], "$$\n\\sum_i i\n$$");
+    }
+
+    #[test]
+    fn prices_and_escaped_dollars_are_text() {
+        assert!(latex("It costs $5 or $10.").is_empty());
+        assert!(latex("Between $ 1 and 2 $").is_empty());
+        assert!(latex("Escaped \\$x\\$ dollars").is_empty());
+        assert!(latex("$x$5").is_empty());
+    }
+
+    #[test]
+    fn math_stays_within_its_line_or_paragraph() {
+        assert!(latex("$a\nb$").is_empty());
+        assert!(latex("$$a\n\nb$$").is_empty());
+        assert_eq!(latex("$$a\nb$$"), [("a\nb", true)]);
+    }
+
+    #[test]
+    fn code_and_skipped_ranges_hold_no_math() {
+        assert!(latex("`$x$` and ``$y$``").is_empty());
+        let content = "```\n$x$\n```\n$y$";
+        let code_block = 0..content.find("$y$").unwrap();
+        let math = find(content, std::slice::from_ref(&code_block));
+        assert_eq!(math.len(), 1);
+        assert_eq!(math[0].latex, "y");
+    }
+
+    #[test]
+    fn unclosed_delimiters_and_multibyte_text_are_handled() {
+        assert!(latex("$").is_empty());
+        assert!(latex("$$").is_empty());
+        assert!(latex("ends with \\").is_empty());
+        assert_eq!(latex("$é$ and $$ü$$"), [("é", false), ("ü", true)]);
+    }
+}
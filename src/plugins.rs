@@ -0,0 +1,442 @@
+//! Sandboxed WebAssembly plugins.
+//!
+//! Plugins are `.wasm` modules listed under `[[plugins]]` in `config.toml`.
+//! They run in an embedded interpreter without any access to the filesystem,
+//! network or clock, with bounded memory and a fuel budget per call.
+//!
+//! A plugin exports its linear `memory`, `nail_alloc(size: i32) -> i32` and
+//! `nail_run()`, which is called once per build after all posts were gathered.
+//! It may import the following functions from the `nail` module:
+//!
+//! - `post_count() -> i32`
+//! - `post_metadata(index, out_ptr, out_len) -> i32` writes the post's
+//!   metadata as JSON and returns its full length, or `-1` for an invalid index
+//! - `post_content(index, out_ptr, out_len) -> i32` does the same for the raw
+//!   post contents
+//! - `emit_file(path_ptr, path_len, contents_ptr, contents_len) -> i32` adds a
+//!   file to the bundle, returning `-1` if the path escapes the output directory
+//! - `register_helper(name_ptr, name_len, export_ptr, export_len)` makes the
+//!   exported function `export(args_ptr: i32, args_len: i32) -> i64` available
+//!   to templates as the helper `name`. It receives the helper's
+//!   `{"params": [...], "hash": {...}}` as JSON and returns the output as
+//!   `(ptr << 32) | len`
+//! - `log(ptr, len)` prints a message to the terminal
+
+use std::{
+    ops::Range,
+    path::{Component, Path},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use wasmi::{Caller, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::{
+    build::{BuildFile, Bundle, Post},
+    hooks::Hook,
+    theme::{
+        templating::{JsonMap, TemplateHelper},
+        Theme,
+    },
+};
+
+fn default_max_memory_mb() -> usize {
+    16
+}
+
+fn default_fuel() -> u64 {
+    1_000_000_000
+}
+
+/// A WebAssembly plugin configured under `[[plugins]]` in `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PluginConfig {
+    /// Path to the `.wasm` module, relative to the project root.
+    pub path: String,
+    /// Upper bound for the plugin's linear memory.
+    #[serde(default = "default_max_memory_mb")]
+    pub max_memory_mb: usize,
+    /// Instructions the plugin may execute per call before it is aborted.
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+}
+
+#[derive(Serialize)]
+struct PluginPostMetadata<'a> {
+    filename: &'a Path,
+    title: &'a str,
+    published: bool,
+    published_at: &'a str,
+}
+
+#[derive(Serialize)]
+struct HelperArgs {
+    params: Vec<serde_json::Value>,
+    hash: JsonMap,
+}
+
+struct PluginState {
+    limits: StoreLimits,
+    posts: Vec<Post>,
+    files: Vec<BuildFile>,
+    helpers: Vec<(String, String)>,
+}
+
+struct PluginRuntime {
+    store: Store<PluginState>,
+    instance: wasmi::Instance,
+    fuel: u64,
+}
+
+impl PluginRuntime {
+    fn refuel(&mut self) -> anyhow::Result<()> {
+        self.store
+            .set_fuel(self.fuel)
+            .map_err(|err| anyhow::anyhow!("{}", err))
+    }
+
+    fn call_run(&mut self) -> anyhow::Result<()> {
+        self.refuel()?;
+        let run = self
+            .instance
+            .get_typed_func::<(), ()>(&self.store, "nail_run")
+            .context("Plugin doesn't export `nail_run`")?;
+        run.call(&mut self.store, ())?;
+        Ok(())
+    }
+
+    fn call_helper(&mut self, export: &str, args: &[u8]) -> anyhow::Result<String> {
+        self.refuel()?;
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&self.store, "nail_alloc")
+            .context("Plugin doesn't export `nail_alloc`")?;
+        let helper = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&self.store, export)
+            .with_context(|| format!("Plugin doesn't export helper function `{}`", export))?;
+        let memory = self
+            .instance
+            .get_memory(&self.store, "memory")
+            .context("Plugin doesn't export `memory`")?;
+        let args_ptr = alloc.call(&mut self.store, args.len() as i32)?;
+        memory
+            .write(&mut self.store, args_ptr as usize, args)
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+        let packed = helper.call(&mut self.store, (args_ptr, args.len() as i32))? as u64;
+        let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+        let range = guest_range(ptr, len, memory.data(&self.store).len()).with_context(|| {
+            format!(
+                "Helper function `{}` returned {} bytes at {}, outside of the plugin's memory",
+                export, len, ptr
+            )
+        })?;
+        Ok(String::from_utf8(memory.data(&self.store)[range].to_vec())?)
+    }
+}
+
+/// A loaded WebAssembly plugin, run as a build [`Hook`].
+pub struct WasmPlugin {
+    name: String,
+    runtime: Arc<Mutex<PluginRuntime>>,
+}
+
+impl WasmPlugin {
+    pub fn load(config: &PluginConfig, wasm: &[u8]) -> anyhow::Result<Self> {
+        let mut engine_config = wasmi::Config::default();
+        engine_config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&engine_config);
+        let module = Module::new(&engine, wasm)?;
+        let state = PluginState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.max_memory_mb * 1024 * 1024)
+                .build(),
+            posts: Vec::new(),
+            files: Vec::new(),
+            helpers: Vec::new(),
+        };
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(config.fuel)
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+        let mut linker = Linker::<PluginState>::new(&engine);
+        Self::define_host_api(&mut linker)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        Ok(Self {
+            name: config.path.clone(),
+            runtime: Arc::new(Mutex::new(PluginRuntime {
+                store,
+                instance,
+                fuel: config.fuel,
+            })),
+        })
+    }
+
+    fn define_host_api(linker: &mut Linker<PluginState>) -> anyhow::Result<()> {
+        linker.func_wrap("nail", "post_count", |caller: Caller<'_, PluginState>| {
+            caller.data().posts.len() as i32
+        })?;
+        linker.func_wrap(
+            "nail",
+            "post_metadata",
+            |mut caller: Caller<'_, PluginState>, index: i32, out_ptr: i32, out_len: i32| {
+                let metadata = match caller.data().posts.get(index as usize) {
+                    Some(post) => serde_json::to_vec(&PluginPostMetadata {
+                        filename: &post.filename,
+                        title: &post.metadata.title,
+                        published: post.metadata.published,
+                        published_at: &post.metadata.published_at,
+                    })
+                    .map_err(guest_error)?,
+                    None => return Ok(-1),
+                };
+                write_guest(&mut caller, out_ptr, out_len, &metadata)
+            },
+        )?;
+        linker.func_wrap(
+            "nail",
+            "post_content",
+            |mut caller: Caller<'_, PluginState>, index: i32, out_ptr: i32, out_len: i32| {
+                let contents = match caller.data().posts.get(index as usize) {
+                    Some(post) => post.contents.clone().into_bytes(),
+                    None => return Ok(-1),
+                };
+                write_guest(&mut caller, out_ptr, out_len, &contents)
+            },
+        )?;
+        linker.func_wrap(
+            "nail",
+            "emit_file",
+            |mut caller: Caller<'_, PluginState>,
+             path_ptr: i32,
+             path_len: i32,
+             contents_ptr: i32,
+             contents_len: i32| {
+                let path = read_guest_string(&caller, path_ptr, path_len)?;
+                let contents = read_guest_string(&caller, contents_ptr, contents_len)?;
+                let is_contained = Path::new(&path)
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)));
+                if path.is_empty() || !is_contained {
+                    return Ok(-1);
+                }
+                let virtual_path = format!("/{}", path.replace('\\', "/"));
+                caller
+                    .data_mut()
+                    .files
+                    .push(BuildFile::new(path, virtual_path, contents));
+                Ok(0)
+            },
+        )?;
+        linker.func_wrap(
+            "nail",
+            "register_helper",
+            |mut caller: Caller<'_, PluginState>,
+             name_ptr: i32,
+             name_len: i32,
+             export_ptr: i32,
+             export_len: i32| {
+                let name = read_guest_string(&caller, name_ptr, name_len)?;
+                let export = read_guest_string(&caller, export_ptr, export_len)?;
+                caller.data_mut().helpers.push((name, export));
+                Ok(())
+            },
+        )?;
+        linker.func_wrap(
+            "nail",
+            "log",
+            |caller: Caller<'_, PluginState>, ptr: i32, len: i32| {
+                println!("| plugin: {}", read_guest_string(&caller, ptr, len)?);
+                Ok(())
+            },
+        )?;
+        Ok(())
+    }
+}
+
+fn guest_error(err: impl std::fmt::Display) -> wasmi::Error {
+    wasmi::Error::new(err.to_string())
+}
+
+fn read_guest_string(
+    caller: &Caller<'_, PluginState>,
+    ptr: i32,
+    len: i32,
+) -> Result<String, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmi::Error::new("Plugin doesn't export `memory`"))?;
+    let range =
+        guest_range(ptr as u32, len as u32, memory.data(caller).len()).ok_or_else(|| {
+            guest_error(format!(
+                "Plugin passed {} bytes at {}, outside of its memory",
+                len as u32, ptr as u32
+            ))
+        })?;
+    String::from_utf8(memory.data(caller)[range].to_vec()).map_err(guest_error)
+}
+
+/// The bytes at `ptr` and `len` of a guest's memory of `size` bytes, if they lie within it.
+///
+/// Checked before anything is allocated, as both come from the guest.
+fn guest_range(ptr: u32, len: u32, size: usize) -> Option<Range<usize>> {
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    (end <= size).then_some(start..end)
+}
+
+/// Writes as much of `bytes` as fits into the guest buffer and returns the full length.
+fn write_guest(
+    caller: &mut Caller<'_, PluginState>,
+    out_ptr: i32,
+    out_len: i32,
+    bytes: &[u8],
+) -> Result<i32, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmi::Error::new("Plugin doesn't export `memory`"))?;
+    let len = bytes.len().min(out_len.max(0) as usize);
+    memory
+        .write(caller, out_ptr as usize, &bytes[..len])
+        .map_err(guest_error)?;
+    Ok(bytes.len() as i32)
+}
+
+impl Hook for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn posts_gathered(&self, posts: &mut Vec<Post>) -> anyhow::Result<()> {
+        let mut runtime = self.runtime.lock();
+        let state = runtime.store.data_mut();
+        state.posts = posts.clone();
+        state.files.clear();
+        state.helpers.clear();
+        runtime.call_run()
+    }
+
+    fn prepare_theme(&self, theme: &mut Theme) -> anyhow::Result<()> {
+        let helpers = self.runtime.lock().store.data().helpers.clone();
+        for (name, export) in helpers {
+            let runtime = self.runtime.clone();
+            theme.register_helper(
+                &name,
+                TemplateHelper::raw(move |params, hash| {
+                    call_helper(&runtime, &export, params, hash)
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    fn bundle_assembled(&self, bundle: &mut Bundle) -> anyhow::Result<()> {
+        let files = std::mem::take(&mut self.runtime.lock().store.data_mut().files);
+        for file in files {
+            if bundle.get(file.virtual_path()).is_some() {
+                bail!("Plugin tried to overwrite `{}`", file.virtual_path())
+            }
+            bundle.add_file(file);
+        }
+        Ok(())
+    }
+}
+
+/// Calls a helper implemented by a plugin export, returning the HTML it produced.
+fn call_helper(
+    runtime: &Mutex<PluginRuntime>,
+    export: &str,
+    params: &[serde_json::Value],
+    hash: &JsonMap,
+) -> Result<serde_json::Value, String> {
+    let args = HelperArgs {
+        params: params.to_vec(),
+        hash: hash.clone(),
+    };
+    let args = serde_json::to_vec(&args).map_err(|err| err.to_string())?;
+    let output = runtime
+        .lock()
+        .call_helper(export, &args)
+        .map_err(|err| format!("Plugin helper failed: {:#}", err))?;
+    Ok(serde_json::Value::String(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plugin whose `nail_run` logs `len` bytes at `ptr` and whose `echo` helper returns
+    /// `(ptr << 32) | len`.
+    fn plugin(ptr: i64, len: i64) -> WasmPlugin {
+        let wat = format!(
+            r#"(module
+                (import "nail" "log" (func $log (param i32 i32)))
+                (import "nail" "register_helper" (func $register (param i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "echo")
+                (func (export "nail_alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "nail_run")
+                    (call $register (i32.const 0) (i32.const 4) (i32.const 0) (i32.const 4))
+                    (call $log (i32.const {ptr}) (i32.const {len})))
+                (func (export "echo") (param i32 i32) (result i64)
+                    i64.const {packed}))"#,
+            ptr = ptr,
+            len = len,
+            packed = (ptr << 32) | len,
+        );
+        let config = PluginConfig {
+            path: "test.wasm".to_string(),
+            max_memory_mb: default_max_memory_mb(),
+            fuel: default_fuel(),
+        };
+        WasmPlugin::load(&config, &wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn guest_ranges_must_lie_within_memory() {
+        assert_eq!(guest_range(0, 4, 4), Some(0..4));
+        assert_eq!(guest_range(4, 0, 4), Some(4..4));
+        assert_eq!(guest_range(1, 4, 4), None);
+        assert_eq!(guest_range(u32::MAX, u32::MAX, 65_536), None);
+    }
+
+    #[test]
+    fn guest_strings_are_read_from_memory() {
+        let plugin = plugin(0, 4);
+        plugin.posts_gathered(&mut Vec::new()).unwrap();
+        let output = plugin.runtime.lock().call_helper("echo", b"{}").unwrap();
+        assert_eq!(output, "echo");
+    }
+
+    #[test]
+    fn oversized_guest_strings_are_rejected() {
+        let plugin = plugin(0, 0x7fff_ffff);
+        let err = plugin.posts_gathered(&mut Vec::new()).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("outside of its memory"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn oversized_helper_output_is_rejected() {
+        let plugin = plugin(65_000, 65_536);
+        let _ = plugin.posts_gathered(&mut Vec::new());
+        let err = plugin
+            .runtime
+            .lock()
+            .call_helper("echo", b"{}")
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("outside of the plugin's memory"),
+            "{:#}",
+            err
+        );
+    }
+}
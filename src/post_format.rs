@@ -1,9 +1,15 @@
 use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
-use crate::post_metadata::PostMetadata;
+use crate::{
+    markdown::{self, ConvertOptions, Document, MarkdownConfig},
+    post_metadata::PostMetadata,
+    shortcodes,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PostFormat {
     Markdown,
     Html,
@@ -116,10 +122,42 @@ impl PostFormat {
             title: name.as_ref().to_string(),
             published: false,
             published_at: chrono::offset::Local::now().to_rfc3339(),
+            template: None,
+            layout: None,
+            toc: None,
         }
         .format(self)
     }
 
+    /// Extracts all metadata key-value pairs along with their (1-based) line numbers.
+    pub fn extract_kvps<S>(&self, contents: S) -> Vec<(usize, String, String)>
+    where
+        S: AsRef<str>,
+    {
+        contents
+            .as_ref()
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                self.extract_kvp(line)
+                    .ok()
+                    .map(|(key, value)| (index + 1, key, value))
+            })
+            .collect()
+    }
+
+    /// Returns the (1-based) line number on which the metadata `key` is defined.
+    pub fn find_metadata_line<S, K>(&self, contents: S, key: K) -> Option<usize>
+    where
+        S: AsRef<str>,
+        K: AsRef<str>,
+    {
+        self.extract_kvps(contents)
+            .into_iter()
+            .find(|(_, k, _)| k == key.as_ref())
+            .map(|(line, _, _)| line)
+    }
+
     pub fn extract_metadata<S>(&self, contents: S) -> anyhow::Result<PostMetadata>
     where
         S: AsRef<str>,
@@ -127,39 +165,53 @@ impl PostFormat {
         let mut title: Option<String> = None;
         let mut published: Option<bool> = None;
         let mut published_at: Option<String> = None;
-        for line in contents.as_ref().lines() {
-            if let Ok((key, value)) = self.extract_kvp(line) {
-                match key.as_str() {
-                    "title" => title = Some(value),
-                    "published" => published = if value == "true" { Some(true) } else { None },
-                    "published_at" => published_at = Some(value),
-                    _ => (),
-                }
+        let mut template: Option<String> = None;
+        let mut layout: Option<String> = None;
+        let mut toc: Option<bool> = None;
+        for (_, key, value) in self.extract_kvps(contents) {
+            match key.as_str() {
+                "title" => title = Some(value),
+                "published" => published = if value == "true" { Some(true) } else { None },
+                "published_at" => published_at = Some(value),
+                "template" => template = Some(value),
+                "layout" => layout = Some(value),
+                "toc" => toc = value.parse().ok(),
+                _ => (),
             }
         }
         Ok(PostMetadata {
             title: title.unwrap_or_else(|| "N/A".to_string()),
             published: published.unwrap_or(false),
             published_at: published_at.unwrap_or_else(|| "N/A".to_string()),
+            template,
+            layout,
+            toc,
         })
     }
 
     pub fn to_html<S>(&self, content: S) -> anyhow::Result<String>
+    where
+        S: AsRef<str>,
+    {
+        let config = MarkdownConfig::default();
+        let document = self.convert(content, &ConvertOptions::new(&config))?;
+        if let Some(diagnostic) = document.diagnostics.iter().find(|d| d.is_error()) {
+            bail!("{}", diagnostic.message);
+        }
+        Ok(document.html)
+    }
+
+    /// Converts a post to HTML, collecting its table of contents.
+    ///
+    /// HTML posts are kept as they are, apart from expanding shortcodes.
+    pub fn convert<S>(&self, content: S, options: &ConvertOptions) -> anyhow::Result<Document>
     where
         S: AsRef<str>,
     {
         let content = content.as_ref();
         match self {
-            PostFormat::Markdown => {
-                use comrak::{format_html, parse_document, Arena, ComrakOptions};
-                let arena = Arena::new();
-                let options = ComrakOptions::default();
-                let ast = parse_document(&arena, content, &options);
-                let mut buf = std::io::BufWriter::new(Vec::new());
-                format_html(ast, &options, &mut buf)?;
-                Ok(String::from_utf8(buf.into_inner()?)?)
-            }
-            PostFormat::Html => Ok(content.to_string()),
+            PostFormat::Markdown => markdown::convert(content, options),
+            PostFormat::Html => Ok(shortcodes::expand_html(content, options)),
         }
     }
 }
@@ -1,18 +1,56 @@
+use serde::{Deserialize, Serialize};
+
 use super::PostFormat;
 
-#[derive(Debug, Clone)]
+/// Metadata keys understood by nail itself.
+pub static KNOWN_METADATA_KEYS: &[&str] = &[
+    "title",
+    "published",
+    "published_at",
+    "template",
+    "layout",
+    "toc",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostMetadata {
     pub title: String,
     pub published: bool,
     pub published_at: String,
+    /// Alternative post template, e.g. `gallery` for the theme's `post.gallery.html`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Alternative layout wrapping the post, e.g. `wide` for the theme's `base.wide.html`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Set to `false` to leave out the table of contents, including any `[[toc]]` marker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toc: Option<bool>,
 }
 
 impl PostMetadata {
+    /// Parses `published_at`, returning `None` if the post doesn't specify a date.
+    pub fn parse_published_at(&self) -> anyhow::Result<Option<chrono::DateTime<chrono::Utc>>> {
+        if self.published_at == "N/A" {
+            return Ok(None);
+        }
+        Ok(Some(self.published_at.parse()?))
+    }
+
     pub fn format(&self, format: &PostFormat) -> String {
         let mut buf = String::new();
         buf.push_str(&format.make_kvp("title", &self.title));
         buf.push_str(&format.make_kvp("published", if self.published { "true" } else { "false" }));
         buf.push_str(&format.make_kvp("published_at", &self.published_at));
+        if let Some(template) = &self.template {
+            buf.push_str(&format.make_kvp("template", template));
+        }
+        if let Some(layout) = &self.layout {
+            buf.push_str(&format.make_kvp("layout", layout));
+        }
+        if let Some(toc) = self.toc {
+            buf.push_str(&format.make_kvp("toc", toc.to_string()));
+        }
         buf.push_str("\n\n");
         buf
     }
@@ -11,9 +11,14 @@ use crate::post_format::PostFormat;
 pub struct Scaffold;
 
 impl Scaffold {
-    pub fn create_post(name: String, format: PostFormat, force: bool) -> anyhow::Result<()> {
+    pub fn create_post(
+        root: &Path,
+        name: String,
+        format: PostFormat,
+        force: bool,
+    ) -> anyhow::Result<()> {
         let snake_case_name = name.replace(' ', "_").to_ascii_lowercase();
-        let post_path = std::env::current_dir()?.join("posts").join(format!(
+        let post_path = root.join("posts").join(format!(
             "{}.{}",
             &snake_case_name,
             format.to_file_extension()
@@ -35,9 +40,9 @@ impl Scaffold {
         Ok(())
     }
 
-    pub fn create_project(name: String, force: bool) -> anyhow::Result<()> {
+    pub fn create_project(parent_dir: &Path, name: String, force: bool) -> anyhow::Result<()> {
         let snake_case_name = name.replace(' ', "_").to_ascii_lowercase();
-        let blog_dir = Path::new(&snake_case_name);
+        let blog_dir = parent_dir.join(&snake_case_name);
 
         // Check if directory exists
         if !force && blog_dir.exists() {
@@ -53,8 +58,9 @@ impl Scaffold {
         Config::new(&name).save_to_file(&blog_dir)?;
 
         println!(
-            "Created project `{}` in directory `./{}`",
-            &name, snake_case_name
+            "Created project `{}` in directory `{}`",
+            &name,
+            blog_dir.display()
         );
 
         Ok(())
@@ -0,0 +1,418 @@
+//! Shortcodes: reusable components from the theme's `shortcodes/` folder, used in posts.
+//!
+//! `{{< figure src="cat.jpg" caption="A cat" >}}` renders `shortcodes/figure.html` with
+//! `src` and `caption`. A shortcode with a closing tag, `{{< note >}}...{{< /note >}}`,
+//! additionally receives what's between the tags as `body`, converted to HTML in Markdown
+//! posts. Unquoted values like `width=300` or `autoplay=true` are passed as numbers and
+//! booleans.
+
+use std::{fmt, ops::Range};
+
+use crate::{
+    diagnostics::Diagnostic,
+    markdown::{self, ConvertOptions, Document},
+};
+
+pub type JsonMap = serde_json::Map<String, serde_json::Value>;
+
+/// Renders shortcodes, implemented by [`Theme`](crate::theme::Theme).
+pub trait ShortcodeRenderer: fmt::Debug + Sync {
+    /// Names of all shortcodes that can be used, sorted.
+    fn shortcode_names(&self) -> Vec<&str>;
+
+    /// Renders shortcode `name` with its arguments and `body` as the context.
+    fn render_shortcode(
+        &self,
+        name: &str,
+        context: &serde_json::Value,
+    ) -> Result<String, Box<Diagnostic>>;
+}
+
+/// A shortcode used in a post.
+#[derive(Debug, Clone)]
+pub struct Shortcode {
+    pub name: String,
+    pub args: JsonMap,
+    /// What's between the opening and closing tag, if the shortcode has one.
+    pub body: Option<String>,
+    /// Byte offset of the body in the post.
+    pub body_offset: usize,
+    /// Bytes from the start of the opening tag to the end of the closing tag.
+    pub range: Range<usize>,
+}
+
+/// A `{{< ... >}}` tag.
+struct Tag<'a> {
+    range: Range<usize>,
+    /// Everything between `{{<` and `>}}`, trimmed.
+    inner: &'a str,
+}
+
+impl Tag<'_> {
+    fn closes(&self) -> Option<&str> {
+        self.inner.strip_prefix('/').map(str::trim)
+    }
+
+    fn is_self_closing(&self) -> bool {
+        self.inner.ends_with('/')
+    }
+
+    fn name(&self) -> &str {
+        let inner = self.inner.trim_end_matches('/');
+        let end = inner
+            .find(|c: char| !is_name_char(c))
+            .unwrap_or(inner.len());
+        &inner[..end]
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Finds all outermost shortcodes outside the `skip` ranges, along with syntax errors and the
+/// byte offsets they occurred at.
+pub fn find(content: &str, skip: &[Range<usize>]) -> (Vec<Shortcode>, Vec<(String, usize)>) {
+    let mut shortcodes = Vec::new();
+    let mut errors = Vec::new();
+    let mut from = 0;
+    loop {
+        let tag = match next_tag(content, from, skip) {
+            Ok(Some(tag)) => tag,
+            Ok(None) => break,
+            Err(offset) => {
+                errors.push((
+                    "Shortcode tag is never closed with `>}}`".to_string(),
+                    offset,
+                ));
+                break;
+            }
+        };
+        from = tag.range.end;
+        if let Some(name) = tag.closes() {
+            errors.push((
+                format!("Closing tag `{{{{< /{} >}}}}` has no opening tag", name),
+                tag.range.start,
+            ));
+            continue;
+        }
+        let name = tag.name();
+        if name.is_empty() {
+            errors.push((
+                format!("Shortcode `{}` needs a name", tag.inner),
+                tag.range.start,
+            ));
+            continue;
+        }
+        let inner = tag.inner.trim_end_matches('/');
+        let args = match parse_args(&inner[name.len()..]) {
+            Ok(args) => args,
+            Err(message) => {
+                errors.push((
+                    format!("Invalid arguments to shortcode `{}`: {}", name, message),
+                    tag.range.start,
+                ));
+                continue;
+            }
+        };
+        let closing = match tag.is_self_closing() {
+            true => None,
+            false => find_closing(content, &tag, skip),
+        };
+        let (body, range) = match closing {
+            Some(closing) => (
+                Some(content[tag.range.end..closing.start].to_string()),
+                tag.range.start..closing.end,
+            ),
+            None => (None, tag.range.clone()),
+        };
+        from = range.end;
+        shortcodes.push(Shortcode {
+            name: name.to_string(),
+            args,
+            body,
+            body_offset: tag.range.end,
+            range,
+        });
+    }
+    (shortcodes, errors)
+}
+
+/// Finds the next tag at or after `from`, or returns the offset of an unclosed one.
+fn next_tag<'a>(
+    content: &'a str,
+    mut from: usize,
+    skip: &[Range<usize>],
+) -> Result<Option<Tag<'a>>, usize> {
+    loop {
+        let start = match content[from..].find("{{<") {
+            Some(start) => from + start,
+            None => return Ok(None),
+        };
+        if let Some(code) = skip.iter().find(|code| code.contains(&start)) {
+            from = code.end;
+            continue;
+        }
+        let end = content[start..].find(">}}").ok_or(start)? + start;
+        return Ok(Some(Tag {
+            range: start..end + ">}}".len(),
+            inner: content[start + "{{<".len()..end].trim(),
+        }));
+    }
+}
+
+/// Finds the tag closing `opening`, skipping nested shortcodes of the same name.
+fn find_closing(content: &str, opening: &Tag, skip: &[Range<usize>]) -> Option<Range<usize>> {
+    let name = opening.name();
+    let mut depth = 0;
+    let mut from = opening.range.end;
+    while let Ok(Some(tag)) = next_tag(content, from, skip) {
+        from = tag.range.end;
+        match tag.closes() {
+            Some(closes) if closes == name && depth == 0 => return Some(tag.range),
+            Some(closes) if closes == name => depth -= 1,
+            None if tag.name() == name && !tag.is_self_closing() => depth += 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Parses `key="value" other='value' count=3 flag=true`.
+fn parse_args(source: &str) -> Result<JsonMap, String> {
+    let mut args = JsonMap::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let key_end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        if key.is_empty() {
+            return Err(format!("expected an argument name at `{}`", rest));
+        }
+        rest = rest[key_end..]
+            .trim_start()
+            .strip_prefix('=')
+            .ok_or_else(|| format!("argument `{}` needs a value, e.g. {}=\"...\"", key, key))?
+            .trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let (value, after) = parse_quoted(&rest[1..], quote)
+                    .ok_or_else(|| format!("the value of `{}` is never closed", key))?;
+                rest = after;
+                serde_json::Value::String(value)
+            }
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                match value {
+                    "true" => true.into(),
+                    "false" => false.into(),
+                    _ => serde_json::from_str::<serde_json::Number>(value)
+                        .map(serde_json::Value::Number)
+                        .unwrap_or_else(|_| value.into()),
+                }
+            }
+        };
+        if args.insert(key.to_string(), value).is_some() {
+            return Err(format!("argument `{}` is given twice", key));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(args)
+}
+
+/// Reads a quoted value up to the closing `quote`, in which `\` escapes the next character.
+fn parse_quoted(source: &str, quote: char) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = source.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            c if c == quote => return Some((value, &source[index + c.len_utf8()..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Renders a shortcode whose body was already converted to `body`, reporting problems at
+/// its position in `content`.
+pub(crate) fn render(
+    shortcode: &Shortcode,
+    body: Option<String>,
+    content: &str,
+    options: &ConvertOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> String {
+    let renderer = match options.shortcodes {
+        Some(renderer) => renderer,
+        None => return content[shortcode.range.clone()].to_string(),
+    };
+    let (line, column) = markdown::position(content, shortcode.range.start);
+    let names = renderer.shortcode_names();
+    if !names.contains(&shortcode.name.as_str()) {
+        let available = match names.is_empty() {
+            true => "the theme provides none".to_string(),
+            false => format!("available: {}", names.join(", ")),
+        };
+        diagnostics.push(
+            Diagnostic::error(format!(
+                "Unknown shortcode `{}` ({})",
+                shortcode.name, available
+            ))
+            .at(Some(line), Some(column))
+            .with_source(content),
+        );
+        return String::new();
+    }
+    let mut context = shortcode.args.clone();
+    if let Some(body) = body {
+        context.insert("body".to_string(), body.into());
+    }
+    renderer
+        .render_shortcode(&shortcode.name, &serde_json::Value::Object(context))
+        .map(|html| html.trim_end().to_string())
+        .unwrap_or_else(|diagnostic| {
+            // Reported at the shortcode, the post is what's being built
+            let template = match (&diagnostic.file, diagnostic.line) {
+                (Some(file), Some(line)) => format!(" ({}:{})", file.display(), line),
+                (Some(file), None) => format!(" ({})", file.display()),
+                _ => String::new(),
+            };
+            diagnostics.push(
+                Diagnostic::error(format!(
+                    "Unable to render shortcode `{}`: {}{}",
+                    shortcode.name, diagnostic.message, template
+                ))
+                .at(Some(line), Some(column))
+                .with_source(content),
+            );
+            String::new()
+        })
+}
+
+/// Expands the shortcodes of an HTML post, whose bodies are left as they are.
+pub(crate) fn expand_html(content: &str, options: &ConvertOptions) -> Document {
+    let mut diagnostics = Vec::new();
+    if options.shortcodes.is_none() {
+        return Document {
+            html: content.to_string(),
+            ..Default::default()
+        };
+    }
+    let (shortcodes, errors) = find(content, &[]);
+    for (message, offset) in errors {
+        diagnostics.push(syntax_error(message, content, offset));
+    }
+    let mut html = String::with_capacity(content.len());
+    let mut copied = 0;
+    for shortcode in &shortcodes {
+        html.push_str(&content[copied..shortcode.range.start]);
+        let body = shortcode.body.as_ref().map(|body| {
+            let document = expand_html(body, options);
+            let (line, column) = markdown::position(content, shortcode.body_offset);
+            diagnostics.extend(
+                document
+                    .diagnostics
+                    .into_iter()
+                    .map(|diagnostic| markdown::shift(diagnostic, line, column, content)),
+            );
+            document.html
+        });
+        html.push_str(&render(shortcode, body, content, options, &mut diagnostics));
+        copied = shortcode.range.end;
+    }
+    html.push_str(&content[copied..]);
+    Document {
+        html,
+        toc: Vec::new(),
+        diagnostics,
+    }
+}
+
+/// A malformed shortcode at `offset` in `content`.
+pub(crate) fn syntax_error(message: String, content: &str, offset: usize) -> Diagnostic {
+    let (line, column) = markdown::position(content, offset);
+    Diagnostic::error(message)
+        .at(Some(line), Some(column))
+        .with_source(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_parsed_with_their_types() {
+        let args = parse_args(
+            r#" src="a \"b\".jpg" alt='it\'s' width = 300 ratio=1.5 autoplay=true title=plain"#,
+        )
+        .unwrap();
+        assert_eq!(args["src"], "a \"b\".jpg");
+        assert_eq!(args["alt"], "it's");
+        assert_eq!(args["width"], 300);
+        assert_eq!(args["ratio"], 1.5);
+        assert_eq!(args["autoplay"], true);
+        assert_eq!(args["title"], "plain");
+        assert!(parse_args("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_arguments_are_explained() {
+        let error = |source| parse_args(source).unwrap_err();
+        assert_eq!(error("=1"), "expected an argument name at `=1`");
+        assert_eq!(
+            error("src"),
+            "argument `src` needs a value, e.g. src=\"...\""
+        );
+        assert_eq!(error("src=\"a.jpg"), "the value of `src` is never closed");
+        assert_eq!(error("a=1 a=2"), "argument `a` is given twice");
+    }
+
+    #[test]
+    fn nested_shortcodes_of_the_same_name_are_skipped() {
+        let content = "{{< note >}}a {{< note >}}b{{< /note >}} c{{< /note >}} after";
+        let (shortcodes, errors) = find(content, &[]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(shortcodes.len(), 1);
+        assert_eq!(
+            shortcodes[0].body.as_deref(),
+            Some("a {{< note >}}b{{< /note >}} c")
+        );
+        assert_eq!(&content[shortcodes[0].range.end..], " after");
+    }
+
+    #[test]
+    fn shortcodes_without_closing_tags_have_no_body() {
+        let content = "{{< figure src=\"a.jpg\" />}} {{< br >}} {{< note >}}x{{< /other >}}";
+        let (shortcodes, errors) = find(content, &[]);
+        let names = shortcodes
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["figure", "br", "note"]);
+        assert!(shortcodes.iter().all(|shortcode| shortcode.body.is_none()));
+        assert_eq!(shortcodes[0].args["src"], "a.jpg");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.contains("has no opening tag"), "{:?}", errors);
+    }
+
+    #[test]
+    fn closing_tags_in_skipped_ranges_are_ignored() {
+        let content = "{{< note >}}`{{< /note >}}`{{< /note >}}";
+        let code = 12..27;
+        assert_eq!(&content[code.clone()], "`{{< /note >}}`");
+        let (shortcodes, _) = find(content, std::slice::from_ref(&code));
+        assert_eq!(shortcodes[0].body.as_deref(), Some("`{{< /note >}}`"));
+    }
+
+    #[test]
+    fn unterminated_tags_are_errors() {
+        let (shortcodes, errors) = find("text {{< note", &[]);
+        assert!(shortcodes.is_empty());
+        assert_eq!(
+            errors,
+            [("Shortcode tag is never closed with `>}}`".to_string(), 5)]
+        );
+    }
+}
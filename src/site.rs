@@ -0,0 +1,115 @@
+//! Data files in `data/`, available to templates as `site.data.<filename>`.
+//!
+//! `data/links.toml`, `data/links.json`, `data/links.yaml` and `data/links.csv` all become
+//! `site.data.links`. CSV files are read as a list of rows, keyed by the header row.
+
+use std::path::Path;
+
+use crate::{
+    diagnostics::{Diagnostic, Diagnostics},
+    source::SourceTree,
+};
+
+pub(crate) static DATA_DIR: &str = "data";
+
+/// Reads every data file of a source tree, reporting files that fail to parse.
+pub fn load_data(
+    source: &SourceTree,
+    diagnostics: &mut Diagnostics,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut data = serde_json::Map::new();
+    if !source.exists(DATA_DIR) {
+        return data;
+    }
+    let paths = match source.list_files(DATA_DIR) {
+        Ok(paths) => paths,
+        Err(err) => {
+            diagnostics.push(
+                Diagnostic::error(format!("Unable to read data directory: {}", err))
+                    .with_file(DATA_DIR),
+            );
+            return data;
+        }
+    };
+    for path in paths {
+        let (name, extension) = match (path.file_stem(), path.extension()) {
+            (Some(name), Some(extension)) => (name.to_string_lossy(), extension.to_string_lossy()),
+            _ => continue,
+        };
+        if !["toml", "json", "yaml", "yml", "csv"].contains(&extension.as_ref()) {
+            continue;
+        }
+        let contents = match source.read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                diagnostics.push(
+                    Diagnostic::error(format!("Unable to read data file: {}", err))
+                        .with_file(&path),
+                );
+                continue;
+            }
+        };
+        let value = match parse(&extension, &contents) {
+            Ok(value) => value,
+            Err((message, line)) => {
+                diagnostics.push(
+                    Diagnostic::error(format!("Unable to parse data file: {}", message))
+                        .with_file(&path)
+                        .at(line, None)
+                        .with_source(&contents),
+                );
+                continue;
+            }
+        };
+        if data.contains_key(name.as_ref()) {
+            diagnostics.push(duplicate(&path, &name));
+            continue;
+        }
+        data.insert(name.into_owned(), value);
+    }
+    data
+}
+
+/// Parses a data file, returning the error message and line on failure.
+fn parse(extension: &str, contents: &str) -> Result<serde_json::Value, (String, Option<usize>)> {
+    match extension {
+        "toml" => toml::from_str(contents).map_err(|err| {
+            let line = err.line_col().map(|(line, _)| line + 1);
+            (err.to_string(), line)
+        }),
+        "json" => serde_json::from_str(contents).map_err(|err| (err.to_string(), Some(err.line()))),
+        "yaml" | "yml" => serde_yaml::from_str(contents).map_err(|err| {
+            let line = err.location().map(|location| location.line());
+            (err.to_string(), line)
+        }),
+        _ => parse_csv(contents),
+    }
+}
+
+fn parse_csv(contents: &str) -> Result<serde_json::Value, (String, Option<usize>)> {
+    let csv_error = |err: csv::Error| {
+        let line = err.position().map(|position| position.line() as usize);
+        (err.to_string(), line)
+    };
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, value)| (header.to_string(), value.into()))
+            .collect::<serde_json::Map<_, _>>();
+        rows.push(serde_json::Value::Object(row));
+    }
+    Ok(serde_json::Value::Array(rows))
+}
+
+fn duplicate(path: &Path, name: &str) -> Diagnostic {
+    Diagnostic::error(format!(
+        "Data file conflicts with another one named `{}`, rename one of them",
+        name
+    ))
+    .with_file(path)
+}
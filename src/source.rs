@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// A project's source files, either on disk or held entirely in memory.
+///
+/// All paths passed to a source tree are relative to the project root,
+/// e.g. `config.toml` or `posts/hello_world.md`.
+#[derive(Debug, Clone)]
+pub enum SourceTree {
+    Disk(PathBuf),
+    Memory(MemoryTree),
+}
+
+/// An in-memory set of source files, keyed by their path relative to the project root.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTree {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(path.into(), contents.into());
+    }
+
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files.remove(path.as_ref())
+    }
+
+    pub fn with_file(mut self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, contents);
+        self
+    }
+}
+
+impl From<MemoryTree> for SourceTree {
+    fn from(tree: MemoryTree) -> Self {
+        SourceTree::Memory(tree)
+    }
+}
+
+impl SourceTree {
+    pub fn disk(root: impl Into<PathBuf>) -> Self {
+        SourceTree::Disk(root.into())
+    }
+
+    /// The project root, if the tree lives on disk.
+    pub fn root(&self) -> Option<&Path> {
+        match self {
+            SourceTree::Disk(root) => Some(root),
+            SourceTree::Memory(_) => None,
+        }
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        match self {
+            SourceTree::Disk(root) => std::fs::read(root.join(path)),
+            SourceTree::Memory(tree) => tree.files.get(path).cloned().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found in source tree", path.display()),
+                )
+            }),
+        }
+    }
+
+    pub fn read_to_string(&self, path: impl AsRef<Path>) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The modification time and size of a file on disk, or `None` in memory or if it's missing.
+    pub fn modified(&self, path: impl AsRef<Path>) -> Option<(SystemTime, u64)> {
+        match self {
+            SourceTree::Disk(root) => {
+                let metadata = std::fs::metadata(root.join(path)).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            }
+            SourceTree::Memory(_) => None,
+        }
+    }
+
+    pub fn is_file(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        match self {
+            SourceTree::Disk(root) => root.join(path).is_file(),
+            SourceTree::Memory(tree) => tree.files.contains_key(path),
+        }
+    }
+
+    /// Whether `path` is a file or a directory containing files.
+    pub fn exists(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        match self {
+            SourceTree::Disk(root) => root.join(path).exists(),
+            SourceTree::Memory(tree) => tree.files.keys().any(|file| file.starts_with(path)),
+        }
+    }
+
+    /// Lists the files directly inside `dir`, sorted by path.
+    pub fn list_files(&self, dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let mut files = match self {
+            SourceTree::Disk(root) => {
+                let mut files = Vec::new();
+                for dir_entry in std::fs::read_dir(root.join(dir))? {
+                    let dir_entry = dir_entry?;
+                    if dir_entry.file_type()?.is_file() {
+                        files.push(dir.join(dir_entry.file_name()));
+                    }
+                }
+                files
+            }
+            SourceTree::Memory(tree) => tree
+                .files
+                .keys()
+                .filter(|file| file.parent() == Some(dir))
+                .cloned()
+                .collect(),
+        };
+        files.sort();
+        Ok(files)
+    }
+
+    /// Lists the files inside `dir` and its subfolders, sorted by path.
+    pub fn walk_files(&self, dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let mut files = match self {
+            SourceTree::Disk(root) => {
+                let mut files = Vec::new();
+                for dir_entry in std::fs::read_dir(root.join(dir))? {
+                    let dir_entry = dir_entry?;
+                    let path = dir.join(dir_entry.file_name());
+                    match dir_entry.file_type()?.is_dir() {
+                        true => files.extend(self.walk_files(&path)?),
+                        false => files.push(path),
+                    }
+                }
+                files
+            }
+            SourceTree::Memory(tree) => tree
+                .files
+                .keys()
+                .filter(|file| file.starts_with(dir))
+                .cloned()
+                .collect(),
+        };
+        files.sort();
+        Ok(files)
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tiny_http::Request;
+
+use crate::{
+    config::Config,
+    dev_server::DevConfig,
+    http::{self, Reply},
+};
+
+/// Settings for `nail serve`, configured under `[serve]` in `config.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct ServeConfig {
+    /// URL path the blog is deployed under, e.g. `/blog`.
+    pub base_path: String,
+}
+
+impl ServeConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Serves a built blog from disk the way a production web server would.
+///
+/// Resolves pretty URLs, answers unknown paths with the theme's `404.html` and prefers
+/// precompressed `.br`/`.gz` variants of files when the client accepts them.
+#[derive(Debug)]
+pub struct StaticServer {
+    dir: PathBuf,
+    address: DevConfig,
+    base_path: String,
+    host: Option<String>,
+    port: Option<u16>,
+}
+
+impl StaticServer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            address: DevConfig::default(),
+            base_path: String::new(),
+            host: None,
+            port: None,
+        }
+    }
+
+    /// Uses the `[dev]` address and the `[serve]` base path of `config`.
+    pub fn config(mut self, config: &Config) -> Self {
+        self.address = config.dev.clone();
+        self.base_path(&config.serve.base_path)
+    }
+
+    pub fn base_path(mut self, base_path: impl AsRef<str>) -> Self {
+        let base_path = base_path.as_ref().trim_matches('/');
+        self.base_path = match base_path {
+            "" => String::new(),
+            base_path => format!("/{}", base_path),
+        };
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Listens on exactly `port`, failing instead of falling back if it is taken.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn serve(&self) -> anyhow::Result<()> {
+        if !self.dir.is_dir() {
+            anyhow::bail!(
+                "`{}` doesn't exist, run `nail build` first",
+                self.dir.display()
+            )
+        }
+        let host = self.host.as_deref().unwrap_or(&self.address.host);
+        let server = match self.port {
+            Some(port) => http::bind(host, port, false)?,
+            None => http::bind(host, self.address.port, true)?,
+        };
+        println!("Serving {} on:", self.dir.display());
+        for url in http::reachable_urls(server.server_addr()) {
+            println!("  {}{}/", url, self.base_path);
+        }
+        http::serve(&server, |path, request| self.handle(path, request));
+        Ok(())
+    }
+
+    fn handle(&self, path: &str, request: &Request) -> Reply {
+        let path = match path.strip_prefix(&self.base_path) {
+            Some("") => return Self::redirect(format!("{}/", self.base_path)),
+            Some(path) if path.starts_with('/') => path,
+            _ => return self.not_found(path),
+        };
+        if !path.ends_with('/') && self.dir.join(&path[1..]).is_dir() {
+            return Self::redirect(format!("{}{}/", self.base_path, path));
+        }
+        for candidate in http::candidates(path) {
+            let file = self.dir.join(candidate.trim_start_matches('/'));
+            if file.is_file() {
+                return Self::file(&file, http::mime_type(&candidate), request);
+            }
+        }
+        self.not_found(path)
+    }
+
+    /// Reads `file`, or a precompressed variant of it the client accepts.
+    fn file(file: &Path, mime: &'static str, request: &Request) -> Reply {
+        for (encoding, extension) in [("br", "br"), ("gzip", "gz")] {
+            if !http::accepts_encoding(request, encoding) {
+                continue;
+            }
+            let mut variant = file.as_os_str().to_owned();
+            variant.push(".");
+            variant.push(extension);
+            if let Ok(contents) = std::fs::read(&variant) {
+                return Reply::new(200, mime, contents)
+                    .with_header("Content-Encoding", encoding)
+                    .with_header("Vary", "Accept-Encoding");
+            }
+        }
+        match std::fs::read(file) {
+            Ok(contents) => Reply::new(200, mime, contents).with_header("Vary", "Accept-Encoding"),
+            Err(err) => Reply::new(500, "text/plain", format!("Unable to read file: {}", err)),
+        }
+    }
+
+    fn not_found(&self, path: &str) -> Reply {
+        match std::fs::read(self.dir.join("404.html")) {
+            Ok(page) => Reply::new(404, "text/html", page),
+            Err(_) => Reply::not_found(path),
+        }
+    }
+
+    fn redirect(location: String) -> Reply {
+        let location = http::percent_encode_path(&location);
+        Reply::new(301, "text/plain", format!("Moved to {}", location))
+            .with_header("Location", location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_to_non_ascii_paths_are_encoded() {
+        let reply = StaticServer::redirect("/blog/café/".to_string());
+        assert_eq!(reply.status, 301);
+        assert_eq!(
+            reply.headers,
+            [("Location", "/blog/caf%C3%A9/".to_string())]
+        );
+    }
+}
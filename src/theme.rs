@@ -1,61 +1,187 @@
-use std::collections::HashMap;
+mod helpers;
+pub mod manager;
+pub mod manifest;
+pub mod styles;
+pub mod templating;
 
-use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::{build::Post, config::Config};
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+use crate::{
+    build::Post,
+    cache::HashCache,
+    config::{Config, CONFIG_FILE_NAME},
+    diagnostics::{Diagnostic, Diagnostics},
+    links,
+    markdown::{Document, TocEntry},
+    shortcodes::ShortcodeRenderer,
+    source::SourceTree,
+};
+
+use self::{
+    manifest::{ThemeConfig, ThemeManifest},
+    styles::Stylesheet,
+    templating::{TemplateEngine, TemplateHelper},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlogRenderData {
-    name: String,
+    pub name: String,
+}
+
+/// The site's settings from `config.toml` and its data files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SiteRenderData {
+    pub name: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub base_url: Option<String>,
+    /// The `[extra]` table of `config.toml`.
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// Contents of the files in `data/`, keyed by file name without extension.
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SiteRenderData {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            name: config.name.clone(),
+            title: config.title.clone(),
+            description: config.description.clone(),
+            author: config.author.clone(),
+            base_url: config.base_url.clone(),
+            extra: serde_json::to_value(&config.extra)
+                .ok()
+                .and_then(|extra| extra.as_object().cloned())
+                .unwrap_or_default(),
+            data: Default::default(),
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostRenderData {
-    title: String,
-    content: String,
+    pub title: String,
+    pub published_at: String,
+    pub content: String,
+    /// Estimated reading time in minutes.
+    pub reading_time: usize,
+    /// The post's headings, nested by level.
+    #[serde(default)]
+    pub toc: Vec<TocEntry>,
+    /// The post's `template` metadata, see [`Theme::post_template`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// The post's `layout` metadata, see [`Theme::layout`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Posts linking to this one, newest first, filled in by the build.
+    #[serde(default)]
+    pub backlinks: Vec<PostIndex>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageRenderData {
-    content: String,
+    pub content: String,
 }
 
-#[derive(Debug, Serialize)]
-struct PostIndex {
-    title: String,
-    link: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostIndex {
+    pub title: String,
+    pub published_at: String,
+    pub link: String,
 }
 
-#[derive(Debug, Serialize)]
+impl PostIndex {
+    /// Lists a post published below `base_path`, marking it if it's unpublished.
+    pub(crate) fn new(post: &Post, base_path: &str) -> Self {
+        let post_title_suffix = {
+            if !post.metadata.published {
+                " (Unpublished)"
+            } else {
+                ""
+            }
+        };
+        Self {
+            title: format!("{}{}", post.metadata.title, post_title_suffix),
+            published_at: post.metadata.published_at.clone(),
+            link: links::post_url(post, base_path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HomeRenderData {
-    posts: Vec<PostIndex>,
+    pub posts: Vec<PostIndex>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotFoundRenderData {
+    pub recent_posts: Vec<PostIndex>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThemeRenderData {
+    pub name: String,
+    pub version: Option<String>,
+    /// Option values from `[theme.options]`, with the manifest's defaults for the rest.
+    pub options: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The data every template is rendered with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RenderData {
-    blog: Option<BlogRenderData>,
-    post: Option<PostRenderData>,
-    page: Option<PageRenderData>,
-    home: Option<HomeRenderData>,
+    pub blog: Option<BlogRenderData>,
+    /// The site's settings and data files; the latter are filled in by the build.
+    #[serde(default)]
+    pub site: SiteRenderData,
+    pub post: Option<PostRenderData>,
+    pub page: Option<PageRenderData>,
+    pub home: Option<HomeRenderData>,
+    pub not_found: Option<NotFoundRenderData>,
+    /// The theme's name, version and options, filled in when rendering.
+    #[serde(default)]
+    pub theme: Option<ThemeRenderData>,
+    /// Additional values injected by build hooks, available to templates as `extra`.
+    #[serde(default)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl RenderData {
-    pub fn for_post(config: &Config, post: &Post) -> anyhow::Result<Self> {
+    /// Creates render data for `post`, whose contents were already converted to `document`.
+    pub fn for_post(config: &Config, post: &Post, document: Document) -> Self {
         let post = Some(PostRenderData {
             title: post.metadata.title.clone(),
-            content: post.format.to_html(&post.contents)?,
+            published_at: post.metadata.published_at.clone(),
+            content: document.html,
+            reading_time: reading_time(&post.contents),
+            toc: document.toc,
+            template: post.metadata.template.clone(),
+            layout: post.metadata.layout.clone(),
+            backlinks: Vec::new(),
         });
         let blog = Some(BlogRenderData {
             name: config.name.clone(),
         });
         let page = None;
         let home = None;
-        Ok(Self {
+        Self {
             post,
             blog,
+            site: SiteRenderData::new(config),
             page,
             home,
-        })
+            not_found: None,
+            theme: None,
+            extra: Default::default(),
+        }
     }
 
     pub fn for_index(config: &Config, output_map: &HashMap<String, Post>) -> Self {
@@ -68,8 +194,32 @@ impl RenderData {
         Self {
             post,
             blog,
+            site: SiteRenderData::new(config),
             page,
             home,
+            not_found: None,
+            theme: None,
+            extra: Default::default(),
+        }
+    }
+
+    /// Creates render data for the 404 page, listing the most recent posts.
+    pub fn for_not_found(config: &Config, output_map: &HashMap<String, Post>) -> Self {
+        let mut posts = Self::build_home_data(config, output_map).posts;
+        posts.truncate(RECENT_POSTS_COUNT);
+        Self {
+            blog: Some(BlogRenderData {
+                name: config.name.clone(),
+            }),
+            site: SiteRenderData::new(config),
+            post: None,
+            page: None,
+            home: None,
+            not_found: Some(NotFoundRenderData {
+                recent_posts: posts,
+            }),
+            theme: None,
+            extra: Default::default(),
         }
     }
 
@@ -81,28 +231,14 @@ impl RenderData {
         let mut post_index_data = map
             .iter()
             .filter(|(_, post)| post.metadata.published || config.__is_dev_mode)
-            .map(|(key, post)| (key, post))
             .collect::<Vec<_>>();
-        post_index_data.sort_by(|a, b| {
-            let a: chrono::DateTime<chrono::Utc> = a.1.metadata.published_at.parse().unwrap();
-            let b: chrono::DateTime<chrono::Utc> = b.1.metadata.published_at.parse().unwrap();
-            b.cmp(&a)
+        // Posts without a valid date are sorted last
+        post_index_data.sort_by_key(|(_, post)| {
+            std::cmp::Reverse(post.metadata.parse_published_at().ok().flatten())
         });
         let post_index_data = post_index_data
             .into_iter()
-            .map(|(key, post)| {
-                let post_title_suffix = {
-                    if !post.metadata.published {
-                        " (Unpublished)"
-                    } else {
-                        ""
-                    }
-                };
-                PostIndex {
-                    title: format!("{}{}", post.metadata.title, post_title_suffix),
-                    link: format!("/posts/{}", key),
-                }
-            })
+            .map(|(_, post)| PostIndex::new(post, &config.serve.base_path))
             .collect::<Vec<_>>();
         HomeRenderData {
             posts: post_index_data,
@@ -110,53 +246,791 @@ impl RenderData {
     }
 }
 
+#[derive(Debug)]
+struct ThemeTemplate {
+    path: PathBuf,
+    source: String,
+}
+
+/// A theme in an `extends` chain.
+struct ThemeLayer {
+    name: String,
+    dir: PathBuf,
+    manifest: Option<ThemeManifest>,
+}
+
+/// A file from a theme's `static` folder, published as it is.
+#[derive(Debug, Clone)]
+pub struct ThemeAsset {
+    /// Path below the `static` folder, which is also where it's published, e.g. `fonts/a.woff2`.
+    pub path: String,
+    pub bytes: Arc<[u8]>,
+}
+
+impl ThemeAsset {
+    /// Path templates link to, with a hash of the contents as a cache-busting query.
+    pub fn link(&self) -> String {
+        format!("{}?v={:08x}", self.path, HashCache::hash_bytes(&self.bytes))
+    }
+}
+
 #[derive(Debug)]
 pub struct Theme {
-    pub css: String,
-    base_template: String,
-    home_template: String,
-    post_template: String,
+    /// Stylesheets emitted alongside the pages, starting with `style`.
+    pub stylesheets: Vec<Stylesheet>,
+    /// Files from the `static` folders of the theme and its parents, sorted by path.
+    pub assets: Vec<ThemeAsset>,
+    engine: Box<dyn TemplateEngine>,
+    templates: HashMap<String, ThemeTemplate>,
+    data: ThemeRenderData,
+    base_path: String,
 }
 
 impl Theme {
-    pub fn render_index(&self, mut data: RenderData) -> anyhow::Result<String> {
-        use handlebars::Handlebars;
-        let renderer = Handlebars::new();
-        let output = renderer.render_template(&self.home_template, &data)?;
-        data.extend_with_page(output);
-        let output = renderer.render_template(&self.base_template, &data)?;
-        Ok(output)
+    /// Makes `url_for` and `asset` link below `base_path`, e.g. `/blog`.
+    pub fn set_base_path(&mut self, base_path: &str) {
+        self.base_path = base_path.to_string();
+        self.register_helpers();
     }
 
-    pub fn render_post(&self, mut data: RenderData) -> anyhow::Result<String> {
-        use handlebars::Handlebars;
-        let renderer = Handlebars::new();
-        let output = renderer.render_template(&self.post_template, &data)?;
+    /// Minifies all stylesheets and fingerprints their file names, e.g. `style.1a2b3c4d.css`.
+    ///
+    /// Templates keep linking to them with `{{asset "style.css"}}`.
+    pub fn optimize(&mut self) -> anyhow::Result<()> {
+        for stylesheet in &mut self.stylesheets {
+            stylesheet.optimize()?;
+        }
+        self.register_helpers();
+        Ok(())
+    }
+
+    fn register_helpers(&mut self) {
+        let assets = self
+            .assets
+            .iter()
+            .map(|asset| (asset.path.clone(), asset.link()))
+            .chain(
+                self.stylesheets
+                    .iter()
+                    .map(|stylesheet| (format!("{}.css", stylesheet.name), stylesheet.link())),
+            )
+            .collect();
+        helpers::register(self.engine.as_mut(), &self.base_path, assets);
+    }
+
+    /// Hashes the theme's options, templates, partials, shortcodes, stylesheets and static files,
+    /// which every page is rendered with.
+    pub(crate) fn hash(&self) -> u32 {
+        let templates = self
+            .templates
+            .iter()
+            .map(|(name, template)| (name, &template.source))
+            .collect::<BTreeMap<_, _>>();
+        let stylesheets = self
+            .stylesheets
+            .iter()
+            .map(|stylesheet| (&stylesheet.name, &stylesheet.css))
+            .collect::<Vec<_>>();
+        let assets = self.assets.iter().map(ThemeAsset::link).collect::<Vec<_>>();
+        HashCache::hash_contents(format!(
+            "{:?}{:?}{:?}{:?}",
+            self.data, templates, stylesheets, assets
+        ))
+    }
+
+    pub fn register_helper(&mut self, name: &str, helper: TemplateHelper) {
+        self.engine.add_helper(name, helper)
+    }
+
+    pub fn render_index(&self, data: RenderData) -> Result<String, Box<Diagnostic>> {
+        self.render_page("home", "home", None, data)
+    }
+
+    /// Renders a post with the template and layout selected in its metadata.
+    pub fn render_post(&self, data: RenderData) -> Result<String, Box<Diagnostic>> {
+        let post = data.post.as_ref();
+        let layout = post.and_then(|post| post.layout.clone());
+        let template = self
+            .post_template(post.and_then(|post| post.template.as_deref()))
+            .map_err(|message| Box::new(Diagnostic::error(message)))?;
+        self.render_page("post", &template, layout.as_deref(), data)
+    }
+
+    /// Whether the theme provides the optional `404` template.
+    pub fn has_not_found_page(&self) -> bool {
+        self.templates.contains_key("404")
+    }
+
+    pub fn render_not_found(&self, data: RenderData) -> Result<String, Box<Diagnostic>> {
+        self.render_page("404", "404", None, data)
+    }
+
+    /// Names of all templates the theme provides, without partials and shortcodes, e.g.
+    /// `post.gallery`.
+    pub fn template_names(&self) -> Vec<&str> {
+        let mut names = self
+            .templates
+            .keys()
+            .filter(|name| self.engine.has_template(name))
+            .map(String::as_str)
+            .filter(|name| !name.starts_with(SHORTCODE_PREFIX))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    /// Resolves a post's `template` metadata to a template, e.g. `gallery` to `post.gallery`.
+    pub fn post_template(&self, template: Option<&str>) -> Result<String, String> {
+        self.variant("post", template)
+    }
+
+    /// Resolves the layout wrapping a page of `kind`, e.g. `home` or `post`.
+    ///
+    /// An explicit `layout` such as `wide` selects `base.wide`. Otherwise a theme can give each
+    /// kind of page its own layout, like `base.home` for the index, falling back to `base`.
+    pub fn layout(&self, kind: &str, layout: Option<&str>) -> Result<String, String> {
+        if layout.is_some() {
+            return self.variant("base", layout);
+        }
+        let layout = format!("base.{}", kind);
+        match self.engine.has_template(&layout) {
+            true => Ok(layout),
+            false => Ok("base".to_string()),
+        }
+    }
+
+    /// Finds the variant `name` of the template `kind`, accepting `gallery`, `post.gallery`
+    /// and `post.gallery.html` alike.
+    fn variant(&self, kind: &str, name: Option<&str>) -> Result<String, String> {
+        let name = match name.map(|name| name.trim().trim_end_matches(".html")) {
+            None | Some("") => return Ok(kind.to_string()),
+            Some(name) if name == kind || name.starts_with(&format!("{}.", kind)) => {
+                name.to_string()
+            }
+            Some(name) => format!("{}.{}", kind, name),
+        };
+        if self.engine.has_template(&name) {
+            return Ok(name);
+        }
+        let mut available = self
+            .templates
+            .keys()
+            .filter(|template| self.engine.has_template(template))
+            .filter(|template| template.starts_with(&format!("{}.", kind)))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        available.sort_unstable();
+        if available.is_empty() {
+            return Err(format!(
+                "Theme `{}` has no template `{}`, it doesn't provide any alternatives to `{}`",
+                self.data.name, name, kind
+            ));
+        }
+        Err(format!(
+            "Theme `{}` has no template `{}` (available: {})",
+            self.data.name,
+            name,
+            available.join(", ")
+        ))
+    }
+
+    /// Renders `template` for a page of `kind` and wraps it in the page's layout.
+    fn render_page(
+        &self,
+        kind: &str,
+        template: &str,
+        layout: Option<&str>,
+        mut data: RenderData,
+    ) -> Result<String, Box<Diagnostic>> {
+        let layout = self
+            .layout(kind, layout)
+            .map_err(|message| Box::new(Diagnostic::error(message)))?;
+        data.theme = Some(self.data.clone());
+        let output = self.render(template, &data)?;
         data.extend_with_page(output);
-        let output = renderer.render_template(&self.base_template, &data)?;
-        Ok(output)
-    }
-
-    pub fn load<S>(name: S) -> anyhow::Result<Self>
-    where
-        S: AsRef<str>,
-    {
-        let theme_dir = std::env::current_dir()?.join("themes").join(name.as_ref());
-        use std::fs::read_to_string;
-        let name = name.as_ref();
-        let css_path = theme_dir.join(format!("{}.css", name));
-        let base_path = theme_dir.join(format!("{}.base.html", name));
-        let home_path = theme_dir.join(format!("{}.home.html", name));
-        let post_path = theme_dir.join(format!("{}.post.html", name));
-        let css = read_to_string(css_path)?;
-        let base_template = read_to_string(base_path)?;
-        let home_template = read_to_string(home_path)?;
-        let post_template = read_to_string(post_path)?;
-        Ok(Self {
-            css,
-            base_template,
-            home_template,
-            post_template,
-        })
+        self.render(&layout, &data)
+    }
+
+    fn render(&self, template: &str, data: &RenderData) -> Result<String, Box<Diagnostic>> {
+        let context = serde_json::to_value(data)
+            .map_err(|err| Box::new(Diagnostic::error(err).in_template(template)))?;
+        self.render_context(template, &context)
+    }
+
+    fn render_context(
+        &self,
+        template: &str,
+        context: &serde_json::Value,
+    ) -> Result<String, Box<Diagnostic>> {
+        self.engine
+            .render(template, context)
+            .map_err(|diagnostic| Box::new(self.locate(*diagnostic, template)))
+    }
+
+    /// Points a diagnostic at the file backing the given template.
+    fn locate(&self, diagnostic: Diagnostic, template: &str) -> Diagnostic {
+        let name = diagnostic
+            .template
+            .clone()
+            .unwrap_or_else(|| template.to_string());
+        match self.templates.get(name.as_str()) {
+            Some(theme_template) => diagnostic
+                .in_template(&name)
+                .with_file(&theme_template.path)
+                .with_source(&theme_template.source),
+            None => diagnostic.in_template(name),
+        }
+    }
+
+    /// Loads the theme selected in `config` from the `themes` directory of a source tree.
+    ///
+    /// A theme extending another starts out with its parent's stylesheet, templates, partials,
+    /// shortcodes and options, and only overrides or adds to them.
+    pub fn load(source: &SourceTree, config: &ThemeConfig) -> anyhow::Result<Self> {
+        let layers = Self::resolve_layers(source, &config.name)?;
+        let mut diagnostics = Diagnostics::new();
+        let mut declared_options = BTreeMap::new();
+        let mut stylesheets = Vec::<Stylesheet>::new();
+        // Sass imports prefer the files of a child theme over those of its parents
+        let load_paths = layers
+            .iter()
+            .rev()
+            .map(|layer| layer.dir.clone())
+            .collect::<Vec<_>>();
+        for (index, layer) in layers.iter().enumerate() {
+            for (name, path) in Self::stylesheet_sources(source, layer, index == 0) {
+                let css = match styles::read(source, &path, &load_paths) {
+                    Ok(css) => css,
+                    Err(diagnostic) => {
+                        diagnostics.push(*diagnostic);
+                        continue;
+                    }
+                };
+                // A child's stylesheet is appended to the parent's of the same name
+                match stylesheets
+                    .iter_mut()
+                    .find(|stylesheet| stylesheet.name == name)
+                {
+                    Some(stylesheet) => {
+                        stylesheet.css.push('\n');
+                        stylesheet.css.push_str(&css);
+                    }
+                    None => stylesheets.push(Stylesheet::new(name, css)),
+                }
+            }
+            if let Some(manifest) = &layer.manifest {
+                declared_options.extend(manifest.options.clone());
+            }
+        }
+        let variables = styles::variables(&config.variables).unwrap_or_else(|errors| {
+            for error in errors {
+                diagnostics.push(Diagnostic::error(error).with_file(CONFIG_FILE_NAME));
+            }
+            None
+        });
+        if let Some(variables) = variables {
+            match stylesheets
+                .iter_mut()
+                .find(|stylesheet| stylesheet.name == STYLESHEET)
+            {
+                Some(stylesheet) => {
+                    stylesheet.css.push('\n');
+                    stylesheet.css.push_str(&variables);
+                }
+                None => stylesheets.push(Stylesheet::new(STYLESHEET, variables)),
+            }
+        }
+        let leaf = layers.last().expect("at least one theme");
+        let engine = manifest::resolve_engine(
+            layers
+                .iter()
+                .map(|layer| (layer.name.as_str(), layer.manifest.as_ref())),
+        )?;
+        let options =
+            manifest::resolve_options(&declared_options, config).unwrap_or_else(|errors| {
+                for error in errors {
+                    diagnostics.push(Diagnostic::error(error).with_file(CONFIG_FILE_NAME));
+                }
+                Default::default()
+            });
+        let mut theme = Self {
+            stylesheets,
+            assets: Vec::new(),
+            engine: engine.create(),
+            templates: HashMap::new(),
+            data: ThemeRenderData {
+                name: leaf
+                    .manifest
+                    .as_ref()
+                    .map_or_else(|| leaf.name.clone(), |manifest| manifest.name.clone()),
+                version: leaf
+                    .manifest
+                    .as_ref()
+                    .and_then(|manifest| manifest.version.clone()),
+                options,
+            },
+            base_path: String::new(),
+        };
+        for layer in &layers {
+            theme.load_templates(source, layer, &mut diagnostics);
+            theme.load_folder(
+                source,
+                &layer.dir.join(PARTIALS_DIR),
+                false,
+                &mut diagnostics,
+            )?;
+            theme.load_folder(
+                source,
+                &layer.dir.join(SHORTCODES_DIR),
+                true,
+                &mut diagnostics,
+            )?;
+            theme.load_assets(source, &layer.dir.join(STATIC_DIR), &mut diagnostics)?;
+        }
+        for template in TEMPLATE_NAMES {
+            if !theme.templates.contains_key(template) {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "Theme `{}` has no `{}` template",
+                        leaf.name, template
+                    ))
+                    .with_file(leaf.dir.join(format!("{}.{}.html", leaf.name, template)))
+                    .in_template(template),
+                );
+            }
+        }
+        theme.set_base_path("");
+        Ok(diagnostics.into_result(theme)?)
+    }
+
+    /// Stylesheets of one theme in the chain, by output name.
+    ///
+    /// Without a `stylesheets` table in the manifest, that is `style` from `<name>.scss`,
+    /// `<name>.sass` or `<name>.css`, which only the base of the chain has to provide.
+    fn stylesheet_sources(
+        source: &SourceTree,
+        layer: &ThemeLayer,
+        is_base: bool,
+    ) -> Vec<(String, PathBuf)> {
+        if let Some(manifest) = layer.manifest.as_ref() {
+            if !manifest.stylesheets.is_empty() {
+                return manifest
+                    .stylesheets
+                    .iter()
+                    .map(|(name, path)| (name.clone(), layer.dir.join(path)))
+                    .collect();
+            }
+        }
+        let path = ["scss", "sass", "css"]
+            .iter()
+            .map(|extension| layer.dir.join(format!("{}.{}", layer.name, extension)))
+            .find(|path| source.is_file(path));
+        match (path, is_base) {
+            (Some(path), _) => vec![(STYLESHEET.to_string(), path)],
+            // Reported as missing when it can't be read
+            (None, true) => vec![(
+                STYLESHEET.to_string(),
+                layer.dir.join(format!("{}.css", layer.name)),
+            )],
+            (None, false) => Vec::new(),
+        }
+    }
+
+    /// Follows `extends` from the theme `name`, returning the chain with its base first.
+    fn resolve_layers(source: &SourceTree, name: &str) -> anyhow::Result<Vec<ThemeLayer>> {
+        let mut layers: Vec<ThemeLayer> = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            if layers.iter().any(|layer| layer.name == name) {
+                let mut chain = layers
+                    .iter()
+                    .map(|layer| layer.name.as_str())
+                    .collect::<Vec<_>>();
+                chain.push(&name);
+                bail!("Theme inheritance forms a cycle: {}", chain.join(" -> "))
+            }
+            let dir = Path::new(THEMES_DIR).join(&name);
+            if !source.exists(&dir) {
+                match layers.last() {
+                    Some(child) => bail!("Theme `{}` extends unknown theme `{}`", child.name, name),
+                    None => bail!("Unknown theme `{}`, expected a folder {:?}", name, dir),
+                }
+            }
+            let manifest = ThemeManifest::read(source, &dir)?;
+            next = manifest
+                .as_ref()
+                .and_then(|manifest| manifest.extends.clone());
+            layers.push(ThemeLayer {
+                name,
+                dir,
+                manifest,
+            });
+        }
+        layers.reverse();
+        Ok(layers)
+    }
+
+    /// Registers the templates of one theme in the chain, overriding those of its parents.
+    ///
+    /// Themes with a manifest listing their `templates` only load those, and must provide them.
+    fn load_templates(
+        &mut self,
+        source: &SourceTree,
+        layer: &ThemeLayer,
+        diagnostics: &mut Diagnostics,
+    ) {
+        let declared = layer
+            .manifest
+            .as_ref()
+            .and_then(|manifest| manifest.templates.clone());
+        let names = match &declared {
+            Some(declared) => declared.clone(),
+            None => Self::find_templates(source, layer),
+        };
+        for template in names {
+            let path = layer.dir.join(format!("{}.{}.html", layer.name, template));
+            if !source.is_file(&path) {
+                // Undeclared templates are optional, they may be inherited or left out
+                if declared.is_some() && !self.templates.contains_key(&template) {
+                    diagnostics.push(
+                        Diagnostic::error(format!(
+                            "Theme `{}` declares the `{}` template, but doesn't provide it",
+                            layer.name, template
+                        ))
+                        .with_file(&path)
+                        .in_template(&template),
+                    );
+                }
+                continue;
+            }
+            let template_source = match source.read_to_string(&path) {
+                Ok(template_source) => template_source,
+                Err(err) => {
+                    diagnostics.push(
+                        Diagnostic::error(format!("Unable to read template: {}", err))
+                            .with_file(&path)
+                            .in_template(&template),
+                    );
+                    continue;
+                }
+            };
+            if let Err(diagnostic) = self.engine.add_template(&template, &template_source) {
+                diagnostics.push(
+                    diagnostic
+                        .in_template(&template)
+                        .with_file(&path)
+                        .with_source(&template_source),
+                );
+            }
+            self.templates.insert(
+                template,
+                ThemeTemplate {
+                    path,
+                    source: template_source,
+                },
+            );
+        }
+    }
+
+    /// Names of all `<theme>.<template>.html` files in a theme's folder, e.g. `post.gallery`.
+    fn find_templates(source: &SourceTree, layer: &ThemeLayer) -> Vec<String> {
+        let prefix = format!("{}.", layer.name);
+        source
+            .list_files(&layer.dir)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str())
+            .filter_map(|file_name| file_name.strip_prefix(&prefix)?.strip_suffix(".html"))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Adds the files below a theme's `static` folder `dir`, replacing those of a parent theme
+    /// at the same path.
+    fn load_assets(
+        &mut self,
+        source: &SourceTree,
+        dir: &Path,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<()> {
+        if !source.exists(dir) {
+            return Ok(());
+        }
+        for path in source.walk_files(dir)? {
+            let asset_path = path
+                .strip_prefix(dir)
+                .unwrap_or(&path)
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let bytes = match source.read(&path) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    diagnostics.push(
+                        Diagnostic::error(format!("Unable to read static file: {}", err))
+                            .with_file(&path),
+                    );
+                    continue;
+                }
+            };
+            self.assets.retain(|asset| asset.path != asset_path);
+            self.assets.push(ThemeAsset {
+                path: asset_path,
+                bytes: bytes.into(),
+            });
+        }
+        self.assets.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(())
+    }
+
+    /// Registers every `*.hbs`, `*.jinja` and `*.html` file in `dir` as a partial named after the
+    /// file, or if `is_shortcodes` as the template of the shortcode named after the file.
+    fn load_folder(
+        &mut self,
+        source: &SourceTree,
+        dir: &Path,
+        is_shortcodes: bool,
+        diagnostics: &mut Diagnostics,
+    ) -> anyhow::Result<()> {
+        if !source.exists(dir) {
+            return Ok(());
+        }
+        for path in source.list_files(dir)? {
+            let name = match (path.file_stem(), path.extension()) {
+                (Some(name), Some(extension))
+                    if ["hbs", "jinja", "html"]
+                        .iter()
+                        .any(|known| extension == *known) =>
+                {
+                    match is_shortcodes {
+                        true => format!("{}{}", SHORTCODE_PREFIX, name.to_string_lossy()),
+                        false => name.to_string_lossy().into_owned(),
+                    }
+                }
+                _ => continue,
+            };
+            let partial = match source.read_to_string(&path) {
+                Ok(partial) => partial,
+                Err(err) => {
+                    diagnostics.push(
+                        Diagnostic::error(format!(
+                            "Unable to read {}: {}",
+                            if is_shortcodes {
+                                "shortcode"
+                            } else {
+                                "partial"
+                            },
+                            err
+                        ))
+                        .with_file(&path)
+                        .in_template(&name),
+                    );
+                    continue;
+                }
+            };
+            let added = match is_shortcodes {
+                true => self.engine.add_template(&name, &partial),
+                false => self.engine.add_partial(&name, &partial),
+            };
+            if let Err(diagnostic) = added {
+                diagnostics.push(
+                    diagnostic
+                        .in_template(&name)
+                        .with_file(&path)
+                        .with_source(&partial),
+                );
+            }
+            self.templates.insert(
+                name,
+                ThemeTemplate {
+                    path,
+                    source: partial,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ShortcodeRenderer for Theme {
+    fn shortcode_names(&self) -> Vec<&str> {
+        let mut names = self
+            .templates
+            .keys()
+            .filter_map(|name| name.strip_prefix(SHORTCODE_PREFIX))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    fn render_shortcode(
+        &self,
+        name: &str,
+        context: &serde_json::Value,
+    ) -> Result<String, Box<Diagnostic>> {
+        self.render_context(&format!("{}{}", SHORTCODE_PREFIX, name), context)
+    }
+}
+
+pub(crate) static THEMES_DIR: &str = "themes";
+
+/// Name of the main stylesheet, emitted as `style.css`.
+static STYLESHEET: &str = "style";
+
+pub(crate) static TEMPLATE_NAMES: [&str; 3] = ["base", "home", "post"];
+
+pub(crate) static PARTIALS_DIR: &str = "partials";
+
+pub(crate) static SHORTCODES_DIR: &str = "shortcodes";
+
+/// Files published as they are, like fonts and images, and linked with the `asset` helper.
+pub(crate) static STATIC_DIR: &str = "static";
+
+/// Shortcode templates are named `shortcodes/<name>`.
+static SHORTCODE_PREFIX: &str = "shortcodes/";
+
+/// How many posts the 404 page lists.
+static RECENT_POSTS_COUNT: usize = 5;
+
+static WORDS_PER_MINUTE: usize = 200;
+
+/// Estimates the minutes it takes to read `contents`, at least one.
+fn reading_time(contents: &str) -> usize {
+    let words = contents.split_whitespace().count();
+    words.div_ceil(WORDS_PER_MINUTE).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{post_format::PostFormat, source::MemoryTree};
+
+    fn post(path: &str, title: &str) -> Post {
+        post_with(path, title, "")
+    }
+
+    /// A post with extra metadata lines, e.g. `[//]: # (template: gallery)`.
+    fn post_with(path: &str, title: &str, metadata: &str) -> Post {
+        let contents = format!(
+            "[//]: # (title: {})\n{}[//]: # (published: true)\n[//]: # (published_at: 2024-01-01T00:00:00Z)\n\nText\n",
+            title, metadata
+        );
+        Post {
+            format: PostFormat::Markdown,
+            filename: path.into(),
+            metadata: PostFormat::Markdown.extract_metadata(&contents).unwrap(),
+            contents,
+        }
+    }
+
+    #[test]
+    fn post_listings_link_below_the_base_path() {
+        let hello = post("posts/hello.md", "Hello");
+        assert_eq!(PostIndex::new(&hello, "").link, "/posts/hello.html");
+        assert_eq!(
+            PostIndex::new(&hello, "/blog/").link,
+            "/blog/posts/hello.html"
+        );
+        let mut config = Config::new("Blog");
+        config.serve.base_path = "/blog".to_string();
+        let output_map = HashMap::from([(hello.get_final_file_name(), hello)]);
+        let home = RenderData::for_index(&config, &output_map).home.unwrap();
+        assert_eq!(home.posts[0].link, "/blog/posts/hello.html");
+        let not_found = RenderData::for_not_found(&config, &output_map)
+            .not_found
+            .unwrap();
+        assert_eq!(not_found.recent_posts[0].link, "/blog/posts/hello.html");
+    }
+    fn theme_with_home(home: &str) -> anyhow::Result<Theme> {
+        let source = SourceTree::from(
+            MemoryTree::new()
+                .with_file("themes/plain/plain.base.html", "{{{ page.content }}}")
+                .with_file("themes/plain/plain.home.html", home)
+                .with_file("themes/plain/plain.post.html", "")
+                .with_file("themes/plain/plain.css", "body {}")
+                .with_file("themes/plain/static/fonts/a.woff2", "font"),
+        );
+        let mut theme = Theme::load(&source, &ThemeConfig::new("plain"))?;
+        theme.set_base_path("/blog");
+        Ok(theme)
+    }
+
+    #[test]
+    fn assets_link_stylesheets_and_static_files() {
+        let theme =
+            theme_with_home("{{{asset \"style.css\"}}} {{{asset \"/fonts/a.woff2\"}}}").unwrap();
+        let data = RenderData::for_index(&Config::new("Blog"), &HashMap::new());
+        let font = &theme.assets[0];
+        assert_eq!(font.path, "fonts/a.woff2");
+        assert_eq!(
+            theme.render_index(data).unwrap(),
+            format!(
+                "/blog/{} /blog/fonts/a.woff2?v={:08x}",
+                theme.stylesheets[0].link(),
+                HashCache::hash_bytes(b"font")
+            )
+        );
+    }
+
+    /// Renders a post with the given metadata through a theme with a gallery template and a
+    /// wide layout.
+    fn render_post_with(metadata: &str) -> Result<String, Box<Diagnostic>> {
+        let source = SourceTree::from(
+            MemoryTree::new()
+                .with_file("themes/plain/plain.base.html", "base: {{{ page.content }}}")
+                .with_file(
+                    "themes/plain/plain.base.wide.html",
+                    "wide: {{{ page.content }}}",
+                )
+                .with_file("themes/plain/plain.home.html", "")
+                .with_file("themes/plain/plain.post.html", "post {{ post.title }}")
+                .with_file(
+                    "themes/plain/plain.post.gallery.html",
+                    "gallery {{ post.title }}",
+                )
+                .with_file("themes/plain/plain.css", "body {}"),
+        );
+        let theme = Theme::load(&source, &ThemeConfig::new("plain")).unwrap();
+        let post = post_with("posts/hello.md", "Hello", metadata);
+        let data = RenderData::for_post(&Config::new("Blog"), &post, Document::default());
+        theme.render_post(data)
+    }
+
+    #[test]
+    fn posts_select_their_template_and_layout() {
+        assert_eq!(render_post_with("").unwrap(), "base: post Hello");
+        assert_eq!(
+            render_post_with("[//]: # (template: gallery)\n").unwrap(),
+            "base: gallery Hello"
+        );
+        assert_eq!(
+            render_post_with("[//]: # (template: post.gallery.html)\n[//]: # (layout: wide)\n")
+                .unwrap(),
+            "wide: gallery Hello"
+        );
+    }
+
+    #[test]
+    fn missing_post_templates_and_layouts_are_errors() {
+        let err = render_post_with("[//]: # (template: slides)\n").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Theme `plain` has no template `post.slides` (available: post.gallery)"
+        );
+        let err = render_post_with("[//]: # (layout: narrow)\n").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Theme `plain` has no template `base.narrow` (available: base.wide)"
+        );
+    }
+
+    #[test]
+    fn unknown_assets_are_errors() {
+        let theme = theme_with_home("{{asset \"fonts/b.woff2\"}}").unwrap();
+        let data = RenderData::for_index(&Config::new("Blog"), &HashMap::new());
+        let err = theme.render_index(data).unwrap_err().to_string();
+        assert!(err.contains("Unknown asset `fonts/b.woff2`"), "{}", err);
+        assert!(err.contains("fonts/a.woff2, style.css"), "{}", err);
     }
 }
@@ -0,0 +1,223 @@
+//! Helpers available to every theme template.
+//!
+//! - `{{date post.published_at "%B %e, %Y"}}` formats a date, `%Y-%m-%d` by default
+//! - `{{truncate text 140}}` shortens text to a number of characters, appending `suffix="…"`
+//! - `{{slugify post.title}}` turns text into a URL-safe slug
+//! - `{{url_for "posts/hello.html"}}` prefixes a path with the blog's base path
+//! - `{{asset "style.css"}}` links a theme stylesheet, or a file from its `static` folder like
+//!   `{{asset "fonts/a.woff2"}}`, cache-busted or fingerprinted
+//! - `{{{markdown text}}}` renders inline Markdown
+//! - `{{{json value}}}` serializes a value, `pretty=true` indents it
+//! - `{{css theme.options.font_family}}` inserts a value into a `<style>` element
+//!
+//! Jinja themes call them as functions or filters instead, e.g.
+//! `{{ post.published_at | date("%B %e, %Y") }}` or `{{ text | markdown | safe }}`.
+
+use std::collections::HashMap;
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDate,
+};
+use serde_json::Value as JsonValue;
+
+use super::{
+    styles,
+    templating::{text, JsonMap, TemplateEngine, TemplateHelper},
+};
+use crate::{markdown::slugify, post_format::PostFormat};
+
+/// Registers all built-in helpers, linking to `assets` (path to published path) under `base_path`.
+pub fn register(engine: &mut dyn TemplateEngine, base_path: &str, assets: HashMap<String, String>) {
+    let base_path = base_path.trim_end_matches('/').to_string();
+    builtin(engine, "date", date);
+    builtin(engine, "truncate", truncate);
+    builtin(engine, "slugify", slugify_helper);
+    builtin(engine, "markdown", markdown);
+    builtin(engine, "json", json);
+    engine.add_helper(
+        "css",
+        TemplateHelper::raw(|params, hash| css(&Args::new("css", params, hash))),
+    );
+    let url_base_path = base_path.clone();
+    engine.add_helper(
+        "url_for",
+        TemplateHelper::new(move |params, hash| {
+            let path = text(Args::new("url_for", params, hash).param(0)?);
+            Ok(JsonValue::String(url_for(&url_base_path, &path)))
+        }),
+    );
+    engine.add_helper(
+        "asset",
+        TemplateHelper::new(move |params, hash| {
+            let path = text(Args::new("asset", params, hash).param(0)?);
+            let path = path.trim_start_matches('/');
+            let published = assets.get(path).ok_or_else(|| {
+                let mut known = assets.keys().map(String::as_str).collect::<Vec<_>>();
+                known.sort_unstable();
+                format!(
+                    "Unknown asset `{}`, expected one of: {}",
+                    path,
+                    known.join(", ")
+                )
+            })?;
+            Ok(JsonValue::String(url_for(&base_path, published)))
+        }),
+    );
+}
+
+fn builtin(
+    engine: &mut dyn TemplateEngine,
+    name: &'static str,
+    helper: fn(&Args) -> Result<JsonValue, String>,
+) {
+    engine.add_helper(
+        name,
+        TemplateHelper::new(move |params, hash| helper(&Args::new(name, params, hash))),
+    );
+}
+
+/// The arguments a helper was called with.
+struct Args<'a> {
+    name: &'static str,
+    params: &'a [JsonValue],
+    hash: &'a JsonMap,
+}
+
+impl<'a> Args<'a> {
+    fn new(name: &'static str, params: &'a [JsonValue], hash: &'a JsonMap) -> Self {
+        Self { name, params, hash }
+    }
+
+    fn param(&self, index: usize) -> Result<&'a JsonValue, String> {
+        self.params.get(index).ok_or_else(|| {
+            format!(
+                "Helper `{}` expects at least {} parameter{}",
+                self.name,
+                index + 1,
+                if index == 0 { "" } else { "s" }
+            )
+        })
+    }
+
+    fn hash_get(&self, key: &str) -> Option<&'a JsonValue> {
+        self.hash.get(key)
+    }
+}
+
+fn url_for(base_path: &str, path: &str) -> String {
+    if path.contains("://") || path.starts_with("//") {
+        return path.to_string();
+    }
+    format!("{}/{}", base_path, path.trim_start_matches('/'))
+}
+
+fn date(args: &Args) -> Result<JsonValue, String> {
+    let value = text(args.param(0)?);
+    let format = args
+        .params
+        .get(1)
+        .map(text)
+        .unwrap_or_else(|| "%Y-%m-%d".to_string());
+    if StrftimeItems::new(&format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Helper `date` got an invalid format `{}`", format));
+    }
+    // Posts without a date have `N/A` as their `published_at`
+    if value.is_empty() || value == "N/A" {
+        return Ok(JsonValue::String(String::new()));
+    }
+    let formatted = if let Ok(date) = DateTime::parse_from_rfc3339(&value) {
+        date.format(&format).to_string()
+    } else if let Ok(date) = NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+        date.format(&format).to_string()
+    } else {
+        return Err(format!("Helper `date` can't parse `{}` as a date", value));
+    };
+    Ok(JsonValue::String(formatted))
+}
+
+fn truncate(args: &Args) -> Result<JsonValue, String> {
+    let value = text(args.param(0)?);
+    let length = args
+        .param(1)?
+        .as_u64()
+        .ok_or_else(|| "Helper `truncate` expects a length".to_string())? as usize;
+    let suffix = args
+        .hash_get("suffix")
+        .map(text)
+        .unwrap_or_else(|| "…".to_string());
+    if value.chars().count() <= length {
+        return Ok(JsonValue::String(value));
+    }
+    let truncated = value.chars().take(length).collect::<String>();
+    Ok(JsonValue::String(format!(
+        "{}{}",
+        truncated.trim_end(),
+        suffix
+    )))
+}
+
+fn slugify_helper(args: &Args) -> Result<JsonValue, String> {
+    Ok(JsonValue::String(slugify(&text(args.param(0)?))))
+}
+
+fn markdown(args: &Args) -> Result<JsonValue, String> {
+    let html = PostFormat::Markdown
+        .to_html(text(args.param(0)?))
+        .map_err(|err| format!("Helper `markdown` failed: {}", err))?;
+    // Render a single paragraph inline, without the wrapping `<p>`
+    let html = html.trim_end();
+    let inline = html
+        .strip_prefix("<p>")
+        .and_then(|html| html.strip_suffix("</p>"))
+        .filter(|inner| !inner.contains("<p>"));
+    Ok(JsonValue::String(inline.unwrap_or(html).to_string()))
+}
+
+/// Escapes a value for a CSS declaration, see [`styles::escape`].
+fn css(args: &Args) -> Result<JsonValue, String> {
+    Ok(JsonValue::String(styles::escape(&text(args.param(0)?))))
+}
+
+fn json(args: &Args) -> Result<JsonValue, String> {
+    let value = args.param(0)?;
+    let pretty = args
+        .hash_get("pretty")
+        .and_then(JsonValue::as_bool)
+        .unwrap_or(false);
+    let json = match pretty {
+        true => serde_json::to_string_pretty(value),
+        false => serde_json::to_string(value),
+    };
+    let json = json.map_err(|err| format!("Helper `json` failed: {}", err))?;
+    Ok(JsonValue::String(json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn css_of(value: &str) -> String {
+        let params = [JsonValue::String(value.to_string())];
+        let hash = JsonMap::new();
+        text(&css(&Args::new("css", &params, &hash)).unwrap())
+    }
+
+    #[test]
+    fn css_values_keep_fonts_and_colors() {
+        assert_eq!(css_of("system-ui, sans-serif"), "system-ui, sans-serif");
+        assert_eq!(
+            css_of("\"Inter Tight\", 'Fira Sans'"),
+            "\"Inter Tight\", 'Fira Sans'"
+        );
+        assert_eq!(css_of("hsl(0, 0%, 25%)"), "hsl(0, 0%, 25%)");
+    }
+
+    #[test]
+    fn css_values_cannot_end_the_declaration_or_style() {
+        let escaped = css_of("x; } </style><script>alert(1)</script>");
+        assert!(!escaped.contains([';', '}', '<', '>']), "{}", escaped);
+        assert_eq!(css_of("a\nb"), "a\\a b");
+        assert_eq!(css_of("\"unclosed"), "\\22 unclosed");
+    }
+}
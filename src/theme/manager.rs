@@ -0,0 +1,474 @@
+//! Creating, listing, packaging, installing and validating the themes of a project.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+
+use super::{
+    manifest::{ThemeConfig, ThemeManifest, MANIFEST_FILE_NAME},
+    RenderData, Theme, PARTIALS_DIR, THEMES_DIR,
+};
+use crate::{
+    build::Post,
+    config::Config,
+    diagnostics::{Diagnostic, Diagnostics},
+    markdown::ConvertOptions,
+    post_format::PostFormat,
+    post_metadata::PostMetadata,
+    site,
+    source::SourceTree,
+};
+
+/// Files a new theme starts out with, by the part of their name after `<name>.`.
+static SKELETON: [(&str, &str); 4] = [
+    ("base.html", include_str!("skeleton/base.html")),
+    ("home.html", include_str!("skeleton/home.html")),
+    ("post.html", include_str!("skeleton/post.html")),
+    ("404.html", include_str!("skeleton/404.html")),
+];
+
+static SKELETON_CSS: &str = include_str!("skeleton/style.css");
+
+/// Files left out of theme packages.
+static IGNORED_FILES: [&str; 2] = [".DS_Store", "Thumbs.db"];
+
+/// A theme installed in a project, as shown by `nail theme list`.
+#[derive(Debug, Clone)]
+pub struct ThemeSummary {
+    pub name: String,
+    pub manifest: Option<ThemeManifest>,
+    /// Whether `config.toml` selects this theme.
+    pub active: bool,
+}
+
+/// Manages the `themes` directory of a project.
+#[derive(Debug)]
+pub struct ThemeManager {
+    root: PathBuf,
+}
+
+impl ThemeManager {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn themes_dir(&self) -> PathBuf {
+        self.root.join(THEMES_DIR)
+    }
+
+    /// The project's config, if `root` is a project and not just a folder of themes.
+    fn config(&self) -> Option<Config> {
+        Config::load(&self.root).ok()
+    }
+
+    /// The theme `name`, or the theme selected in `config.toml`.
+    pub fn resolve_name(&self, name: Option<String>) -> anyhow::Result<String> {
+        match (name, self.config()) {
+            (Some(name), _) => Ok(name),
+            (None, Some(config)) => Ok(config.theme.name),
+            (None, None) => bail!("Specify a theme or run this inside a project"),
+        }
+    }
+
+    /// The folder of theme `name`, refusing names like `../posts` that would leave `themes/`.
+    fn theme_dir(&self, name: &str) -> anyhow::Result<PathBuf> {
+        let mut components = Path::new(name).components();
+        let is_plain = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) && !name.contains(['/', '\\']);
+        if !is_plain {
+            bail!(
+                "Invalid theme name `{}`, expected a plain folder name like `minimal`",
+                name
+            )
+        }
+        Ok(self.themes_dir().join(name))
+    }
+
+    /// Scaffolds a new theme, or only its manifest and stylesheet if it `extends` another.
+    pub fn create(&self, name: &str, extends: Option<String>, force: bool) -> anyhow::Result<()> {
+        let theme_dir = self.theme_dir(name)?;
+        if !force && theme_dir.exists() {
+            bail!("The theme `{}` already exists!", theme_dir.display())
+        }
+        fs::create_dir_all(&theme_dir)?;
+        let manifest = ThemeManifest {
+            name: name.to_string(),
+            version: Some("0.1.0".to_string()),
+            author: None,
+            nail: Some(format!(">={}", env!("CARGO_PKG_VERSION"))),
+            templates: None,
+            extends: extends.clone(),
+            engine: None,
+            stylesheets: BTreeMap::new(),
+            options: BTreeMap::new(),
+        };
+        fs::write(
+            theme_dir.join(MANIFEST_FILE_NAME),
+            toml::to_string_pretty(&manifest)?,
+        )?;
+        match &extends {
+            // A child theme only contains what it changes
+            Some(parent) => fs::write(
+                theme_dir.join(format!("{}.css", name)),
+                format!("/* Appended to the stylesheet of `{}` */\n", parent),
+            )?,
+            None => {
+                fs::write(theme_dir.join(format!("{}.css", name)), SKELETON_CSS)?;
+                for (file_name, contents) in SKELETON {
+                    fs::write(theme_dir.join(format!("{}.{}", name, file_name)), contents)?;
+                }
+                fs::create_dir_all(theme_dir.join(PARTIALS_DIR))?;
+            }
+        }
+        println!("Created theme `{}` in `./{}/{}`", name, THEMES_DIR, name);
+        Ok(())
+    }
+
+    /// All themes in the project, sorted by name.
+    pub fn list(&self) -> anyhow::Result<Vec<ThemeSummary>> {
+        let themes_dir = self.themes_dir();
+        if !themes_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let active = self.config().map(|config| config.theme.name);
+        let source = SourceTree::disk(&self.root);
+        let mut themes = Vec::new();
+        for entry in fs::read_dir(&themes_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let manifest = ThemeManifest::read(&source, &Path::new(THEMES_DIR).join(&name))?;
+            themes.push(ThemeSummary {
+                active: active.as_deref() == Some(name.as_str()),
+                name,
+                manifest,
+            });
+        }
+        themes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(themes)
+    }
+
+    /// Renders every template of a theme with sample posts, collecting all errors.
+    ///
+    /// Options set in `config.toml` are checked as well if the project uses the theme.
+    pub fn validate(&self, name: &str) -> anyhow::Result<()> {
+        let mut config = self.config().unwrap_or_else(|| Config::new("Sample Blog"));
+        if config.theme.name != name {
+            config.theme = ThemeConfig::new(name);
+        }
+        let source = SourceTree::disk(&self.root);
+        let theme = Theme::load(&source, &config.theme)?;
+        let posts = Self::sample_posts();
+        let output_map = posts
+            .iter()
+            .map(|post| (post.get_final_file_name(), post.clone()))
+            .collect::<HashMap<_, _>>();
+        let mut diagnostics = Diagnostics::new();
+        // Templates may rely on the project's data files
+        let site_data = site::load_data(&source, &mut diagnostics);
+        let with_site_data = |mut data: RenderData| {
+            data.site.data = site_data.clone();
+            data
+        };
+        let post_data = |post: &Post| {
+            let document = post
+                .format
+                .convert(&post.contents, &ConvertOptions::new(&config.markdown))?;
+            anyhow::Ok(with_site_data(RenderData::for_post(
+                &config, post, document,
+            )))
+        };
+        let mut check = |page: &str, result: Result<String, Box<Diagnostic>>| {
+            if let Err(diagnostic) = result {
+                diagnostics.push(Diagnostic {
+                    message: format!("{} (while rendering {})", diagnostic.message, page),
+                    ..*diagnostic
+                });
+            }
+        };
+        check(
+            "the index",
+            theme.render_index(with_site_data(RenderData::for_index(&config, &output_map))),
+        );
+        for post in &posts {
+            check("a sample post", theme.render_post(post_data(post)?));
+        }
+        if theme.has_not_found_page() {
+            check(
+                "the 404 page",
+                theme.render_not_found(with_site_data(RenderData::for_not_found(
+                    &config,
+                    &output_map,
+                ))),
+            );
+        }
+        // Alternative post templates and layouts are rendered with a sample post
+        for template in theme.template_names() {
+            let mut post = posts[0].clone();
+            match template.split_once('.') {
+                Some(("post", _)) => post.metadata.template = Some(template.to_string()),
+                Some(("base", "home" | "post" | "404")) => continue,
+                Some(("base", _)) => post.metadata.layout = Some(template.to_string()),
+                _ => continue,
+            }
+            check(
+                &format!("a sample post with `{}`", template),
+                theme.render_post(post_data(&post)?),
+            );
+        }
+        diagnostics.into_result(())?;
+        println!("Theme `{}` is valid", name);
+        Ok(())
+    }
+
+    /// Validates a theme and archives it as `<name>-<version>.zip` in `out_dir`.
+    pub fn package(&self, name: &str, out_dir: &Path) -> anyhow::Result<PathBuf> {
+        let theme_dir = self.themes_dir().join(name);
+        let manifest = ThemeManifest::read(
+            &SourceTree::disk(&self.root),
+            &Path::new(THEMES_DIR).join(name),
+        )?
+        .with_context(|| {
+            format!(
+                "Theme `{}` has no {}, which packages require",
+                name, MANIFEST_FILE_NAME
+            )
+        })?;
+        let version = manifest.version.as_deref().with_context(|| {
+            format!(
+                "Theme `{}` has no `version` in its {}",
+                name, MANIFEST_FILE_NAME
+            )
+        })?;
+        self.validate(name)?;
+        fs::create_dir_all(out_dir)?;
+        let archive_path = out_dir.join(format!("{}-{}.zip", name, version));
+        let mut archive = zip::ZipWriter::new(File::create(&archive_path)?);
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for path in Self::files(&theme_dir)? {
+            let relative = path.strip_prefix(&theme_dir)?;
+            let entry = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            archive.start_file(entry, options)?;
+            archive.write_all(&fs::read(&path)?)?;
+        }
+        archive.finish()?;
+        println!("Packaged theme `{}` as `{}`", name, archive_path.display());
+        Ok(archive_path)
+    }
+
+    /// Installs a theme from a package or a folder, returning its name.
+    pub fn install(&self, path: &Path, force: bool) -> anyhow::Result<String> {
+        let files = match path.is_dir() {
+            true => Self::files(path)?
+                .into_iter()
+                .map(|file| {
+                    let contents = fs::read(&file)?;
+                    Ok((file.strip_prefix(path)?.to_path_buf(), contents))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            false => Self::unpack(path)?,
+        };
+        let manifest = files
+            .iter()
+            .find(|(file, _)| file == Path::new(MANIFEST_FILE_NAME))
+            .map(|(file, contents)| {
+                let manifest = String::from_utf8_lossy(contents);
+                ThemeManifest::parse(&manifest, &path.join(file))
+            })
+            .transpose()?;
+        let name = match &manifest {
+            Some(manifest) => manifest.name.clone(),
+            // Packages are named `<name>-<version>.zip`
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .map(|stem| match stem.rsplit_once('-') {
+                    Some((name, version)) if version.starts_with(|c: char| c.is_ascii_digit()) => {
+                        name.to_string()
+                    }
+                    _ => stem.into_owned(),
+                })
+                .context("Unable to tell the theme's name")?,
+        };
+        let theme_dir = self.theme_dir(&name)?;
+        if theme_dir.exists() {
+            if !force {
+                bail!("The theme `{}` already exists!", theme_dir.display())
+            }
+            fs::remove_dir_all(&theme_dir)?;
+        }
+        for (file, _) in &files {
+            if !file.components().all(|c| matches!(c, Component::Normal(_))) {
+                bail!("Invalid file `{}` in theme `{}`", file.display(), name)
+            }
+        }
+        for (file, contents) in files {
+            let target = theme_dir.join(file);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(target, contents)?;
+        }
+        let version = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.version.as_deref())
+            .map(|version| format!(" {}", version))
+            .unwrap_or_default();
+        println!(
+            "Installed theme `{}`{} in `./{}/{}`",
+            name, version, THEMES_DIR, name
+        );
+        if let Some(parent) = manifest.and_then(|manifest| manifest.extends) {
+            if !self.themes_dir().join(&parent).is_dir() {
+                println!(
+                    "| Theme `{}` extends `{}`, which isn't installed yet",
+                    name, parent
+                );
+            }
+        }
+        Ok(name)
+    }
+
+    /// Reads all files of a theme package, with paths relative to the theme folder.
+    fn unpack(path: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<u8>)>> {
+        let file =
+            File::open(path).with_context(|| format!("Unable to open `{}`", path.display()))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("`{}` isn't a theme package", path.display()))?;
+        let mut files = Vec::new();
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+            // Refuses entries like `../config.toml` that would escape the theme folder
+            let name = entry
+                .enclosed_name()
+                .with_context(|| format!("Package contains an invalid path `{}`", entry.name()))?;
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            files.push((name, contents));
+        }
+        Ok(files)
+    }
+
+    /// All files below `dir`, sorted, without the ones packages leave out.
+    fn files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("Unable to read `{}`", dir.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                files.extend(Self::files(&path)?);
+            } else if !IGNORED_FILES.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Posts exercising the usual Markdown features, to render templates with.
+    fn sample_posts() -> Vec<Post> {
+        let post = |title: &str, published_at: &str, contents: &str| Post {
+            format: PostFormat::Markdown,
+            filename: PathBuf::from("posts")
+                .join(format!("{}.md", title.to_lowercase().replace(' ', "_"))),
+            metadata: PostMetadata {
+                title: title.to_string(),
+                published: true,
+                published_at: published_at.to_string(),
+                template: None,
+                layout: None,
+                toc: None,
+            },
+            contents: contents.to_string(),
+        };
+        vec![
+            post(
+                "Hello World",
+                "2024-01-15T09:30:00+00:00",
+                "# Hello World\n\n[[toc]]\n\nSome *emphasis*, a [link](https://example.com) and \
+                 `code`.\n\n## A List\n\n- One\n- Two\n\n## A Quote\n\n> A quote\n\n\
+                 ```rust\nfn main() {}\n```\n",
+            ),
+            post("Second Post", "N/A", "A post without a date.\n"),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nail-themes-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn theme_names_must_be_plain_folder_names() {
+        let manager = ThemeManager::new("project");
+        assert!(manager.theme_dir("minimal").is_ok());
+        assert!(manager.theme_dir("my-theme.v2").is_ok());
+        for name in [
+            "",
+            ".",
+            "..",
+            "../posts",
+            "a/b",
+            "a\\b",
+            "/tmp",
+            "themes/..",
+        ] {
+            assert!(manager.theme_dir(name).is_err(), "accepted `{}`", name);
+        }
+    }
+
+    #[test]
+    fn installing_cannot_replace_folders_outside_themes() {
+        let root = temp_dir("escape");
+        fs::create_dir_all(root.join("posts")).unwrap();
+        fs::write(root.join("posts/first.md"), "# First").unwrap();
+        let theme = root.join("download");
+        fs::create_dir_all(&theme).unwrap();
+        fs::write(theme.join(MANIFEST_FILE_NAME), "name = \"../posts\"\n").unwrap();
+        let result = ThemeManager::new(&root).install(&theme, true);
+        assert!(result.is_err());
+        assert!(root.join("posts/first.md").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn installing_a_folder_copies_it_into_themes() {
+        let root = temp_dir("install");
+        let theme = root.join("download");
+        fs::create_dir_all(theme.join(PARTIALS_DIR)).unwrap();
+        fs::write(theme.join(MANIFEST_FILE_NAME), "name = \"plain\"\n").unwrap();
+        fs::write(theme.join(PARTIALS_DIR).join("footer.html"), "<footer>").unwrap();
+        let manager = ThemeManager::new(&root);
+        assert_eq!(manager.install(&theme, false).unwrap(), "plain");
+        let installed = manager.themes_dir().join("plain");
+        assert!(installed.join(MANIFEST_FILE_NAME).exists());
+        assert!(installed.join(PARTIALS_DIR).join("footer.html").exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
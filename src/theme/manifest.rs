@@ -0,0 +1,412 @@
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use anyhow::{bail, Context};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::templating::EngineKind;
+use crate::source::SourceTree;
+
+pub(crate) static MANIFEST_FILE_NAME: &str = "theme.toml";
+
+/// The theme selected in `config.toml`.
+///
+/// Either just a name, `theme = "minimal"`, or a `[theme]` table whose
+/// `[theme.options]` set the options declared in the theme's manifest and whose
+/// `[theme.variables]` become CSS custom properties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThemeConfig {
+    pub name: String,
+    pub options: toml::value::Table,
+    pub variables: toml::value::Table,
+}
+
+impl ThemeConfig {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            options: toml::value::Table::new(),
+            variables: toml::value::Table::new(),
+        }
+    }
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self::new("minimal")
+    }
+}
+
+impl fmt::Display for ThemeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ThemeConfigRepr {
+    Name(String),
+    Table {
+        name: String,
+        #[serde(default, skip_serializing_if = "toml::value::Table::is_empty")]
+        options: toml::value::Table,
+        #[serde(default, skip_serializing_if = "toml::value::Table::is_empty")]
+        variables: toml::value::Table,
+    },
+}
+
+impl Serialize for ThemeConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Keep `theme = "name"` unless there are options or variables to write
+        let repr = match self.options.is_empty() && self.variables.is_empty() {
+            true => ThemeConfigRepr::Name(self.name.clone()),
+            false => ThemeConfigRepr::Table {
+                name: self.name.clone(),
+                options: self.options.clone(),
+                variables: self.variables.clone(),
+            },
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match ThemeConfigRepr::deserialize(deserializer) {
+            Ok(ThemeConfigRepr::Name(name)) => Ok(Self::new(name)),
+            Ok(ThemeConfigRepr::Table {
+                name,
+                options,
+                variables,
+            }) => Ok(Self {
+                name,
+                options,
+                variables,
+            }),
+            Err(_) => Err(de::Error::custom(
+                "expected a theme name or a table with `name`, `options` and `variables`",
+            )),
+        }
+    }
+}
+
+/// A theme's `theme.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThemeManifest {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Required nail version, e.g. `>=0.2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nail: Option<String>,
+    /// Templates the theme provides; all templates found if omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templates: Option<Vec<String>>,
+    /// Parent theme whose templates, partials, stylesheet and options are inherited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Template engine, `handlebars` unless inherited from the parent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<EngineKind>,
+    /// Stylesheets by output name, e.g. `print = "print.scss"` for `print.css`.
+    ///
+    /// Defaults to `style` from `<name>.scss` or `<name>.css`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stylesheets: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, ThemeOption>,
+}
+
+/// An option users may set under `[theme.options]`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThemeOption {
+    #[serde(rename = "type")]
+    pub kind: OptionType,
+    pub default: toml::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OptionType {
+    String,
+    Color,
+    Bool,
+    Integer,
+    Float,
+}
+
+impl fmt::Display for OptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionType::String => write!(f, "string"),
+            OptionType::Color => write!(f, "color"),
+            OptionType::Bool => write!(f, "bool"),
+            OptionType::Integer => write!(f, "integer"),
+            OptionType::Float => write!(f, "float"),
+        }
+    }
+}
+
+impl OptionType {
+    /// Whether `value` is valid for an option of this type.
+    pub fn accepts(&self, value: &toml::Value) -> bool {
+        match (self, value) {
+            (OptionType::String, toml::Value::String(_)) => true,
+            (OptionType::Color, toml::Value::String(color)) => is_color(color),
+            (OptionType::Bool, toml::Value::Boolean(_)) => true,
+            (OptionType::Integer, toml::Value::Integer(_)) => true,
+            (OptionType::Float, toml::Value::Float(_) | toml::Value::Integer(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Accepts hex colors, CSS color functions and named colors.
+fn is_color(color: &str) -> bool {
+    if let Some(hex) = color.strip_prefix('#') {
+        return [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit());
+    }
+    let functions = [
+        "rgb(", "rgba(", "hsl(", "hsla(", "hwb(", "lab(", "lch(", "oklch(", "var(",
+    ];
+    // Arguments are numbers, units, names and nested functions, which can't leave the value
+    let is_argument = |c: char| c.is_ascii_alphanumeric() || " ,.%/+-#()".contains(c);
+    functions.iter().any(|function| color.starts_with(function))
+        && color.ends_with(')')
+        && color.chars().all(is_argument)
+        || (!color.is_empty() && color.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+impl ThemeManifest {
+    /// Reads `theme.toml` from `theme_dir`, if the theme has one.
+    pub fn read(source: &SourceTree, theme_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = theme_dir.join(MANIFEST_FILE_NAME);
+        if !source.is_file(&path) {
+            return Ok(None);
+        }
+        let manifest = source.read_to_string(&path)?;
+        Ok(Some(Self::parse(&manifest, &path)?))
+    }
+
+    /// Parses and checks a manifest read from `path`.
+    pub fn parse(manifest: &str, path: &Path) -> anyhow::Result<Self> {
+        let manifest = toml::from_str::<Self>(manifest)
+            .with_context(|| format!("Invalid theme manifest {}", path.display()))?;
+        manifest.check_nail_version()?;
+        for (name, option) in &manifest.options {
+            if !option.kind.accepts(&option.default) {
+                bail!(
+                    "Theme option `{}` in {} has a default that isn't a valid {}",
+                    name,
+                    path.display(),
+                    option.kind
+                )
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn check_nail_version(&self) -> anyhow::Result<()> {
+        let requirement = match &self.nail {
+            Some(requirement) => requirement,
+            None => return Ok(()),
+        };
+        let requirement = semver::VersionReq::parse(requirement).with_context(|| {
+            format!(
+                "Theme `{}` has an invalid nail version requirement `{}`",
+                self.name, requirement
+            )
+        })?;
+        let version = semver::Version::parse(env!("CARGO_PKG_VERSION"))?;
+        if !requirement.matches(&version) {
+            bail!(
+                "Theme `{}` requires nail {}, but this is nail {}",
+                self.name,
+                requirement,
+                version
+            )
+        }
+        Ok(())
+    }
+}
+
+/// Picks the template engine of a chain of themes, given root first.
+///
+/// Themes inherit their parent's engine, since they share its templates and partials.
+pub fn resolve_engine<'a>(
+    layers: impl IntoIterator<Item = (&'a str, Option<&'a ThemeManifest>)>,
+) -> anyhow::Result<EngineKind> {
+    let mut resolved: Option<(&str, EngineKind)> = None;
+    for (name, manifest) in layers {
+        let engine = match (manifest.and_then(|manifest| manifest.engine), resolved) {
+            (Some(engine), _) => engine,
+            (None, None) => EngineKind::default(),
+            (None, Some(_)) => continue,
+        };
+        match resolved {
+            Some((parent, parent_engine)) if parent_engine != engine => bail!(
+                "Theme `{}` uses {} templates, but extends `{}` which uses {}",
+                name,
+                engine,
+                parent,
+                parent_engine
+            ),
+            _ => resolved = Some((name, engine)),
+        }
+    }
+    Ok(resolved.map(|(_, engine)| engine).unwrap_or_default())
+}
+
+/// Combines option declarations with the values set in `config.toml`.
+///
+/// Returns the resolved values, or a message for each unknown or invalid value.
+pub fn resolve_options(
+    declared: &BTreeMap<String, ThemeOption>,
+    config: &ThemeConfig,
+) -> Result<serde_json::Map<String, serde_json::Value>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut options = serde_json::Map::new();
+    for (name, option) in declared {
+        let value = match config.options.get(name) {
+            Some(value) if !option.kind.accepts(value) => {
+                errors.push(format!(
+                    "Theme option `{}` expects a {}, found `{}`",
+                    name, option.kind, value
+                ));
+                continue;
+            }
+            Some(value) => value,
+            None => &option.default,
+        };
+        match serde_json::to_value(value) {
+            Ok(value) => {
+                options.insert(name.clone(), value);
+            }
+            Err(err) => errors.push(format!("Theme option `{}` is invalid: {}", name, err)),
+        }
+    }
+    for name in config.options.keys() {
+        if !declared.contains_key(name) {
+            let mut known = declared.keys().map(String::as_str).collect::<Vec<_>>();
+            if known.is_empty() {
+                known.push("none");
+            }
+            errors.push(format!(
+                "Theme `{}` has no option `{}` (available: {})",
+                config.name,
+                name,
+                known.join(", ")
+            ));
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(options),
+        false => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(source: &str) -> ThemeManifest {
+        ThemeManifest::parse(source, Path::new("theme.toml")).unwrap()
+    }
+
+    fn options() -> BTreeMap<String, ThemeOption> {
+        manifest(
+            r##"
+            name = "dark"
+            [options.accent]
+            type = "color"
+            default = "#fff"
+            [options.wide]
+            type = "bool"
+            default = false
+            "##,
+        )
+        .options
+    }
+
+    #[test]
+    fn options_fall_back_to_their_defaults() {
+        let mut config = ThemeConfig::new("dark");
+        config
+            .options
+            .insert("wide".into(), toml::Value::Boolean(true));
+        let resolved = resolve_options(&options(), &config).unwrap();
+        assert_eq!(resolved["accent"], "#fff");
+        assert_eq!(resolved["wide"], true);
+    }
+
+    #[test]
+    fn invalid_and_unknown_options_are_reported() {
+        let mut config = ThemeConfig::new("dark");
+        config.options.insert("accent".into(), "red;}".into());
+        config
+            .options
+            .insert("typo".into(), toml::Value::Integer(1));
+        let errors = resolve_options(&options(), &config).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0].contains("`accent` expects a color"),
+            "{:?}",
+            errors
+        );
+        assert!(
+            errors[1].contains("no option `typo` (available: accent, wide)"),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn child_themes_inherit_the_engine() {
+        let parent = manifest("name = \"base\"\nengine = \"jinja\"");
+        let child = manifest("name = \"child\"");
+        let layers = [
+            ("base", Some(&parent)),
+            ("child", Some(&child)),
+            ("plain", None),
+        ];
+        assert_eq!(resolve_engine(layers).unwrap(), EngineKind::Jinja);
+        assert_eq!(
+            resolve_engine([("plain", None)]).unwrap(),
+            EngineKind::Handlebars
+        );
+    }
+
+    #[test]
+    fn child_themes_cannot_switch_engines() {
+        let parent = manifest("name = \"base\"");
+        let child = manifest("name = \"child\"\nengine = \"jinja\"");
+        let err = resolve_engine([("base", Some(&parent)), ("child", Some(&child))]).unwrap_err();
+        assert!(err.to_string().contains("extends `base`"), "{}", err);
+    }
+
+    #[test]
+    fn colors_are_hex_names_or_functions() {
+        for color in [
+            "#fff",
+            "#336699cc",
+            "rebeccapurple",
+            "hsl(0,0%,25%)",
+            "rgb(0 0 0 / 50%)",
+        ] {
+            assert!(is_color(color), "rejected `{}`", color);
+        }
+        for color in [
+            "#ff",
+            "",
+            "red;",
+            "rgb(0,0,0",
+            "rgb(0,0,0);}</style><script>x()",
+        ] {
+            assert!(!is_color(color), "accepted `{}`", color);
+        }
+    }
+}
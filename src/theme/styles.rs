@@ -0,0 +1,221 @@
+//! Theme stylesheets: plain CSS, Sass entry points and the variables injected from `config.toml`.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use crate::{cache::HashCache, diagnostics::Diagnostic, source::SourceTree};
+
+/// A stylesheet emitted to the output directory as `<name>.css`.
+#[derive(Debug, Clone)]
+pub struct Stylesheet {
+    pub name: String,
+    pub css: String,
+    fingerprint: bool,
+}
+
+impl Stylesheet {
+    pub fn new(name: impl Into<String>, css: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            css: css.into(),
+            fingerprint: false,
+        }
+    }
+
+    /// File name in the output directory, e.g. `style.css`, or `style.1a2b3c4d.css` once
+    /// fingerprinted.
+    pub fn file_name(&self) -> String {
+        match self.fingerprint {
+            true => format!("{}.{:08x}.css", self.name, self.hash()),
+            false => format!("{}.css", self.name),
+        }
+    }
+
+    /// Path templates link to, with a cache-busting query unless the name is fingerprinted.
+    pub fn link(&self) -> String {
+        match self.fingerprint {
+            true => self.file_name(),
+            false => format!("{}?v={:08x}", self.file_name(), self.hash()),
+        }
+    }
+
+    /// Minifies the stylesheet and includes a hash of its contents in its file name.
+    pub fn optimize(&mut self) -> anyhow::Result<()> {
+        let options = grass::Options::default()
+            .input_syntax(grass::InputSyntax::Css)
+            .style(grass::OutputStyle::Compressed);
+        self.css = grass::from_string(self.css.as_str(), &options)
+            .map_err(|err| anyhow!("Unable to minify `{}.css`: {}", self.name, err))?;
+        self.fingerprint = true;
+        Ok(())
+    }
+
+    fn hash(&self) -> u32 {
+        HashCache::hash_contents(&self.css)
+    }
+}
+
+/// Reads a stylesheet, compiling `.scss` and `.sass` files.
+///
+/// Imports are resolved relative to the importing file, then in `load_paths`.
+pub fn read(
+    source: &SourceTree,
+    path: &Path,
+    load_paths: &[PathBuf],
+) -> Result<String, Box<Diagnostic>> {
+    let syntax = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("scss") => grass::InputSyntax::Scss,
+        Some("sass") => grass::InputSyntax::Sass,
+        _ => {
+            return source.read_to_string(path).map_err(|err| {
+                Box::new(
+                    Diagnostic::error(format!("Unable to read stylesheet: {}", err))
+                        .with_file(path),
+                )
+            })
+        }
+    };
+    let fs = SourceFs(source);
+    let options = grass::Options::default()
+        .fs(&fs)
+        .load_paths(load_paths)
+        .input_syntax(syntax);
+    grass::from_path(path, &options).map_err(|err| Box::new(sass_diagnostic(*err, path)))
+}
+
+/// Points a Sass error at the file and line it occurred in, which may be an imported one.
+fn sass_diagnostic(err: grass::Error, path: &Path) -> Diagnostic {
+    let fallback = format!("Unable to compile stylesheet: {}", err);
+    match err.kind() {
+        grass::ErrorKind::ParseError { message, loc, .. } => {
+            let file = loc.file.name().trim_start_matches("./");
+            Diagnostic::error(format!("Unable to compile stylesheet: {}", message))
+                .with_file(file)
+                .at(Some(loc.begin.line + 1), Some(loc.begin.column + 1))
+                .with_source(loc.file.source())
+        }
+        _ => Diagnostic::error(fallback).with_file(path),
+    }
+}
+
+/// Turns `[theme.variables]` into CSS custom properties, `accent_color` becoming `--accent-color`.
+///
+/// Names may only contain letters, digits, `-` and `_`, and values are escaped like the `css`
+/// helper does, so neither can end the rule or the stylesheet.
+pub fn variables(variables: &toml::value::Table) -> Result<Option<String>, Vec<String>> {
+    if variables.is_empty() {
+        return Ok(None);
+    }
+    let invalid = variables
+        .keys()
+        .filter(|name| {
+            name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        .map(|name| {
+            format!(
+                "Invalid theme variable `{}`, names may only contain letters, digits, `-` and `_`",
+                name
+            )
+        })
+        .collect::<Vec<_>>();
+    if !invalid.is_empty() {
+        return Err(invalid);
+    }
+    let mut css = String::from(":root {\n");
+    for (name, value) in variables {
+        let value = match value {
+            toml::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        css.push_str(&format!(
+            "    --{}: {};\n",
+            name.replace('_', "-"),
+            escape(&value)
+        ));
+    }
+    css.push_str("}\n");
+    Ok(Some(css))
+}
+
+/// Escapes a value for a CSS declaration, so it can't end the declaration, rule or `<style>`.
+///
+/// Quotes are kept if they are balanced, so font names like `"Inter Tight"` stay strings.
+pub(crate) fn escape(value: &str) -> String {
+    let balanced = |quote| value.matches(quote).count() % 2 == 0;
+    let keep_quotes = balanced('"') && balanced('\'');
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        let is_quote = c == '"' || c == '\'';
+        if "\\<>{};".contains(c) || c.is_control() || (is_quote && !keep_quotes) {
+            escaped.push_str(&format!("\\{:x} ", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Lets the Sass compiler read from a source tree, which may live in memory.
+#[derive(Debug)]
+struct SourceFs<'a>(&'a SourceTree);
+
+impl grass::Fs for SourceFs<'_> {
+    fn is_dir(&self, path: &Path) -> bool {
+        self.0.exists(path) && !self.0.is_file(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.0.is_file(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.0.read(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables_of(toml: &str) -> Result<Option<String>, Vec<String>> {
+        variables(&toml::from_str(toml).unwrap())
+    }
+
+    #[test]
+    fn variables_become_custom_properties() {
+        assert_eq!(variables_of(""), Ok(None));
+        assert_eq!(
+            variables_of("accent_color = \"#e63946\"\nfont = \"'Inter Tight', sans-serif\"\nwidth = 40"),
+            Ok(Some(
+                ":root {\n    --accent-color: #e63946;\n    --font: 'Inter Tight', sans-serif;\n    --width: 40;\n}\n"
+                    .to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn css_values_cannot_end_the_declaration_or_style() {
+        let css = variables_of("accent = \"red; } </style><script>alert(1)</script>\"")
+            .unwrap()
+            .unwrap();
+        let value = css
+            .strip_prefix(":root {\n    --accent: ")
+            .and_then(|css| css.strip_suffix(";\n}\n"))
+            .unwrap();
+        assert!(!value.contains([';', '}', '<', '>']), "{}", value);
+    }
+
+    #[test]
+    fn variable_names_are_restricted() {
+        let errors = variables_of("\"a;b\" = \"1\"\n\"x}\" = \"2\"\nok = \"3\"").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("`a;b`"), "{}", errors[0]);
+    }
+}
@@ -0,0 +1,189 @@
+//! The template engines a theme can be written for.
+//!
+//! Themes use Handlebars by default. Setting `engine = "jinja"` in `theme.toml` renders
+//! their templates with MiniJinja instead, which adds filters, arithmetic and sorting.
+//! Both receive the same [`RenderData`](super::RenderData) and the same built-in helpers.
+
+mod hbs;
+mod jinja;
+
+use std::{fmt, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics::Diagnostic;
+
+pub use self::{hbs::HandlebarsEngine, jinja::JinjaEngine};
+
+pub type JsonMap = serde_json::Map<String, serde_json::Value>;
+
+/// Compiles a theme's templates and renders them with [`RenderData`](super::RenderData), or
+/// a shortcode's arguments.
+pub trait TemplateEngine: fmt::Debug + Send + Sync {
+    fn add_template(&mut self, name: &str, source: &str) -> Result<(), Box<Diagnostic>>;
+
+    /// Adds a template that others include by `name`, but which is never rendered by itself.
+    fn add_partial(&mut self, name: &str, source: &str) -> Result<(), Box<Diagnostic>>;
+
+    /// Whether a template, not a partial, called `name` was added.
+    fn has_template(&self, name: &str) -> bool;
+
+    fn add_helper(&mut self, name: &str, helper: TemplateHelper);
+
+    fn render(&self, name: &str, context: &serde_json::Value) -> Result<String, Box<Diagnostic>>;
+}
+
+/// The template engine a theme is written for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    #[default]
+    Handlebars,
+    #[serde(alias = "minijinja")]
+    Jinja,
+}
+
+impl EngineKind {
+    pub fn create(self) -> Box<dyn TemplateEngine> {
+        match self {
+            EngineKind::Handlebars => Box::new(HandlebarsEngine::new()),
+            EngineKind::Jinja => Box::new(JinjaEngine::new()),
+        }
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineKind::Handlebars => write!(f, "handlebars"),
+            EngineKind::Jinja => write!(f, "jinja"),
+        }
+    }
+}
+
+type HelperFn =
+    dyn Fn(&[serde_json::Value], &JsonMap) -> Result<serde_json::Value, String> + Send + Sync;
+
+/// A helper available to templates of every engine.
+///
+/// It receives positional and named arguments, `{{name a b key=value}}` in Handlebars and
+/// `{{ name(a, b, key=value) }}` or `{{ a | name(b, key=value) }}` in Jinja.
+#[derive(Clone)]
+pub struct TemplateHelper {
+    function: Arc<HelperFn>,
+    raw: bool,
+}
+
+impl TemplateHelper {
+    /// A helper whose result is escaped like any other value.
+    pub fn new<F>(function: F) -> Self
+    where
+        F: Fn(&[serde_json::Value], &JsonMap) -> Result<serde_json::Value, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            function: Arc::new(function),
+            raw: false,
+        }
+    }
+
+    /// A helper producing HTML, which is inserted without escaping.
+    pub fn raw<F>(function: F) -> Self
+    where
+        F: Fn(&[serde_json::Value], &JsonMap) -> Result<serde_json::Value, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            raw: true,
+            ..Self::new(function)
+        }
+    }
+
+    pub fn call(
+        &self,
+        params: &[serde_json::Value],
+        hash: &JsonMap,
+    ) -> Result<serde_json::Value, String> {
+        (self.function)(params, hash)
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.raw
+    }
+}
+
+impl fmt::Debug for TemplateHelper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TemplateHelper")
+            .field("raw", &self.raw)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Renders a value the way templates print it, with `null` as an empty string.
+pub fn text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        theme::{helpers, PostIndex, RenderData},
+    };
+
+    /// Renders `source` as a page of the home listing with the built-in helpers under `/blog`.
+    fn render(kind: EngineKind, source: &str) -> String {
+        let mut engine = kind.create();
+        let assets = HashMap::from([("style.css".to_string(), "style.1a2b3c4d.css".to_string())]);
+        helpers::register(engine.as_mut(), "/blog", assets);
+        engine.add_template("home", source).unwrap();
+        let mut data = RenderData::for_index(&Config::new("Tom & Jerry"), &HashMap::new());
+        data.home.as_mut().unwrap().posts.push(PostIndex {
+            title: "Hello <World>".to_string(),
+            published_at: "2024-03-01T00:00:00Z".to_string(),
+            link: "/blog/posts/hello-world.html".to_string(),
+        });
+        engine
+            .render("home", &serde_json::to_value(data).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn engines_render_the_same_data_and_helpers_alike() {
+        let handlebars = render(
+            EngineKind::Handlebars,
+            "<h1>{{site.title}}</h1>\n\
+             {{#each home.posts}}<a href=\"{{link}}\">{{title}}</a> {{date published_at \"%Y-%m-%d\"}} \
+             {{slugify title}} {{truncate title 5}}{{/each}}\n\
+             <link href=\"{{asset \"style.css\"}}\"> <a href=\"{{url_for \"posts/\"}}\">",
+        );
+        let jinja = render(
+            EngineKind::Jinja,
+            "<h1>{{ site.title }}</h1>\n\
+             {% for post in home.posts %}<a href=\"{{ post.link }}\">{{ post.title }}</a> \
+             {{ post.published_at | date(\"%Y-%m-%d\") }} {{ post.title | slugify }} \
+             {{ post.title | truncate(5) }}{% endfor %}\n\
+             <link href=\"{{ asset(\"style.css\") }}\"> <a href=\"{{ url_for(\"posts/\") }}\">",
+        );
+        assert_eq!(handlebars, jinja);
+        assert_eq!(
+            handlebars,
+            "<h1>Tom &amp; Jerry</h1>\n\
+             <a href=\"/blog/posts/hello-world.html\">Hello &lt;World&gt;</a> 2024-03-01 \
+             hello-world Hello…\n\
+             <link href=\"/blog/style.1a2b3c4d.css\"> <a href=\"/blog/posts/\">"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonValue, Output, RenderContext,
+    RenderError, ScopedJson,
+};
+
+use super::{text, JsonMap, TemplateEngine, TemplateHelper};
+use crate::diagnostics::Diagnostic;
+
+/// Renders Handlebars templates, with partials included as `{{> name}}`.
+#[derive(Debug)]
+pub struct HandlebarsEngine {
+    registry: Handlebars<'static>,
+}
+
+impl HandlebarsEngine {
+    pub fn new() -> Self {
+        Self {
+            registry: Handlebars::new(),
+        }
+    }
+}
+
+impl Default for HandlebarsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemplateEngine for HandlebarsEngine {
+    fn add_template(&mut self, name: &str, source: &str) -> Result<(), Box<Diagnostic>> {
+        self.registry
+            .register_template_string(name, source)
+            .map_err(|err| Box::new(Diagnostic::from_template_error(&err)))
+    }
+
+    fn add_partial(&mut self, name: &str, source: &str) -> Result<(), Box<Diagnostic>> {
+        self.registry
+            .register_partial(name, source)
+            .map_err(|err| Box::new(Diagnostic::from_template_error(&err)))
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        self.registry.has_template(name)
+    }
+
+    fn add_helper(&mut self, name: &str, helper: TemplateHelper) {
+        match helper.is_raw() {
+            true => self
+                .registry
+                .register_helper(name, Box::new(RawHelper(helper))),
+            false => self
+                .registry
+                .register_helper(name, Box::new(ValueHelper(helper))),
+        }
+    }
+
+    fn render(&self, name: &str, context: &serde_json::Value) -> Result<String, Box<Diagnostic>> {
+        self.registry
+            .render(name, context)
+            .map_err(|err| Box::new(Diagnostic::from_render_error(&err)))
+    }
+}
+
+fn arguments(h: &Helper) -> (Vec<JsonValue>, JsonMap) {
+    let params = h
+        .params()
+        .iter()
+        .map(|param| param.value().clone())
+        .collect();
+    let hash = h
+        .hash()
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.value().clone()))
+        .collect();
+    (params, hash)
+}
+
+/// Adapts a helper computing a value, which can also be used in subexpressions.
+///
+/// Like any other value, the result is HTML-escaped unless used with `{{{triple-stash}}}`.
+struct ValueHelper(TemplateHelper);
+
+impl HelperDef for ValueHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let (params, hash) = arguments(h);
+        self.0
+            .call(&params, &hash)
+            .map(ScopedJson::Derived)
+            .map_err(RenderError::new)
+    }
+}
+
+/// Adapts a helper writing HTML, which is never escaped.
+struct RawHelper(TemplateHelper);
+
+impl HelperDef for RawHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let (params, hash) = arguments(h);
+        let output = self.0.call(&params, &hash).map_err(RenderError::new)?;
+        out.write(&text(&output))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+
+use minijinja::{
+    escape_formatter,
+    value::{Kwargs, Rest},
+    AutoEscape, Environment, Error, ErrorKind, Output, State, Value,
+};
+
+use super::{text, JsonMap, TemplateEngine, TemplateHelper};
+use crate::diagnostics::Diagnostic;
+
+/// Renders Jinja templates with MiniJinja, with partials included as `{% include "name" %}`.
+///
+/// Output is HTML-escaped unless marked `| safe`. Helpers are available both as functions,
+/// `{{ url_for("posts/") }}`, and as filters, `{{ post.published_at | date("%Y") }}`.
+#[derive(Debug)]
+pub struct JinjaEngine {
+    env: Environment<'static>,
+    partials: HashSet<String>,
+}
+
+impl JinjaEngine {
+    pub fn new() -> Self {
+        let mut env = Environment::new();
+        // Template names like `post` carry no extension to derive escaping from
+        env.set_auto_escape_callback(|_| AutoEscape::Html);
+        env.set_keep_trailing_newline(true);
+        env.set_formatter(format);
+        Self {
+            env,
+            partials: HashSet::new(),
+        }
+    }
+}
+
+impl Default for JinjaEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TemplateEngine for JinjaEngine {
+    fn add_template(&mut self, name: &str, source: &str) -> Result<(), Box<Diagnostic>> {
+        self.partials.remove(name);
+        self.env
+            .add_template_owned(name.to_string(), source.to_string())
+            .map_err(|err| Box::new(Diagnostic::from_jinja_error(&err)))
+    }
+
+    fn add_partial(&mut self, name: &str, source: &str) -> Result<(), Box<Diagnostic>> {
+        self.add_template(name, source)?;
+        self.partials.insert(name.to_string());
+        Ok(())
+    }
+
+    fn has_template(&self, name: &str) -> bool {
+        !self.partials.contains(name) && self.env.get_template(name).is_ok()
+    }
+
+    fn add_helper(&mut self, name: &str, helper: TemplateHelper) {
+        let function = helper.clone();
+        self.env.add_function(
+            name.to_string(),
+            move |args: Rest<Value>, kwargs: Kwargs| call(&function, &args, kwargs),
+        );
+        self.env.add_filter(
+            name.to_string(),
+            move |args: Rest<Value>, kwargs: Kwargs| call(&helper, &args, kwargs),
+        );
+    }
+
+    fn render(&self, name: &str, context: &serde_json::Value) -> Result<String, Box<Diagnostic>> {
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map_err(|err| Box::new(Diagnostic::from_jinja_error(&err)))
+    }
+}
+
+/// Prints values like Handlebars does: `null` as nothing, and without escaping `/` in URLs.
+fn format(out: &mut Output, state: &State, value: &Value) -> Result<(), Error> {
+    if value.is_none() {
+        return Ok(());
+    }
+    if state.auto_escape() == AutoEscape::Html && !value.is_safe() && !value.is_undefined() {
+        return out
+            .write_str(&handlebars::html_escape(&value.to_string()))
+            .map_err(Error::from);
+    }
+    escape_formatter(out, state, value)
+}
+
+fn call(helper: &TemplateHelper, args: &[Value], kwargs: Kwargs) -> Result<Value, Error> {
+    let to_json = |value: &Value| {
+        serde_json::to_value(value)
+            .map_err(|err| Error::new(ErrorKind::BadSerialization, err.to_string()))
+    };
+    let params = args.iter().map(to_json).collect::<Result<Vec<_>, _>>()?;
+    let mut hash = JsonMap::new();
+    for key in kwargs.args().map(str::to_string).collect::<Vec<_>>() {
+        hash.insert(key.clone(), to_json(&kwargs.get::<Value>(&key)?)?);
+    }
+    let output = helper
+        .call(&params, &hash)
+        .map_err(|message| Error::new(ErrorKind::InvalidOperation, message))?;
+    Ok(match helper.is_raw() {
+        true => Value::from_safe_string(text(&output)),
+        false => Value::from_serialize(&output),
+    })
+}